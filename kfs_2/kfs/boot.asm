@@ -8,12 +8,23 @@ section .multiboot_header
 align 4
 
 MULTIBOOT_MAGIC     equ 0x1BADB002      ; Magic number for GRUB (BadBoot)
-MULTIBOOT_FLAGS     equ 0x00000003      ; Align modules + memory map
+MULTIBOOT_FLAGS     equ 0x00000007      ; Align modules + memory map + video mode
 MULTIBOOT_CHECKSUM  equ -(MULTIBOOT_MAGIC + MULTIBOOT_FLAGS)
 
+; Preferred video mode (GRUB may pick something else, or leave us in text mode)
+VIDEO_MODE_TYPE     equ 0               ; 0 = linear framebuffer, 1 = EGA text
+VIDEO_WIDTH         equ 1024
+VIDEO_HEIGHT        equ 768
+VIDEO_DEPTH         equ 32
+
 dd MULTIBOOT_MAGIC
 dd MULTIBOOT_FLAGS
 dd MULTIBOOT_CHECKSUM
+dd 0, 0, 0, 0, 0                        ; Address fields (unused, flag 16 not set)
+dd VIDEO_MODE_TYPE
+dd VIDEO_WIDTH
+dd VIDEO_HEIGHT
+dd VIDEO_DEPTH
 
 ; ==============================================================================
 ; STACK
@@ -22,8 +33,14 @@ dd MULTIBOOT_CHECKSUM
 section .bss
 align 16
 
+STACK_SIZE          equ 65536
+STACK_FILL          equ 0x5741434B      ; "KCAW": untouched stack (see stack.rs)
+
+global stack_bottom
+global stack_top
+
 stack_bottom:
-    resb 65536       ; 64KB stack
+    resb STACK_SIZE  ; 64KB stack
 stack_top:
 
 ; ==============================================================================
@@ -35,7 +52,18 @@ global _start
 extern kernel_main
 
 _start:
+    mov esi, eax        ; Keep the Multiboot magic, stosd needs eax
+    mov edi, stack_bottom
+    mov ecx, STACK_SIZE / 4
+    mov eax, STACK_FILL
+    cld
+    rep stosd           ; Fill the stack so its high-water mark can be measured
+    mov eax, esi
+
     mov esp, stack_top  ; Set up stack pointer
+    xor ebp, ebp        ; Null frame pointer ends stack walks
+    push ebx            ; Arg 2: Multiboot info structure address
+    push eax            ; Arg 1: Multiboot magic (0x2BADB002)
     call kernel_main    ; Jump to Rust kernel
 
 .hang:
@@ -1,4 +1,16 @@
 ; exc.asm - Assembly wrappers for exception handlers
+;
+; Most stubs pass the Rust handler a pointer to the saved registers and the
+; CPU's frame (TrapFrame in idt.rs) so it can tell user mode faults apart.
+; Every stub can be entered from ring 3, so it saves the data segment
+; registers and runs the handler on the kernel's own.
+
+KERNEL_DATA equ 0x10
+
+section .bss
+
+global page_fault_error
+page_fault_error: resd 1
 
 section .text
 
@@ -7,9 +19,24 @@ global divide_by_zero_handler
 extern rust_divide_by_zero
 divide_by_zero_handler:
     cli
+    push ds
+    push es
+    push fs
+    push gs
     pusha
+    mov ax, KERNEL_DATA
+    mov ds, ax
+    mov es, ax
+    mov fs, ax
+    mov gs, ax
+    push esp        ; TrapFrame * (see idt.rs)
     call rust_divide_by_zero
+    add esp, 4
     popa
+    pop gs
+    pop fs
+    pop es
+    pop ds
     iretd
 
 ; Exception #6: Invalid opcode
@@ -17,31 +44,85 @@ global invalid_opcode_handler
 extern rust_invalid_opcode
 invalid_opcode_handler:
     cli
+    push ds
+    push es
+    push fs
+    push gs
     pusha
+    mov ax, KERNEL_DATA
+    mov ds, ax
+    mov es, ax
+    mov fs, ax
+    mov gs, ax
+    push esp        ; TrapFrame * (see idt.rs)
     call rust_invalid_opcode
+    add esp, 4
     popa
+    pop gs
+    pop fs
+    pop es
+    pop ds
     iretd
 
-; Exception #8: Double fault (has error code)
-global double_fault_handler
-extern rust_double_fault
-double_fault_handler:
-    cli
-    add esp, 4      ; Remove error code
+; Exception #7: Device not available (FPU used while CR0.TS is set)
+; Returns: the Rust side loads the FPU state and the instruction is retried
+global device_not_available_handler
+extern rust_device_not_available
+device_not_available_handler:
+    push ds
+    push es
+    push fs
+    push gs
     pusha
-    call rust_double_fault
+    mov ax, KERNEL_DATA
+    mov ds, ax
+    mov es, ax
+    mov fs, ax
+    mov gs, ax
+    call rust_device_not_available
     popa
+    pop gs
+    pop fs
+    pop es
+    pop ds
     iretd
 
+; Exception #8: Double fault, reached through a task gate (see tss.rs)
+; Starts as its own task on a fresh stack with the error code on top of it,
+; which is exactly where the Rust handler expects its argument after the call.
+global double_fault_task
+extern rust_double_fault
+double_fault_task:
+    call rust_double_fault
+.hang:
+    cli
+    hlt
+    jmp .hang
+
 ; Exception #13: General protection fault (has error code)
 global general_protection_fault_handler
 extern rust_general_protection_fault
 general_protection_fault_handler:
     cli
     add esp, 4      ; Remove error code
+    push ds
+    push es
+    push fs
+    push gs
     pusha
+    mov ax, KERNEL_DATA
+    mov ds, ax
+    mov es, ax
+    mov fs, ax
+    mov gs, ax
+    push esp        ; TrapFrame * (see idt.rs)
     call rust_general_protection_fault
+    add esp, 4
     popa
+    pop gs
+    pop fs
+    pop es
+    pop ds
     iretd
 
 ; Exception #14: Page fault (has error code)
@@ -49,10 +130,75 @@ global page_fault_handler
 extern rust_page_fault
 page_fault_handler:
     cli
-    add esp, 4      ; Remove error code
+    pop dword [page_fault_error]    ; Error code, for the Rust handler
+    push ds
+    push es
+    push fs
+    push gs
     pusha
+    mov ax, KERNEL_DATA
+    mov ds, ax
+    mov es, ax
+    mov fs, ax
+    mov gs, ax
+    push esp        ; TrapFrame * (see idt.rs)
     call rust_page_fault
+    add esp, 4
+    popa
+    pop gs
+    pop fs
+    pop es
+    pop ds
+    iretd
+
+; Exception #16: x87 floating point error
+global x87_fpu_error_handler
+extern rust_x87_fpu_error
+x87_fpu_error_handler:
+    cli
+    push ds
+    push es
+    push fs
+    push gs
+    pusha
+    mov ax, KERNEL_DATA
+    mov ds, ax
+    mov es, ax
+    mov fs, ax
+    mov gs, ax
+    push esp        ; TrapFrame * (see idt.rs)
+    call rust_x87_fpu_error
+    add esp, 4
+    popa
+    pop gs
+    pop fs
+    pop es
+    pop ds
+    iretd
+
+; Exception #19: SIMD floating point exception
+global simd_exception_handler
+extern rust_simd_exception
+simd_exception_handler:
+    cli
+    push ds
+    push es
+    push fs
+    push gs
+    pusha
+    mov ax, KERNEL_DATA
+    mov ds, ax
+    mov es, ax
+    mov fs, ax
+    mov gs, ax
+    push esp        ; TrapFrame * (see idt.rs)
+    call rust_simd_exception
+    add esp, 4
     popa
+    pop gs
+    pop fs
+    pop es
+    pop ds
     iretd
 
 ; Default handler for unhandled interrupts
@@ -60,7 +206,22 @@ global default_interrupt_handler
 extern rust_default_interrupt
 default_interrupt_handler:
     cli
+    push ds
+    push es
+    push fs
+    push gs
     pusha
+    mov ax, KERNEL_DATA
+    mov ds, ax
+    mov es, ax
+    mov fs, ax
+    mov gs, ax
+    push esp        ; TrapFrame * (see idt.rs)
     call rust_default_interrupt
+    add esp, 4
     popa
+    pop gs
+    pop fs
+    pop es
+    pop ds
     iretd
@@ -10,6 +10,7 @@ gdt_flush:
     mov es, ax
     mov fs, ax
     mov gs, ax
+    mov ax, 0x38      ; 0x38 is offset to kernel stack segment
     mov ss, ax
     
     jmp 0x08:.flush   ; 0x08 is offset to kernel code segment
@@ -1,4 +1,9 @@
 ; kb.asm - Assembly wrapper with explicit interrupt control
+;
+; IRQs can interrupt ring 3: save the data segment registers and load the
+; kernel's for the handler.
+
+KERNEL_DATA equ 0x10
 
 section .text
 
@@ -7,8 +12,21 @@ extern kbhandler
 
 kb_pic_handler:
     cli                          ; Explicitly disable interrupts
+    push ds                      ; Save the interrupted data segments
+    push es
+    push fs
+    push gs
     pusha                        ; Save all general-purpose registers
+    mov ax, KERNEL_DATA          ; Handlers run on the kernel's data segment
+    mov ds, ax
+    mov es, ax
+    mov fs, ax
+    mov gs, ax
     call kbhandler               ; Call Rust handler
     popa                         ; Restore all general-purpose registers
+    pop gs                       ; And the interrupted data segments
+    pop fs
+    pop es
+    pop ds
     sti                          ; Re-enable interrupts
     iretd                        ; Return from interrupt (32-bit)
@@ -0,0 +1,169 @@
+// cmdline.rs - Kernel command line: `key=value` options and bare flags
+//
+// Modules declare the options they understand with `kernel_param!`. Each
+// declaration is a static placed in the .kparams linker section, so the parser
+// finds all of them without a central list. Parsing runs right after the
+// Multiboot info is known and before any driver is initialized, so setup
+// functions should only record their value for later.
+//
+//   kernel /boot/kernel.bin console=serial pit_hz=1000 autorun="gdt 1"
+
+use crate::multiboot;
+use crate::nps::{self, Builtin};
+
+const MAX_ARGS: usize = 32;
+
+pub struct Param {
+    pub name: &'static str,
+    pub help: &'static str,
+    // Called with the value, or None for a bare flag. Returns false to reject it.
+    pub setup: fn(Option<&'static str>) -> bool,
+}
+
+// kernel_param!("pit_hz", "Timer frequency in Hz", setup_pit_hz);
+#[macro_export]
+macro_rules! kernel_param {
+    ($name:literal, $help:literal, $setup:expr) => {
+        const _: () = {
+            #[used]
+            #[link_section = ".kparams"]
+            static PARAM: $crate::cmdline::Param = $crate::cmdline::Param {
+                name: $name,
+                help: $help,
+                setup: $setup,
+            };
+        };
+    };
+}
+
+extern "C" {
+    static _kparams_start: u8;
+    static _kparams_end: u8;
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Status {
+    Applied,
+    Rejected,
+    Unknown,
+}
+
+// One word of the command line, kept for the `cmdline` command
+#[derive(Copy, Clone)]
+struct Arg {
+    key: &'static str,
+    value: Option<&'static str>,
+    status: Status,
+}
+
+static mut ARGS: [Option<Arg>; MAX_ARGS] = [None; MAX_ARGS];
+static mut RAW: &str = "";
+
+fn params() -> &'static [Param] {
+    unsafe {
+        let start = &raw const _kparams_start as usize;
+        let end = &raw const _kparams_end as usize;
+        let count = (end - start) / core::mem::size_of::<Param>();
+        core::slice::from_raw_parts(start as *const Param, count)
+    }
+}
+
+pub fn find(name: &str) -> Option<&'static Param> {
+    params().iter().find(|p| p.name == name)
+}
+
+// Split on spaces outside double quotes
+fn next_word(line: &'static str) -> Option<(&'static str, &'static str)> {
+    let line = line.trim_start_matches(' ');
+    if line.is_empty() {
+        return None;
+    }
+    let mut quoted = false;
+    let end = line
+        .bytes()
+        .position(|b| {
+            if b == b'"' {
+                quoted = !quoted;
+            }
+            b == b' ' && !quoted
+        })
+        .unwrap_or(line.len());
+    Some((&line[..end], &line[end..]))
+}
+
+fn unquote(value: &'static str) -> &'static str {
+    value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .unwrap_or(value)
+}
+
+// Parse the Multiboot command line and hand each option to its owner
+pub fn init() {
+    let Some(info) = multiboot::info() else { return };
+    if info.flags & multiboot::INFO_CMDLINE == 0 {
+        return;
+    }
+    let raw = multiboot::c_str(info.cmdline);
+    unsafe {
+        RAW = raw;
+    }
+
+    let args = unsafe { &mut *core::ptr::addr_of_mut!(ARGS) };
+    let mut rest = raw;
+    let mut count = 0;
+    while let Some((word, tail)) = next_word(rest) {
+        rest = tail;
+        // GRUB puts the kernel path first
+        if count == 0 && word.starts_with('/') {
+            continue;
+        }
+
+        let (key, value) = match word.split_once('=') {
+            Some((key, value)) => (key, Some(unquote(value))),
+            None => (word, None),
+        };
+        let status = match find(key) {
+            Some(param) if (param.setup)(value) => Status::Applied,
+            Some(_) => Status::Rejected,
+            None => Status::Unknown,
+        };
+        if count < MAX_ARGS {
+            args[count] = Some(Arg { key, value, status });
+        }
+        count += 1;
+    }
+}
+
+fn cmd_cmdline(_argv: &[&str]) {
+    println!("Command line: {}", unsafe { RAW });
+
+    let args = unsafe { &*core::ptr::addr_of!(ARGS) };
+    for arg in args.iter().flatten() {
+        let status = match arg.status {
+            Status::Applied => "",
+            Status::Rejected => "  (invalid value, ignored)",
+            Status::Unknown => "  (unknown, ignored)",
+        };
+        match arg.value {
+            Some(value) => println!("  {} = {}{}", arg.key, value, status),
+            None => println!("  {}{}", arg.key, status),
+        }
+    }
+
+    println!();
+    println!("Parameters:");
+    for param in params() {
+        let given = args.iter().flatten().any(|a| a.key == param.name && a.status == Status::Applied);
+        let marker = if given { '*' } else { ' ' };
+        println!(" {} {:<10} {}", marker, param.name, param.help);
+    }
+}
+
+static COMMANDS: [Builtin; 1] = [
+    Builtin { name: "cmdline", usage: "cmdline", help: "Show the kernel command line and its parameters", handler: cmd_cmdline, complete: None },
+];
+
+pub fn register_commands() {
+    nps::register_all(&COMMANDS);
+}
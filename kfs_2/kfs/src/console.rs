@@ -0,0 +1,303 @@
+// console.rs - Console backends and the registry every output path goes through
+//
+// print!/println!/printk!, printc, the shell and the exception handlers all
+// write here, and the registry fans the output out to every active backend
+// (VGA text, framebuffer, serial, in-memory). The first registered console is
+// the primary one: cursor position and size queries are answered by it.
+
+use core::fmt;
+use crate::idt;
+use crate::kernel_param; // console comes before cmdline in lib.rs
+use crate::vga::Color;
+
+const MAX_CONSOLES: usize = 4;
+
+// Backends selected with console= (all of them by default)
+pub const SCREEN: u8 = 1 << 0;     // VGA text or framebuffer
+pub const SERIAL: u8 = 1 << 1;
+
+// Message level of boot progress and strace output; lower levels are more
+// important (as in Linux)
+pub const LOG_INFO: u8 = 6;
+
+static mut ENABLED: u8 = SCREEN | SERIAL;
+// Messages are shown when their level is below this (loglevel=)
+static mut LOGLEVEL: u8 = 7;
+
+// console=serial, console=vga or console=vga,serial
+fn setup_console(value: Option<&str>) -> bool {
+    let Some(value) = value else { return false };
+    let mut mask = 0;
+    for name in value.split(',') {
+        mask |= match name {
+            "vga" | "fb" | "screen" => SCREEN,
+            "serial" | "ttyS0" => SERIAL,
+            _ => return false,
+        };
+    }
+    unsafe {
+        ENABLED = mask;
+    }
+    true
+}
+kernel_param!("console", "Outputs to use: vga, serial or vga,serial", setup_console);
+
+fn setup_loglevel(value: Option<&str>) -> bool {
+    match value.and_then(|v| v.parse::<u8>().ok()) {
+        Some(level) if level <= 8 => {
+            unsafe {
+                LOGLEVEL = level;
+            }
+            true
+        }
+        _ => false,
+    }
+}
+kernel_param!("loglevel", "Show messages below this level (0-8, info is 6)", setup_loglevel);
+
+// Was this kind of backend asked for on the command line?
+pub fn wanted(kind: u8) -> bool {
+    unsafe { ENABLED & kind != 0 }
+}
+
+pub fn log_enabled(level: u8) -> bool {
+    unsafe { level < LOGLEVEL }
+}
+
+pub trait Console {
+    fn write_byte(&mut self, byte: u8);
+
+    fn write_string(&mut self, s: &str) {
+        for byte in s.bytes() {
+            self.write_byte(byte);
+        }
+    }
+
+    fn set_color(&mut self, fg: Color, bg: Color);
+    fn clear(&mut self);
+    fn backspace(&mut self);
+    fn set_cursor_visible(&mut self, visible: bool);
+    fn set_cursor_position(&mut self, row: usize, col: usize);
+    fn cursor_position(&self) -> (usize, usize);
+
+    // (rows, columns)
+    fn size(&self) -> (usize, usize);
+}
+
+static mut CONSOLES: [Option<&'static mut dyn Console>; MAX_CONSOLES] = [None, None, None, None];
+
+fn consoles() -> &'static mut [Option<&'static mut dyn Console>; MAX_CONSOLES] {
+    unsafe { &mut *core::ptr::addr_of_mut!(CONSOLES) }
+}
+
+// Writers keep cursor and scroll state, and threads are preempted and
+// interrupt handlers print too: one CPU, so no interrupts means no interleaving
+fn for_each(mut f: impl FnMut(&mut dyn Console)) {
+    let interrupts = idt::disable_interrupts();
+    for console in consoles().iter_mut().flatten() {
+        f(&mut **console);
+    }
+    idt::restore_interrupts(interrupts);
+}
+
+fn primary() -> Option<&'static mut dyn Console> {
+    consoles().iter_mut().flatten().next().map(|c| &mut **c)
+}
+
+// Add a backend; returns false when all slots are taken
+pub fn register(console: &'static mut dyn Console) -> bool {
+    for slot in consoles().iter_mut() {
+        if slot.is_none() {
+            *slot = Some(console);
+            return true;
+        }
+    }
+    false
+}
+
+pub fn write_string(s: &str) {
+    for_each(|c| c.write_string(s));
+}
+
+pub fn set_color(fg: Color, bg: Color) {
+    for_each(|c| c.set_color(fg, bg));
+}
+
+pub fn clear() {
+    for_each(|c| c.clear());
+}
+
+pub fn backspace() {
+    for_each(|c| c.backspace());
+}
+
+pub fn set_cursor_visible(visible: bool) {
+    for_each(|c| c.set_cursor_visible(visible));
+}
+
+pub fn set_cursor_position(row: usize, col: usize) {
+    for_each(|c| c.set_cursor_position(row, col));
+}
+
+pub fn cursor_position() -> (usize, usize) {
+    primary().map_or((0, 0), |c| c.cursor_position())
+}
+
+pub fn size() -> (usize, usize) {
+    primary().map_or((25, 80), |c| c.size())
+}
+
+// Print in color, then go back to the default white on black
+pub fn printc(msg: &str, fg: Color, bg: Color) {
+    let interrupts = idt::disable_interrupts();
+    set_color(fg, bg);
+    write_string(msg);
+    set_color(Color::White, Color::Black);
+    idt::restore_interrupts(interrupts);
+}
+
+// printc for informational messages (boot progress), dropped below loglevel
+pub fn info(msg: &str, fg: Color, bg: Color) {
+    if log_enabled(LOG_INFO) {
+        printc(msg, fg, bg);
+    }
+}
+
+// Formats once and hands every chunk to all consoles
+struct Fanout;
+
+impl fmt::Write for Fanout {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        write_string(s);
+        Ok(())
+    }
+}
+
+pub fn _print(args: fmt::Arguments) {
+    use core::fmt::Write;
+    // Keep one print in one piece
+    let interrupts = idt::disable_interrupts();
+    let _ = Fanout.write_fmt(args);
+    idt::restore_interrupts(interrupts);
+}
+
+// In-memory console: keeps a character grid, so tests can check what got
+// printed without any hardware behind it
+#[cfg(test)]
+pub struct BufferConsole {
+    cells: [[u8; 80]; 25],
+    row: usize,
+    column: usize,
+}
+
+#[cfg(test)]
+impl BufferConsole {
+    pub const fn new() -> BufferConsole {
+        BufferConsole {
+            cells: [[b' '; 80]; 25],
+            row: 0,
+            column: 0,
+        }
+    }
+
+    pub fn line(&self, row: usize) -> &str {
+        core::str::from_utf8(&self.cells[row]).unwrap_or("").trim_end()
+    }
+}
+
+#[cfg(test)]
+impl Console for BufferConsole {
+    fn write_byte(&mut self, byte: u8) {
+        if byte == b'\n' || self.column >= 80 {
+            self.column = 0;
+            if self.row < 24 {
+                self.row += 1;
+            } else {
+                self.cells.copy_within(1.., 0);
+                self.cells[24] = [b' '; 80];
+            }
+            if byte == b'\n' {
+                return;
+            }
+        }
+        self.cells[self.row][self.column] = byte;
+        self.column += 1;
+    }
+
+    fn set_color(&mut self, _fg: Color, _bg: Color) {}
+
+    fn clear(&mut self) {
+        self.cells = [[b' '; 80]; 25];
+        self.row = 0;
+        self.column = 0;
+    }
+
+    fn backspace(&mut self) {
+        if self.column > 0 {
+            self.column -= 1;
+            self.cells[self.row][self.column] = b' ';
+        }
+    }
+
+    fn set_cursor_visible(&mut self, _visible: bool) {}
+
+    fn set_cursor_position(&mut self, row: usize, col: usize) {
+        if row < 25 && col < 80 {
+            self.row = row;
+            self.column = col;
+        }
+    }
+
+    fn cursor_position(&self) -> (usize, usize) {
+        (self.row, self.column)
+    }
+
+    fn size(&self) -> (usize, usize) {
+        (25, 80)
+    }
+}
+
+#[macro_export]
+macro_rules! print {
+    ($($arg:tt)*) => ($crate::console::_print(format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! println {
+    () => ($crate::print!("\n"));
+    ($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)));
+}
+
+// printk!(console::LOG_INFO, "...") prints only while the level is enabled
+#[macro_export]
+macro_rules! printk {
+    ($level:expr, $($arg:tt)*) => {
+        if $crate::console::log_enabled($level) {
+            $crate::print!($($arg)*);
+        }
+    };
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static mut CAPTURE: BufferConsole = BufferConsole::new();
+
+    #[test_case]
+    fn output_fans_out_to_registered_consoles() {
+        // Stays registered: later output just scrolls through it
+        assert!(register(unsafe { &mut *core::ptr::addr_of_mut!(CAPTURE) }));
+        let capture = || unsafe { &*core::ptr::addr_of!(CAPTURE) };
+
+        println!("hello {}", 42);
+        printk!(LOG_INFO, "info\n");
+        assert!(setup_loglevel(Some("6")));
+        printk!(LOG_INFO, "dropped\n");
+        assert!(setup_loglevel(Some("7")));
+        print!("done");
+
+        assert_eq!(capture().line(0), "hello 42");
+        assert_eq!(capture().line(1), "info");
+        assert_eq!(capture().line(2), "done");
+    }
+}
@@ -0,0 +1,222 @@
+// cpu.rs - CPU identification with CPUID
+//
+// init() runs the instruction once at boot and keeps the decoded results, so
+// other subsystems can ask cpu::has(Feature::Pae) before relying on a feature.
+// Decoding lives in kfs_core::cpuid.
+
+use core::arch::asm;
+use core::arch::x86::__cpuid_count;
+use kfs_core::cpuid::{self, Cache, CacheKind, Features, Regs, Signature};
+use crate::nps::{self, Builtin};
+
+pub use kfs_core::cpuid::Feature;
+
+const MAX_CACHES: usize = 8;
+
+pub struct CpuInfo {
+    pub vendor: [u8; 12],
+    pub brand: [u8; 48],
+    pub signature: Signature,
+    pub max_leaf: u32,
+    pub max_ext_leaf: u32,
+    pub features: Features,
+    caches: [Option<Cache>; MAX_CACHES],
+}
+
+impl CpuInfo {
+    pub fn vendor(&self) -> &str {
+        cpuid::as_str(&self.vendor)
+    }
+
+    pub fn brand(&self) -> &str {
+        cpuid::as_str(&self.brand)
+    }
+
+    pub fn caches(&self) -> impl Iterator<Item = &Cache> {
+        self.caches.iter().flatten()
+    }
+}
+
+static mut INFO: CpuInfo = CpuInfo {
+    vendor: [0; 12],
+    brand: [0; 48],
+    signature: Signature { family: 0, model: 0, stepping: 0 },
+    max_leaf: 0,
+    max_ext_leaf: 0,
+    features: Features::none(),
+    caches: [None; MAX_CACHES],
+};
+static mut PRESENT: bool = false;
+
+const EFLAGS_ID: u32 = 1 << 21;
+
+// CPUID exists if the ID flag in EFLAGS can be toggled (not on early 486s)
+fn has_cpuid() -> bool {
+    let (before, after): (u32, u32);
+    unsafe {
+        asm!(
+            "pushfd",
+            "pop {before}",
+            "mov {after}, {before}",
+            "xor {after}, {flag}",
+            "push {after}",
+            "popfd",
+            "pushfd",
+            "pop {after}",
+            "push {before}",
+            "popfd",
+            before = out(reg) before,
+            after = out(reg) after,
+            flag = const EFLAGS_ID,
+        );
+    }
+    (before ^ after) & EFLAGS_ID != 0
+}
+
+fn query(leaf: u32, subleaf: u32) -> Regs {
+    let r = __cpuid_count(leaf, subleaf);
+    Regs { eax: r.eax, ebx: r.ebx, ecx: r.ecx, edx: r.edx }
+}
+
+// Leaves past the reported maximum return garbage on Intel, so ask only for valid ones
+fn query_if(max: u32, leaf: u32) -> Regs {
+    if leaf <= max {
+        query(leaf, 0)
+    } else {
+        Regs::default()
+    }
+}
+
+fn read_caches(info: &mut CpuInfo) {
+    let mut count = 0;
+    if info.max_leaf >= cpuid::LEAF_CACHE_PARAMS {
+        for subleaf in 0..MAX_CACHES as u32 {
+            match Cache::from_leaf4(&query(cpuid::LEAF_CACHE_PARAMS, subleaf)) {
+                Some(cache) => {
+                    info.caches[count] = Some(cache);
+                    count += 1;
+                }
+                None => break,
+            }
+        }
+    }
+    // AMD (and QEMU's default CPU) describe caches in the extended leaves instead
+    if count == 0 && info.max_ext_leaf >= cpuid::LEAF_AMD_L2_CACHE {
+        let l1 = query(cpuid::LEAF_AMD_L1_CACHE, 0);
+        let l2 = query(cpuid::LEAF_AMD_L2_CACHE, 0);
+        info.caches[0] = Cache::amd_l1(l1.ecx, CacheKind::Data);
+        info.caches[1] = Cache::amd_l1(l1.edx, CacheKind::Instruction);
+        info.caches[2] = Cache::amd_l2(l2.ecx);
+    }
+}
+
+pub fn init() {
+    if !has_cpuid() {
+        return;
+    }
+    let info = unsafe { &mut *core::ptr::addr_of_mut!(INFO) };
+
+    let leaf0 = query(cpuid::LEAF_VENDOR, 0);
+    info.max_leaf = leaf0.eax;
+    info.vendor = cpuid::vendor(&leaf0);
+    info.max_ext_leaf = query(cpuid::LEAF_EXT_MAX, 0).eax;
+    if info.max_ext_leaf < cpuid::LEAF_EXT_MAX {
+        info.max_ext_leaf = 0;
+    }
+
+    let leaf1 = query_if(info.max_leaf, cpuid::LEAF_FEATURES);
+    info.signature = Signature::decode(leaf1.eax);
+    info.features = Features::new(
+        &leaf1,
+        &query_if(info.max_leaf, cpuid::LEAF_EXTENDED_FEATURES),
+        &query_if(info.max_ext_leaf, cpuid::LEAF_EXT_FEATURES),
+    );
+
+    if info.max_ext_leaf >= cpuid::LEAF_BRAND + 2 {
+        info.brand = cpuid::brand(&[
+            query(cpuid::LEAF_BRAND, 0),
+            query(cpuid::LEAF_BRAND + 1, 0),
+            query(cpuid::LEAF_BRAND + 2, 0),
+        ]);
+    }
+    read_caches(info);
+
+    unsafe {
+        PRESENT = true;
+    }
+}
+
+// False on CPUs without CPUID; every feature then reads as absent
+pub fn present() -> bool {
+    unsafe { PRESENT }
+}
+
+pub fn info() -> &'static CpuInfo {
+    unsafe { &*core::ptr::addr_of!(INFO) }
+}
+
+pub fn has(feature: Feature) -> bool {
+    info().features.has(feature)
+}
+
+fn cmd_cpuinfo(_argv: &[&str]) {
+    if !present() {
+        println!("CPUID is not supported by this processor");
+        return;
+    }
+    let info = info();
+    println!("vendor:    {}", info.vendor());
+    if !info.brand().is_empty() {
+        println!("model:     {}", info.brand());
+    }
+    println!(
+        "signature: family {:#x}, model {:#x}, stepping {}",
+        info.signature.family, info.signature.model, info.signature.stepping
+    );
+    println!("leaves:    max {:#x}, extended max {:#x}", info.max_leaf, info.max_ext_leaf);
+
+    print!("flags:    ");
+    let mut column = 10;
+    for feature in Feature::ALL {
+        if has(feature) {
+            let name = feature.name();
+            if column + name.len() + 1 > 79 {
+                print!("\n          ");
+                column = 10;
+            }
+            print!(" {}", name);
+            column += name.len() + 1;
+        }
+    }
+    println!();
+
+    for cache in info.caches() {
+        print!("cache:     L{} {:<11} {:>5} KiB, {} byte lines, ", cache.level, cache.kind.name(), cache.size_kb, cache.line_size);
+        if cache.ways == 0 {
+            println!("fully associative");
+        } else {
+            println!("{}-way", cache.ways);
+        }
+    }
+}
+
+static COMMANDS: [Builtin; 1] = [
+    Builtin { name: "cpuinfo", usage: "cpuinfo", help: "Show CPU vendor, model, features and caches", handler: cmd_cpuinfo, complete: None },
+];
+
+pub fn register_commands() {
+    nps::register_all(&COMMANDS);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Anything QEMU emulates as i386 target has at least an FPU
+    #[test_case]
+    fn detects_basic_features() {
+        assert!(present());
+        assert!(has(Feature::Fpu));
+        assert!(!info().vendor().is_empty());
+    }
+}
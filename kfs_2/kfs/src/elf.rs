@@ -0,0 +1,212 @@
+// elf.rs - Loading ELF32 executables into user processes
+//
+// Program files are Multiboot modules (see the Makefile's PROGRAMS) parsed by
+// kfs_core::elf. Each PT_LOAD segment is copied into a fresh address space,
+// read-only unless the segment is writable (i386 has no no-execute bit
+// without PAE), and its .bss is zeroed. Segments must lie in the image area
+// at the bottom of the user window, so programs are linked at USER_BASE; the
+// heap starts right after them.
+//
+// The stack gets argc, argv, envp and the auxiliary vector the way Linux lays
+// them out, so static i386 Linux programs that stick to the system calls in
+// syscall.rs run unchanged.
+
+use core::fmt;
+use kfs_core::elf::{self, Elf, ElfError, Segment, PHDR_SIZE};
+use kfs_core::elf::{AT_EGID, AT_ENTRY, AT_EUID, AT_GID, AT_PAGESZ, AT_PHDR, AT_PHENT, AT_PHNUM, AT_UID};
+use kfs_core::paging::{align_down, align_up, PAGE_SIZE};
+use crate::multiboot;
+use crate::nps::{self, Builtin};
+use crate::paging::{AddressSpace, USER_BASE, USER_TOP};
+use crate::process;
+use crate::syscall;
+use crate::user::{self, Exit};
+
+const ENVIRONMENT: [&str; 2] = ["HOME=/", "TERM=dumb"];
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum LoadError {
+    Elf(ElfError),
+    OutsideImageArea,
+    BadEntry,
+    NoMemory,
+    ArgumentsTooLong,
+    TooManyProcesses,
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Elf(error) => write!(f, "{}", error),
+            LoadError::OutsideImageArea => write!(f, "not linked at {:#x}", USER_BASE),
+            LoadError::BadEntry => write!(f, "entry point outside the program"),
+            LoadError::NoMemory => write!(f, "out of memory"),
+            LoadError::ArgumentsTooLong => write!(f, "argument list too long"),
+            LoadError::TooManyProcesses => write!(f, "too many processes"),
+        }
+    }
+}
+
+fn load_segment(space: &AddressSpace, elf: &Elf, segment: &Segment) -> Result<(), LoadError> {
+    let mut page = align_down(segment.vaddr);
+    while page < segment.end() {
+        space.map(page, segment.writable()).ok_or(LoadError::NoMemory)?;
+        // A page shared with a writable segment must stay writable
+        if segment.writable() {
+            space.make_writable(page);
+        }
+        page += PAGE_SIZE;
+    }
+    let bss = segment.memsz - segment.filesz;
+    space.write(segment.vaddr, elf.file_bytes(segment));
+    space.zero(segment.vaddr + segment.filesz, bss);
+    Ok(())
+}
+
+// Fill `space` with the program and its stack; returns (esp, heap start)
+fn prepare(space: &AddressSpace, elf: &Elf, argv: &[&str]) -> Result<(u32, u32), LoadError> {
+    for segment in elf.segments() {
+        load_segment(space, elf, &segment)?;
+    }
+    let image_end = elf.segments().map(|s| s.end()).max().unwrap_or(USER_BASE);
+    let heap_start = align_up(image_end).ok_or(LoadError::OutsideImageArea)?;
+
+    // Arguments and environment have to fit in the first stack page
+    let frame = space.map(USER_TOP - PAGE_SIZE, true).ok_or(LoadError::NoMemory)?;
+    let stack = unsafe { core::slice::from_raw_parts_mut(frame as *mut u8, PAGE_SIZE as usize) };
+    let mut auxv = [
+        (AT_PHENT, PHDR_SIZE as u32),
+        (AT_PHNUM, elf.phnum()),
+        (AT_PAGESZ, PAGE_SIZE),
+        (AT_ENTRY, elf.entry),
+        (AT_UID, 0),
+        (AT_EUID, 0),
+        (AT_GID, 0),
+        (AT_EGID, 0),
+        (AT_PHDR, 0),
+    ];
+    let auxv = match elf.phdr_addr() {
+        Some(addr) => {
+            auxv[8].1 = addr;
+            &auxv[..]
+        }
+        None => &auxv[..8],
+    };
+    let esp = elf::build_stack(stack, USER_TOP, argv, &ENVIRONMENT, auxv).ok_or(LoadError::ArgumentsTooLong)?;
+    Ok((esp, heap_start))
+}
+
+// Start the executable in `data` as a new process; returns its pid
+pub fn exec(name: &'static str, data: &[u8], argv: &[&str]) -> Result<u32, LoadError> {
+    let elf = Elf::parse(data).map_err(LoadError::Elf)?;
+    if !elf.segments().all(|s| s.vaddr >= USER_BASE && s.end() <= syscall::MMAP_START) {
+        return Err(LoadError::OutsideImageArea);
+    }
+    if !elf.segments().any(|s| (s.vaddr..s.end()).contains(&elf.entry)) {
+        return Err(LoadError::BadEntry);
+    }
+    let space = AddressSpace::new().ok_or(LoadError::NoMemory)?;
+    match prepare(&space, &elf, argv) {
+        Ok((esp, heap_start)) => {
+            process::start_program(name, space, elf.entry, esp, heap_start).ok_or(LoadError::TooManyProcesses)
+        }
+        Err(error) => {
+            space.destroy();
+            Err(error)
+        }
+    }
+}
+
+fn cmd_run(argv: &[&str]) {
+    let Some(&path) = argv.get(1) else {
+        println!("Usage: run <file> [args...]");
+        return;
+    };
+    let Some(module) = multiboot::find_module(path) else {
+        println!("run: {}: no such file", path);
+        return;
+    };
+    let pid = match exec(module.name, module.data(), &argv[1..]) {
+        Ok(pid) => pid,
+        Err(error) => {
+            println!("run: {}: {}", path, error);
+            return;
+        }
+    };
+    match user::wait_foreground(pid) {
+        Exit::Code(0) => {}
+        Exit::Code(code) => println!("run: {} exited with {}", path, code),
+        Exit::Killed(reason) => println!("run: {} killed ({})", path, reason),
+        Exit::NotStarted => println!("run: {}: lost track of process {}", path, pid),
+    }
+}
+
+fn complete_run(arg: usize, _prefix: &str, add: &mut dyn FnMut(&str)) {
+    if arg == 1 {
+        (0..multiboot::module_count()).filter_map(multiboot::module).for_each(|m| add(m.name));
+    }
+}
+
+static COMMANDS: [Builtin; 1] = [
+    Builtin { name: "run", usage: "run <file> [args...]", help: "Run an ELF program from a boot module", handler: cmd_run, complete: Some(complete_run) },
+];
+
+pub fn register_commands() {
+    nps::register_all(&COMMANDS);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A program that exits with argc: mov eax, 1; mov ebx, [esp]; int 0x80
+    const EXIT_ARGC: [u8; 10] = [0xB8, 0x01, 0x00, 0x00, 0x00, 0x8B, 0x1C, 0x24, 0xCD, 0x80];
+
+    const ENTRY: u32 = 0x54;
+
+    // read(0, entry, 4) into its own code, then exit with the result:
+    // mov eax, 3; xor ebx, ebx; mov ecx, USER_BASE + ENTRY; mov edx, 4; int 0x80;
+    // mov ebx, eax; mov eax, 1; int 0x80
+    const READ_INTO_TEXT: [u8; 28] = [
+        0xB8, 0x03, 0x00, 0x00, 0x00, 0x31, 0xDB, 0xB9, 0x54, 0x00, 0x00, 0x80, 0xBA, 0x04, 0x00, 0x00, 0x00,
+        0xCD, 0x80, 0x89, 0xC3, 0xB8, 0x01, 0x00, 0x00, 0x00, 0xCD, 0x80,
+    ];
+
+    fn program(vaddr: u32, code: &[u8]) -> [u8; 0x80] {
+        let mut data = [0u8; 0x80];
+        data[..7].copy_from_slice(b"\x7fELF\x01\x01\x01");
+        let header: [(usize, u32); 5] = [(16, 2 | 3 << 16), (20, 1), (24, vaddr + ENTRY), (28, 0x34), (42, 32 | 1 << 16)];
+        for (at, value) in header {
+            data[at..at + 4].copy_from_slice(&value.to_le_bytes());
+        }
+        // One read-only segment with the code and a page of .bss
+        let segment = [1, 0, vaddr, 0, 0x80, 0x1080, 5, 0x1000];
+        for (i, value) in segment.iter().enumerate() {
+            data[0x34 + i * 4..0x38 + i * 4].copy_from_slice(&value.to_le_bytes());
+        }
+        data[ENTRY as usize..ENTRY as usize + code.len()].copy_from_slice(code);
+        data
+    }
+
+    #[test_case]
+    fn runs_elf_programs() {
+        let data = program(USER_BASE, &EXIT_ARGC);
+        let pid = exec("test", &data, &["test", "a", "b"]).unwrap();
+        assert_eq!(user::wait_foreground(pid), Exit::Code(3));
+    }
+
+    #[test_case]
+    fn text_is_not_a_read_buffer() {
+        let data = program(USER_BASE, &READ_INTO_TEXT);
+        let pid = exec("test", &data, &["test"]).unwrap();
+        assert_eq!(user::wait_foreground(pid), Exit::Code((-syscall::EFAULT) as u8 as u32));
+    }
+
+    #[test_case]
+    fn rejects_programs_outside_the_user_window() {
+        assert_eq!(exec("test", &program(0x0804_8000, &EXIT_ARGC), &["test"]), Err(LoadError::OutsideImageArea));
+        let mut data = program(USER_BASE, &EXIT_ARGC);
+        data[18] = 62;
+        assert_eq!(exec("test", &data, &["test"]), Err(LoadError::Elf(ElfError::WrongMachine)));
+    }
+}
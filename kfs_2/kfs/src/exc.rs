@@ -1,61 +1,151 @@
 // exceptions.rs - Complete exception handlers
 
 pub mod exceptions {
-    const VGA_BUFFER: *mut u8 = 0xb8000 as *mut u8;
+    use kfs_core::paging::PageFault;
+    use kfs_core::signal::{SIGFPE, SIGILL, SIGSEGV};
+    use crate::console;
+    use crate::fpu;
+    use crate::idt::TrapFrame;
+    use crate::paging;
+    use crate::stack;
+    use crate::symbols;
+    use crate::tss;
+    use crate::user;
+    use crate::vga::Color;
+
+    // Stop for good after a fatal exception, like .hang in exc.asm
+    pub fn halt_forever() -> ! {
+        loop {
+            unsafe {
+                core::arch::asm!("cli; hlt", options(nomem, nostack));
+            }
+        }
+    }
+
+    unsafe fn write_error(row: usize, msg: &str, fg: Color, bg: Color) {
+        console::set_cursor_position(row, 0);
+        console::printc(msg, fg, bg);
+    }
+    
+    #[no_mangle]
+    pub extern "C" fn rust_divide_by_zero(frame: &mut TrapFrame) {
+        if frame.in_user_mode() {
+            user::fault(frame, "DIVIDE BY ZERO", SIGFPE);
+            return;
+        }
+        unsafe {
+            write_error(10, "EXCEPTION #0: DIVIDE BY ZERO", Color::White, Color::Red);
+            halt_forever();
+        }
+    }
     
-    unsafe fn write_error(row: usize, msg: &str, color: u8) {
-        for (i, byte) in msg.bytes().enumerate() {
-            let offset = (row * 80 + i) * 2;
-            *VGA_BUFFER.add(offset) = byte;
-            *VGA_BUFFER.add(offset + 1) = color;
+    #[no_mangle]
+    pub extern "C" fn rust_invalid_opcode(frame: &mut TrapFrame) {
+        if frame.in_user_mode() {
+            user::fault(frame, "INVALID OPCODE", SIGILL);
+            return;
+        }
+        unsafe {
+            write_error(10, "EXCEPTION #6: INVALID OPCODE", Color::White, Color::Red);
+            halt_forever();
         }
     }
     
+    // Runs as the double fault task: the interrupted state was saved in the main TSS
     #[no_mangle]
-    pub extern "C" fn rust_divide_by_zero() {
+    pub extern "C" fn rust_double_fault(error_code: u32) {
         unsafe {
-            write_error(10, "EXCEPTION #0: DIVIDE BY ZERO", 0x4F);
-            loop {}
+            write_error(10, "EXCEPTION #8: DOUBLE FAULT", Color::White, Color::Red);
+            let state = tss::tss();
+            console::set_cursor_position(11, 0);
+            print!("eip=0x{:08x}", state.eip);
+            if let Some(symbol) = symbols::lookup(state.eip) {
+                print!(" <{}+0x{:x}>", symbol.demangled(), state.eip - symbol.addr);
+            }
+            println!();
+            println!("esp=0x{:08x} ebp=0x{:08x} error=0x{:x}", state.esp, state.ebp, error_code);
+            // Bounds of the thread that was running
+            let stack = stack::current();
+            if state.esp <= stack.bottom + 64 && state.esp + 4096 > stack.bottom {
+                console::printc("Kernel stack overflow", Color::White, Color::Red);
+            }
+            halt_forever();
         }
     }
     
     #[no_mangle]
-    pub extern "C" fn rust_invalid_opcode() {
+    pub extern "C" fn rust_general_protection_fault(frame: &mut TrapFrame) {
+        if frame.in_user_mode() {
+            user::fault(frame, "GENERAL PROTECTION FAULT", SIGSEGV);
+            return;
+        }
         unsafe {
-            write_error(10, "EXCEPTION #6: INVALID OPCODE", 0x4F);
-            loop {}
+            write_error(10, "EXCEPTION #13: GENERAL PROTECTION FAULT", Color::White, Color::Red);
+            halt_forever();
         }
     }
     
+    extern "C" {
+        static page_fault_error: u32;
+    }
+
+    // Copy-on-write and stack growth are dealt with quietly, anything else is a real fault
     #[no_mangle]
-    pub extern "C" fn rust_double_fault() {
+    pub extern "C" fn rust_page_fault(frame: &mut TrapFrame) {
+        let fault = PageFault { addr: paging::fault_address(), error: unsafe { page_fault_error } };
+        if paging::handle_fault(&fault) {
+            return;
+        }
+        if frame.in_user_mode() {
+            println!("user: {}", fault);
+            user::fault(frame, "PAGE FAULT", SIGSEGV);
+            return;
+        }
         unsafe {
-            write_error(10, "EXCEPTION #8: DOUBLE FAULT (STACK OVERFLOW?)", 0x4F);
-            loop {}
+            write_error(10, "EXCEPTION #14: PAGE FAULT", Color::White, Color::Red);
+            console::set_cursor_position(11, 0);
+            println!("{} eip=0x{:08x}", fault, frame.eip);
+            halt_forever();
         }
     }
     
     #[no_mangle]
-    pub extern "C" fn rust_general_protection_fault() {
+    pub extern "C" fn rust_x87_fpu_error(frame: &mut TrapFrame) {
+        if frame.in_user_mode() {
+            user::fault(frame, "X87 FLOATING POINT ERROR", SIGFPE);
+            return;
+        }
         unsafe {
-            write_error(10, "EXCEPTION #13: GENERAL PROTECTION FAULT", 0x4F);
-            loop {}
+            write_error(10, "EXCEPTION #16: X87 FLOATING POINT ERROR", Color::White, Color::Red);
+            console::set_cursor_position(11, 0);
+            println!("{}", fpu::x87_status());
+            halt_forever();
         }
     }
     
     #[no_mangle]
-    pub extern "C" fn rust_page_fault() {
+    pub extern "C" fn rust_simd_exception(frame: &mut TrapFrame) {
+        if frame.in_user_mode() {
+            user::fault(frame, "SIMD FLOATING POINT EXCEPTION", SIGFPE);
+            return;
+        }
         unsafe {
-            write_error(10, "EXCEPTION #14: PAGE FAULT", 0x4F);
-            loop {}
+            write_error(10, "EXCEPTION #19: SIMD FLOATING POINT EXCEPTION", Color::White, Color::Red);
+            console::set_cursor_position(11, 0);
+            println!("{}", fpu::mxcsr());
+            halt_forever();
         }
     }
     
     #[no_mangle]
-    pub extern "C" fn rust_default_interrupt() {
+    pub extern "C" fn rust_default_interrupt(frame: &mut TrapFrame) {
+        if frame.in_user_mode() {
+            user::fault(frame, "UNHANDLED INTERRUPT", SIGSEGV);
+            return;
+        }
         unsafe {
-            write_error(10, "UNHANDLED INTERRUPT!", 0x4F);
-            loop {}
+            write_error(10, "UNHANDLED INTERRUPT!", Color::White, Color::Red);
+            halt_forever();
         }
     }
 }
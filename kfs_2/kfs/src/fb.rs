@@ -0,0 +1,339 @@
+// fb.rs - Linear framebuffer console (VBE mode handed over by GRUB)
+//
+// Same interface as the VGA text `Writer`, but every character is drawn
+// pixel by pixel with the embedded 8x8 font below, doubled vertically
+// to get the usual 8x16 text cell.
+
+use crate::console::Console;
+use crate::multiboot;
+use crate::vga::Color;
+
+const CELL_WIDTH: usize = 8;
+const CELL_HEIGHT: usize = 16;
+const FRAMEBUFFER_TYPE_RGB: u8 = 1;
+
+// Standard VGA text palette so colors look the same as in text mode
+const PALETTE: [(u8, u8, u8); 16] = [
+    (0x00, 0x00, 0x00), (0x00, 0x00, 0xAA), (0x00, 0xAA, 0x00), (0x00, 0xAA, 0xAA),
+    (0xAA, 0x00, 0x00), (0xAA, 0x00, 0xAA), (0xAA, 0x55, 0x00), (0xAA, 0xAA, 0xAA),
+    (0x55, 0x55, 0x55), (0x55, 0x55, 0xFF), (0x55, 0xFF, 0x55), (0x55, 0xFF, 0xFF),
+    (0xFF, 0x55, 0x55), (0xFF, 0x55, 0xFF), (0xFF, 0xFF, 0x55), (0xFF, 0xFF, 0xFF),
+];
+
+// Public domain 8x8 font (font8x8_basic) for 0x20-0x7E, bit 0 is the leftmost pixel
+static FONT_8X8: [[u8; 8]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // ' '
+    [0x18, 0x3C, 0x3C, 0x18, 0x18, 0x00, 0x18, 0x00], // !
+    [0x36, 0x36, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // "
+    [0x36, 0x36, 0x7F, 0x36, 0x7F, 0x36, 0x36, 0x00], // #
+    [0x0C, 0x3E, 0x03, 0x1E, 0x30, 0x1F, 0x0C, 0x00], // $
+    [0x00, 0x63, 0x33, 0x18, 0x0C, 0x66, 0x63, 0x00], // %
+    [0x1C, 0x36, 0x1C, 0x6E, 0x3B, 0x33, 0x6E, 0x00], // &
+    [0x06, 0x06, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00], // '
+    [0x18, 0x0C, 0x06, 0x06, 0x06, 0x0C, 0x18, 0x00], // (
+    [0x06, 0x0C, 0x18, 0x18, 0x18, 0x0C, 0x06, 0x00], // )
+    [0x00, 0x66, 0x3C, 0xFF, 0x3C, 0x66, 0x00, 0x00], // *
+    [0x00, 0x0C, 0x0C, 0x3F, 0x0C, 0x0C, 0x00, 0x00], // +
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C, 0x06], // ,
+    [0x00, 0x00, 0x00, 0x3F, 0x00, 0x00, 0x00, 0x00], // -
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C, 0x00], // .
+    [0x60, 0x30, 0x18, 0x0C, 0x06, 0x03, 0x01, 0x00], // /
+    [0x3E, 0x63, 0x73, 0x7B, 0x6F, 0x67, 0x3E, 0x00], // 0
+    [0x0C, 0x0E, 0x0C, 0x0C, 0x0C, 0x0C, 0x3F, 0x00], // 1
+    [0x1E, 0x33, 0x30, 0x1C, 0x06, 0x33, 0x3F, 0x00], // 2
+    [0x1E, 0x33, 0x30, 0x1C, 0x30, 0x33, 0x1E, 0x00], // 3
+    [0x38, 0x3C, 0x36, 0x33, 0x7F, 0x30, 0x78, 0x00], // 4
+    [0x3F, 0x03, 0x1F, 0x30, 0x30, 0x33, 0x1E, 0x00], // 5
+    [0x1C, 0x06, 0x03, 0x1F, 0x33, 0x33, 0x1E, 0x00], // 6
+    [0x3F, 0x33, 0x30, 0x18, 0x0C, 0x0C, 0x0C, 0x00], // 7
+    [0x1E, 0x33, 0x33, 0x1E, 0x33, 0x33, 0x1E, 0x00], // 8
+    [0x1E, 0x33, 0x33, 0x3E, 0x30, 0x18, 0x0E, 0x00], // 9
+    [0x00, 0x0C, 0x0C, 0x00, 0x00, 0x0C, 0x0C, 0x00], // :
+    [0x00, 0x0C, 0x0C, 0x00, 0x00, 0x0C, 0x0C, 0x06], // ;
+    [0x18, 0x0C, 0x06, 0x03, 0x06, 0x0C, 0x18, 0x00], // <
+    [0x00, 0x00, 0x3F, 0x00, 0x00, 0x3F, 0x00, 0x00], // =
+    [0x06, 0x0C, 0x18, 0x30, 0x18, 0x0C, 0x06, 0x00], // >
+    [0x1E, 0x33, 0x30, 0x18, 0x0C, 0x00, 0x0C, 0x00], // ?
+    [0x3E, 0x63, 0x7B, 0x7B, 0x7B, 0x03, 0x1E, 0x00], // @
+    [0x0C, 0x1E, 0x33, 0x33, 0x3F, 0x33, 0x33, 0x00], // A
+    [0x3F, 0x66, 0x66, 0x3E, 0x66, 0x66, 0x3F, 0x00], // B
+    [0x3C, 0x66, 0x03, 0x03, 0x03, 0x66, 0x3C, 0x00], // C
+    [0x1F, 0x36, 0x66, 0x66, 0x66, 0x36, 0x1F, 0x00], // D
+    [0x7F, 0x46, 0x16, 0x1E, 0x16, 0x46, 0x7F, 0x00], // E
+    [0x7F, 0x46, 0x16, 0x1E, 0x16, 0x06, 0x0F, 0x00], // F
+    [0x3C, 0x66, 0x03, 0x03, 0x73, 0x66, 0x7C, 0x00], // G
+    [0x33, 0x33, 0x33, 0x3F, 0x33, 0x33, 0x33, 0x00], // H
+    [0x1E, 0x0C, 0x0C, 0x0C, 0x0C, 0x0C, 0x1E, 0x00], // I
+    [0x78, 0x30, 0x30, 0x30, 0x33, 0x33, 0x1E, 0x00], // J
+    [0x67, 0x66, 0x36, 0x1E, 0x36, 0x66, 0x67, 0x00], // K
+    [0x0F, 0x06, 0x06, 0x06, 0x46, 0x66, 0x7F, 0x00], // L
+    [0x63, 0x77, 0x7F, 0x7F, 0x6B, 0x63, 0x63, 0x00], // M
+    [0x63, 0x67, 0x6F, 0x7B, 0x73, 0x63, 0x63, 0x00], // N
+    [0x1C, 0x36, 0x63, 0x63, 0x63, 0x36, 0x1C, 0x00], // O
+    [0x3F, 0x66, 0x66, 0x3E, 0x06, 0x06, 0x0F, 0x00], // P
+    [0x1E, 0x33, 0x33, 0x33, 0x3B, 0x1E, 0x38, 0x00], // Q
+    [0x3F, 0x66, 0x66, 0x3E, 0x36, 0x66, 0x67, 0x00], // R
+    [0x1E, 0x33, 0x07, 0x0E, 0x38, 0x33, 0x1E, 0x00], // S
+    [0x3F, 0x2D, 0x0C, 0x0C, 0x0C, 0x0C, 0x1E, 0x00], // T
+    [0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x3F, 0x00], // U
+    [0x33, 0x33, 0x33, 0x33, 0x33, 0x1E, 0x0C, 0x00], // V
+    [0x63, 0x63, 0x63, 0x6B, 0x7F, 0x77, 0x63, 0x00], // W
+    [0x63, 0x63, 0x36, 0x1C, 0x1C, 0x36, 0x63, 0x00], // X
+    [0x33, 0x33, 0x33, 0x1E, 0x0C, 0x0C, 0x1E, 0x00], // Y
+    [0x7F, 0x63, 0x31, 0x18, 0x4C, 0x66, 0x7F, 0x00], // Z
+    [0x1E, 0x06, 0x06, 0x06, 0x06, 0x06, 0x1E, 0x00], // [
+    [0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x40, 0x00], // backslash
+    [0x1E, 0x18, 0x18, 0x18, 0x18, 0x18, 0x1E, 0x00], // ]
+    [0x08, 0x1C, 0x36, 0x63, 0x00, 0x00, 0x00, 0x00], // ^
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xFF], // _
+    [0x0C, 0x0C, 0x18, 0x00, 0x00, 0x00, 0x00, 0x00], // `
+    [0x00, 0x00, 0x1E, 0x30, 0x3E, 0x33, 0x6E, 0x00], // a
+    [0x07, 0x06, 0x06, 0x3E, 0x66, 0x66, 0x3B, 0x00], // b
+    [0x00, 0x00, 0x1E, 0x33, 0x03, 0x33, 0x1E, 0x00], // c
+    [0x38, 0x30, 0x30, 0x3E, 0x33, 0x33, 0x6E, 0x00], // d
+    [0x00, 0x00, 0x1E, 0x33, 0x3F, 0x03, 0x1E, 0x00], // e
+    [0x1C, 0x36, 0x06, 0x0F, 0x06, 0x06, 0x0F, 0x00], // f
+    [0x00, 0x00, 0x6E, 0x33, 0x33, 0x3E, 0x30, 0x1F], // g
+    [0x07, 0x06, 0x36, 0x6E, 0x66, 0x66, 0x67, 0x00], // h
+    [0x0C, 0x00, 0x0E, 0x0C, 0x0C, 0x0C, 0x1E, 0x00], // i
+    [0x30, 0x00, 0x30, 0x30, 0x30, 0x33, 0x33, 0x1E], // j
+    [0x07, 0x06, 0x66, 0x36, 0x1E, 0x36, 0x67, 0x00], // k
+    [0x0E, 0x0C, 0x0C, 0x0C, 0x0C, 0x0C, 0x1E, 0x00], // l
+    [0x00, 0x00, 0x33, 0x7F, 0x7F, 0x6B, 0x63, 0x00], // m
+    [0x00, 0x00, 0x1F, 0x33, 0x33, 0x33, 0x33, 0x00], // n
+    [0x00, 0x00, 0x1E, 0x33, 0x33, 0x33, 0x1E, 0x00], // o
+    [0x00, 0x00, 0x3B, 0x66, 0x66, 0x3E, 0x06, 0x0F], // p
+    [0x00, 0x00, 0x6E, 0x33, 0x33, 0x3E, 0x30, 0x78], // q
+    [0x00, 0x00, 0x3B, 0x6E, 0x66, 0x06, 0x0F, 0x00], // r
+    [0x00, 0x00, 0x3E, 0x03, 0x1E, 0x30, 0x1F, 0x00], // s
+    [0x08, 0x0C, 0x3E, 0x0C, 0x0C, 0x2C, 0x18, 0x00], // t
+    [0x00, 0x00, 0x33, 0x33, 0x33, 0x33, 0x6E, 0x00], // u
+    [0x00, 0x00, 0x33, 0x33, 0x33, 0x1E, 0x0C, 0x00], // v
+    [0x00, 0x00, 0x63, 0x6B, 0x7F, 0x7F, 0x36, 0x00], // w
+    [0x00, 0x00, 0x63, 0x36, 0x1C, 0x36, 0x63, 0x00], // x
+    [0x00, 0x00, 0x33, 0x33, 0x33, 0x3E, 0x30, 0x1F], // y
+    [0x00, 0x00, 0x3F, 0x19, 0x0C, 0x26, 0x3F, 0x00], // z
+    [0x38, 0x0C, 0x0C, 0x07, 0x0C, 0x0C, 0x38, 0x00], // {
+    [0x18, 0x18, 0x18, 0x00, 0x18, 0x18, 0x18, 0x00], // |
+    [0x07, 0x0C, 0x0C, 0x38, 0x0C, 0x0C, 0x07, 0x00], // }
+    [0x6E, 0x3B, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // ~
+];
+
+// Replacement glyph for anything outside the font (same idea as 0xfe in text mode)
+const BLOCK_GLYPH: [u8; 8] = [0x00, 0x00, 0x3C, 0x3C, 0x3C, 0x3C, 0x00, 0x00];
+
+pub struct FbWriter {
+    base: *mut u8,
+    pitch: usize,
+    bytes_per_pixel: usize,
+    red: (u8, u8),      // (field position, mask size)
+    green: (u8, u8),
+    blue: (u8, u8),
+    cols: usize,
+    rows: usize,
+    column: usize,
+    row: usize,
+    fg: u32,
+    bg: u32,
+    cursor_visible: bool,
+}
+
+impl FbWriter {
+    fn pack(&self, color: u8) -> u32 {
+        let (r, g, b) = PALETTE[(color & 0x0F) as usize];
+        // Scale 8-bit components to the mask width, which can exceed 8 (30 bpp modes)
+        let channel = |value: u8, (pos, size): (u8, u8)| {
+            let value = value as u32;
+            let scaled = if size <= 8 { value >> (8 - size) } else { value << (size - 8).min(24) };
+            scaled << pos
+        };
+        channel(r, self.red) | channel(g, self.green) | channel(b, self.blue)
+    }
+
+    fn pixel(&self, x: usize, y: usize) -> *mut u8 {
+        unsafe { self.base.add(y * self.pitch + x * self.bytes_per_pixel) }
+    }
+
+    fn put_pixel(&self, x: usize, y: usize, value: u32) {
+        let ptr = self.pixel(x, y);
+        for i in 0..self.bytes_per_pixel {
+            unsafe { *ptr.add(i) = (value >> (i * 8)) as u8; }
+        }
+    }
+
+    fn draw_glyph(&self, row: usize, col: usize, byte: u8) {
+        let glyph = match byte {
+            0x20..=0x7E => &FONT_8X8[(byte - 0x20) as usize],
+            _ => &BLOCK_GLYPH,
+        };
+        let (x0, y0) = (col * CELL_WIDTH, row * CELL_HEIGHT);
+        for y in 0..CELL_HEIGHT {
+            let bits = glyph[y / 2];
+            for x in 0..CELL_WIDTH {
+                let value = if bits & (1 << x) != 0 { self.fg } else { self.bg };
+                self.put_pixel(x0 + x, y0 + y, value);
+            }
+        }
+    }
+
+    // The cursor is the bottom two scanlines of the cell, inverted
+    fn toggle_cursor(&self) {
+        if !self.cursor_visible || self.row >= self.rows || self.column >= self.cols {
+            return;
+        }
+        let (x0, y0) = (self.column * CELL_WIDTH, self.row * CELL_HEIGHT);
+        for y in CELL_HEIGHT - 2..CELL_HEIGHT {
+            for x in 0..CELL_WIDTH {
+                let ptr = self.pixel(x0 + x, y0 + y);
+                for i in 0..self.bytes_per_pixel {
+                    unsafe { *ptr.add(i) ^= (self.fg >> (i * 8)) as u8; }
+                }
+            }
+        }
+    }
+
+    fn new_line(&mut self) {
+        self.column = 0;
+        if self.row < self.rows - 1 {
+            self.row += 1;
+        } else {
+            self.scroll();
+        }
+    }
+
+    fn scroll(&mut self) {
+        let line_bytes = self.pitch * CELL_HEIGHT;
+        unsafe {
+            core::ptr::copy(self.base.add(line_bytes), self.base, line_bytes * (self.rows - 1));
+        }
+        for col in 0..self.cols {
+            self.draw_glyph(self.rows - 1, col, b' ');
+        }
+        self.row = self.rows - 1;
+        self.column = 0;
+    }
+}
+
+impl Console for FbWriter {
+    fn set_color(&mut self, fg: Color, bg: Color) {
+        self.fg = self.pack(fg as u8);
+        self.bg = self.pack(bg as u8);
+    }
+
+    fn write_byte(&mut self, byte: u8) {
+        self.toggle_cursor();
+        match byte {
+            b'\n' => self.new_line(),
+            byte => {
+                if self.column >= self.cols {
+                    self.new_line();
+                }
+                self.draw_glyph(self.row, self.column, byte);
+                self.column += 1;
+            }
+        }
+        self.toggle_cursor();
+    }
+
+    fn backspace(&mut self) {
+        if self.column == 0 {
+            return;
+        }
+        self.toggle_cursor();
+        self.column -= 1;
+        self.draw_glyph(self.row, self.column, b' ');
+        self.toggle_cursor();
+    }
+
+    fn clear(&mut self) {
+        for row in 0..self.rows {
+            for col in 0..self.cols {
+                self.draw_glyph(row, col, b' ');
+            }
+        }
+        self.column = 0;
+        self.row = 0;
+        self.toggle_cursor();
+    }
+
+    fn set_cursor_visible(&mut self, visible: bool) {
+        if self.cursor_visible != visible {
+            self.toggle_cursor();
+            self.cursor_visible = visible;
+            self.toggle_cursor();
+        }
+    }
+
+    fn set_cursor_position(&mut self, row: usize, col: usize) {
+        if row < self.rows && col < self.cols {
+            self.toggle_cursor();
+            self.row = row;
+            self.column = col;
+            self.toggle_cursor();
+        }
+    }
+
+    fn cursor_position(&self) -> (usize, usize) {
+        (self.row, self.column)
+    }
+
+    fn size(&self) -> (usize, usize) {
+        (self.rows, self.cols)
+    }
+}
+
+static mut FB_WRITER: Option<FbWriter> = None;
+
+// Take over the console if GRUB gave us an RGB linear framebuffer.
+// Returns false (and leaves VGA text mode in charge) otherwise.
+pub fn init() -> bool {
+    let Some(info) = multiboot::info() else {
+        return false;
+    };
+    if info.flags & multiboot::INFO_FRAMEBUFFER == 0
+        || info.framebuffer_type != FRAMEBUFFER_TYPE_RGB
+        || info.framebuffer_bpp < 15
+        || info.framebuffer_addr > u32::MAX as u64
+    {
+        return false;
+    }
+
+    // Not even one character cell
+    let cols = info.framebuffer_width as usize / CELL_WIDTH;
+    let rows = info.framebuffer_height as usize / CELL_HEIGHT;
+    if cols == 0 || rows == 0 {
+        return false;
+    }
+
+    let colors = info.color_info;
+    let mut writer = FbWriter {
+        base: info.framebuffer_addr as u32 as *mut u8,
+        pitch: info.framebuffer_pitch as usize,
+        bytes_per_pixel: info.framebuffer_bpp.div_ceil(8) as usize,
+        red: (colors[0], colors[1]),
+        green: (colors[2], colors[3]),
+        blue: (colors[4], colors[5]),
+        cols,
+        rows,
+        column: 0,
+        row: 0,
+        fg: 0,
+        bg: 0,
+        cursor_visible: false,
+    };
+    writer.set_color(Color::White, Color::Black);
+    writer.clear();
+
+    unsafe {
+        FB_WRITER = Some(writer);
+    }
+    true
+}
+
+pub fn writer() -> Option<&'static mut FbWriter> {
+    unsafe { (*core::ptr::addr_of_mut!(FB_WRITER)).as_mut() }
+}
+
+pub fn active() -> bool {
+    writer().is_some()
+}
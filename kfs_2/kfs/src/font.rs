@@ -0,0 +1,260 @@
+// font.rs - Loadable VGA text mode fonts
+//
+// In text mode the glyph bitmaps live in VGA plane 2, 32 bytes per character
+// (only the first `height` bytes are used). To write there we temporarily
+// switch the sequencer and graphics controller to flat plane 2 access at
+// 0xA0000, copy the glyphs and restore the previous register values.
+
+use crate::fb;
+use crate::multiboot;
+use crate::nps::{self, Builtin};
+use crate::hal::{Port, PortRead, PortWrite};
+use crate::vga;
+
+const SEQ_INDEX: Port<u8> = Port::new(0x3C4);
+const SEQ_DATA: Port<u8> = Port::new(0x3C5);
+const GC_INDEX: Port<u8> = Port::new(0x3CE);
+const GC_DATA: Port<u8> = Port::new(0x3CF);
+const CRTC_INDEX: Port<u8> = Port::new(0x3D4);
+const CRTC_DATA: Port<u8> = Port::new(0x3D5);
+
+const FONT_MEMORY: *mut u8 = 0xA0000 as *mut u8;
+const GLYPH_STRIDE: usize = 32;   // Bytes reserved per glyph in plane 2
+const GLYPH_COUNT: usize = 256;
+const SCANLINES: usize = 400;     // Vertical resolution of the 80x25 text mode
+
+const PSF1_MAGIC: [u8; 2] = [0x36, 0x04];
+const PSF1_MODE512: u8 = 0x01;
+const PSF2_MAGIC: [u8; 4] = [0x72, 0xB5, 0x4A, 0x86];
+
+#[derive(Copy, Clone)]
+pub struct Font {
+    pub name: &'static str,
+    pub height: u8,
+    glyphs: &'static [u8],  // `height` bytes per glyph, at least 256 glyphs
+}
+
+impl Font {
+    pub fn glyph(&self, ch: u8) -> &'static [u8] {
+        let height = self.height as usize;
+        &self.glyphs[ch as usize * height..(ch as usize + 1) * height]
+    }
+}
+
+// Copy of the BIOS font, taken before anything else touches plane 2
+static mut ROM_FONT: [u8; GLYPH_COUNT * 16] = [0; GLYPH_COUNT * 16];
+// 8x8 font derived from the ROM font by merging pairs of scanlines
+static mut HALF_FONT: [u8; GLYPH_COUNT * 8] = [0; GLYPH_COUNT * 8];
+static mut CURRENT: &str = "vga8x16";
+
+// Saved sequencer/graphics controller registers while plane 2 is mapped
+struct PlaneState {
+    seq_map_mask: u8,
+    seq_memory_mode: u8,
+    gc_read_map: u8,
+    gc_mode: u8,
+    gc_misc: u8,
+}
+
+fn read_reg(index_port: Port<u8>, data_port: Port<u8>, index: u8) -> u8 {
+    index_port.write(index);
+    data_port.read()
+}
+
+fn write_reg(index_port: Port<u8>, data_port: Port<u8>, index: u8, value: u8) {
+    index_port.write(index);
+    data_port.write(value);
+}
+
+unsafe fn map_plane2() -> PlaneState {
+    let state = PlaneState {
+        seq_map_mask: read_reg(SEQ_INDEX, SEQ_DATA, 0x02),
+        seq_memory_mode: read_reg(SEQ_INDEX, SEQ_DATA, 0x04),
+        gc_read_map: read_reg(GC_INDEX, GC_DATA, 0x04),
+        gc_mode: read_reg(GC_INDEX, GC_DATA, 0x05),
+        gc_misc: read_reg(GC_INDEX, GC_DATA, 0x06),
+    };
+
+    write_reg(SEQ_INDEX, SEQ_DATA, 0x02, 0x04);  // Write to plane 2 only
+    write_reg(SEQ_INDEX, SEQ_DATA, 0x04, 0x07);  // Sequential access, no odd/even
+    write_reg(GC_INDEX, GC_DATA, 0x04, 0x02);    // Read from plane 2
+    write_reg(GC_INDEX, GC_DATA, 0x05, 0x00);    // Disable odd/even addressing
+    write_reg(GC_INDEX, GC_DATA, 0x06, 0x04);    // Map 64K at 0xA0000
+    state
+}
+
+unsafe fn unmap_plane2(state: PlaneState) {
+    write_reg(SEQ_INDEX, SEQ_DATA, 0x02, state.seq_map_mask);
+    write_reg(SEQ_INDEX, SEQ_DATA, 0x04, state.seq_memory_mode);
+    write_reg(GC_INDEX, GC_DATA, 0x04, state.gc_read_map);
+    write_reg(GC_INDEX, GC_DATA, 0x05, state.gc_mode);
+    write_reg(GC_INDEX, GC_DATA, 0x06, state.gc_misc);
+}
+
+// Save the BIOS font so it can be restored and build the 8x8 variant from it
+pub fn init() {
+    unsafe {
+        let rom = &mut *core::ptr::addr_of_mut!(ROM_FONT);
+        let half = &mut *core::ptr::addr_of_mut!(HALF_FONT);
+
+        let state = map_plane2();
+        for glyph in 0..GLYPH_COUNT {
+            for line in 0..16 {
+                rom[glyph * 16 + line] = *FONT_MEMORY.add(glyph * GLYPH_STRIDE + line);
+            }
+        }
+        unmap_plane2(state);
+
+        for glyph in 0..GLYPH_COUNT {
+            for line in 0..8 {
+                half[glyph * 8 + line] = rom[glyph * 16 + line * 2] | rom[glyph * 16 + line * 2 + 1];
+            }
+        }
+    }
+}
+
+// Parse a PSF1 or PSF2 file. Only 8 pixel wide fonts fit the VGA text mode.
+pub fn parse_psf(name: &'static str, data: &'static [u8]) -> Option<Font> {
+    if data.len() >= 4 && data[..2] == PSF1_MAGIC {
+        let height = data[3] as usize;
+        let count = if data[2] & PSF1_MODE512 != 0 { 512 } else { 256 };
+        return make_font(name, data, 4, height, 8, count);
+    }
+
+    if data.len() >= 32 && data[..4] == PSF2_MAGIC {
+        let field = |i: usize| u32::from_le_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]) as usize;
+        let header_size = field(8);
+        let count = field(16);
+        let bytes_per_glyph = field(20);
+        let height = field(24);
+        let width = field(28);
+        if bytes_per_glyph != height {
+            return None;
+        }
+        return make_font(name, data, header_size, height, width, count);
+    }
+
+    None
+}
+
+fn make_font(name: &'static str, data: &'static [u8], offset: usize,
+             height: usize, width: usize, count: usize) -> Option<Font> {
+    // The cursor takes the last two scanlines of a cell
+    if width != 8 || !(2..=GLYPH_STRIDE).contains(&height) || count < GLYPH_COUNT {
+        return None;
+    }
+    // The PSF2 header size comes from the file
+    let end = GLYPH_COUNT.checked_mul(height).and_then(|size| offset.checked_add(size))?;
+    if end > data.len() {
+        return None;
+    }
+    Some(Font {
+        name,
+        height: height as u8,
+        glyphs: &data[offset..end],
+    })
+}
+
+// Every font we can switch to: the two built-ins plus PSF boot modules
+pub fn for_each(mut f: impl FnMut(Font)) {
+    unsafe {
+        f(Font {
+            name: "vga8x16",
+            height: 16,
+            glyphs: &*core::ptr::addr_of!(ROM_FONT),
+        });
+        f(Font {
+            name: "vga8x8",
+            height: 8,
+            glyphs: &*core::ptr::addr_of!(HALF_FONT),
+        });
+    }
+    for i in 0..multiboot::module_count() {
+        if let Some(module) = multiboot::module(i) {
+            if let Some(font) = parse_psf(module.name, module.data()) {
+                f(font);
+            }
+        }
+    }
+}
+
+pub fn find(name: &str) -> Option<Font> {
+    let mut found = None;
+    for_each(|font| {
+        if found.is_none() && font.name == name {
+            found = Some(font);
+        }
+    });
+    found
+}
+
+pub fn current() -> &'static str {
+    unsafe { *core::ptr::addr_of!(CURRENT) }
+}
+
+// Upload the glyphs into plane 2 and resize the character cell to match
+pub fn load(font: &Font) {
+    let height = font.height as usize;
+    unsafe {
+        let state = map_plane2();
+        for glyph in 0..GLYPH_COUNT {
+            for line in 0..GLYPH_STRIDE {
+                let value = if line < height { font.glyphs[glyph * height + line] } else { 0 };
+                *FONT_MEMORY.add(glyph * GLYPH_STRIDE + line) = value;
+            }
+        }
+        unmap_plane2(state);
+
+        // Maximum scan line register: low 5 bits are the cell height - 1
+        let max_scan = read_reg(CRTC_INDEX, CRTC_DATA, 0x09);
+        write_reg(CRTC_INDEX, CRTC_DATA, 0x09, (max_scan & 0xE0) | (font.height - 1));
+
+        CURRENT = font.name;
+    }
+    vga::writer().set_geometry(SCANLINES / height, font.height);
+}
+
+pub fn select(name: &str) -> Result<(), &'static str> {
+    match find(name) {
+        Some(font) => {
+            load(&font);
+            Ok(())
+        }
+        None => Err("no such font"),
+    }
+}
+
+fn cmd_font(argv: &[&str]) {
+    if fb::active() {
+        println!("font: not available on the framebuffer console");
+        return;
+    }
+
+    let Some(name) = argv.get(1) else {
+        println!("Available fonts:");
+        for_each(|f| {
+            let marker = if f.name == current() { '*' } else { ' ' };
+            println!(" {} {:<12} 8x{}", marker, f.name, f.height);
+        });
+        return;
+    };
+
+    match select(name) {
+        Ok(()) => println!("Font switched to '{}'", name),
+        Err(err) => println!("font: {}: '{}'", err, name),
+    }
+}
+
+fn complete_font(arg: usize, _prefix: &str, add: &mut dyn FnMut(&str)) {
+    if arg == 1 {
+        for_each(|f| add(f.name));
+    }
+}
+
+static COMMANDS: [Builtin; 1] = [
+    Builtin { name: "font", usage: "font [name]", help: "List fonts or switch to one", handler: cmd_font, complete: Some(complete_font) },
+];
+
+pub fn register_commands() {
+    nps::register_all(&COMMANDS);
+}
@@ -0,0 +1,256 @@
+// fpu.rs - x87/SSE initialization and lazy context switching
+//
+// The kernel itself is built without MMX/SSE (see the target JSON), so
+// interrupt handlers never touch FPU registers and only code that opts in
+// with explicit instructions does. Each task owns an FpuState; switch_to()
+// merely sets CR0.TS, and the first FPU instruction afterwards raises #NM,
+// where the registers are saved to the previous owner and loaded from the
+// current task. Tasks that never use the FPU never pay for a save.
+
+use core::arch::asm;
+use kfs_core::fpu::{Mxcsr, X87Status, DEFAULT_MXCSR};
+use crate::console;
+use crate::cpu::{self, Feature};
+use crate::exc::exceptions;
+use crate::vga::Color;
+
+const CR0_MP: u32 = 1 << 1;     // WAIT/FWAIT honour TS
+const CR0_EM: u32 = 1 << 2;     // Emulate: every FPU instruction raises #NM
+const CR0_TS: u32 = 1 << 3;     // Task switched: next FPU instruction raises #NM
+const CR0_NE: u32 = 1 << 5;     // Report x87 errors as #MF instead of IRQ13
+const CR4_OSFXSR: u32 = 1 << 9;
+const CR4_OSXMMEXCPT: u32 = 1 << 10;
+
+// FXSAVE image (FNSAVE needs only the first 108 bytes)
+#[repr(C, align(16))]
+pub struct FpuState {
+    area: [u8; 512],
+    initialized: bool,
+}
+
+impl FpuState {
+    pub const fn new() -> FpuState {
+        FpuState { area: [0; 512], initialized: false }
+    }
+}
+
+static mut ENABLED: bool = true;
+static mut SSE_ALLOWED: bool = true;
+static mut SSE: bool = false;
+static mut FXSR: bool = false;
+
+// Context whose registers are in the FPU right now, and the running one
+static mut OWNER: *mut FpuState = core::ptr::null_mut();
+static mut CURRENT: *mut FpuState = core::ptr::null_mut();
+static mut KERNEL_STATE: FpuState = FpuState::new();
+
+// fpu=off leaves CR0.EM set, fpu=nosse enables only the x87
+fn setup_fpu(value: Option<&str>) -> bool {
+    unsafe {
+        match value {
+            Some("on") | None => ENABLED = true,
+            Some("off") => ENABLED = false,
+            Some("nosse") => SSE_ALLOWED = false,
+            _ => return false,
+        }
+    }
+    true
+}
+kernel_param!("fpu", "Floating point support: on, off or nosse", setup_fpu);
+
+unsafe fn read_cr0() -> u32 {
+    let value: u32;
+    asm!("mov {}, cr0", out(reg) value, options(nomem, nostack, preserves_flags));
+    value
+}
+
+unsafe fn write_cr0(value: u32) {
+    asm!("mov cr0, {}", in(reg) value, options(nostack, preserves_flags));
+}
+
+unsafe fn set_ts() {
+    write_cr0(read_cr0() | CR0_TS);
+}
+
+unsafe fn clear_ts() {
+    asm!("clts", options(nomem, nostack, preserves_flags));
+}
+
+unsafe fn save(state: *mut FpuState) {
+    let area = (*state).area.as_mut_ptr();
+    if FXSR {
+        asm!("fxsave [{}]", in(reg) area, options(nostack, preserves_flags));
+    } else {
+        // FNSAVE also reinitializes the FPU, which is fine: a restore follows
+        asm!("fnsave [{}]", in(reg) area, options(nostack, preserves_flags));
+    }
+    (*state).initialized = true;
+}
+
+unsafe fn restore(state: *mut FpuState) {
+    if !(*state).initialized {
+        // First use in this context: start from the power-on defaults
+        asm!("fninit", options(nomem, nostack, preserves_flags));
+        if SSE {
+            asm!("ldmxcsr [{}]", in(reg) &DEFAULT_MXCSR, options(nostack, preserves_flags));
+        }
+        return;
+    }
+    let area = (*state).area.as_ptr();
+    if FXSR {
+        asm!("fxrstor [{}]", in(reg) area, options(nostack, preserves_flags));
+    } else {
+        asm!("frstor [{}]", in(reg) area, options(nostack, preserves_flags));
+    }
+}
+
+pub fn init() {
+    unsafe {
+        if !ENABLED || !cpu::has(Feature::Fpu) {
+            // Keep EM set so stray FPU instructions fault instead of running
+            write_cr0(read_cr0() | CR0_EM);
+            console::info("      FPU disabled\n\n", Color::Yellow, Color::Black);
+            return;
+        }
+
+        write_cr0((read_cr0() & !(CR0_EM | CR0_TS)) | CR0_MP | CR0_NE);
+        asm!("fninit", options(nomem, nostack, preserves_flags));
+
+        FXSR = cpu::has(Feature::Fxsr);
+        if SSE_ALLOWED && FXSR && cpu::has(Feature::Sse) {
+            let cr4: u32;
+            asm!("mov {}, cr4", out(reg) cr4, options(nomem, nostack, preserves_flags));
+            asm!("mov cr4, {}", in(reg) cr4 | CR4_OSFXSR | CR4_OSXMMEXCPT, options(nostack, preserves_flags));
+            asm!("ldmxcsr [{}]", in(reg) &DEFAULT_MXCSR, options(nostack, preserves_flags));
+            SSE = true;
+        }
+
+        // The boot thread is the first context; nobody owns the registers yet
+        CURRENT = core::ptr::addr_of_mut!(KERNEL_STATE);
+        OWNER = core::ptr::null_mut();
+        set_ts();
+    }
+    let msg = if sse_enabled() { "      FPU enabled (x87, SSE)\n\n" } else { "      FPU enabled (x87)\n\n" };
+    console::info(msg, Color::Green, Color::Black);
+}
+
+pub fn enabled() -> bool {
+    unsafe { !CURRENT.is_null() }
+}
+
+pub fn sse_enabled() -> bool {
+    unsafe { SSE }
+}
+
+// Called by the scheduler when `state`'s task starts running
+pub fn switch_to(state: *mut FpuState) {
+    if !enabled() {
+        return;
+    }
+    unsafe {
+        CURRENT = state;
+        if OWNER == state {
+            clear_ts();
+        } else {
+            set_ts();
+        }
+    }
+}
+
+// A task is going away: its registers no longer need saving
+pub fn release(state: *mut FpuState) {
+    unsafe {
+        if OWNER == state {
+            OWNER = core::ptr::null_mut();
+        }
+    }
+}
+
+// #NM: hand the FPU to the running context
+#[no_mangle]
+pub extern "C" fn rust_device_not_available() {
+    unsafe {
+        if !enabled() {
+            console::set_cursor_position(10, 0);
+            console::printc("EXCEPTION #7: DEVICE NOT AVAILABLE (FPU DISABLED)", Color::White, Color::Red);
+            exceptions::halt_forever();
+        }
+        clear_ts();
+        if OWNER == CURRENT {
+            return;
+        }
+        if !OWNER.is_null() {
+            save(OWNER);
+        }
+        restore(CURRENT);
+        OWNER = CURRENT;
+    }
+}
+
+// Status of the x87 unit, for the #MF report
+pub fn x87_status() -> X87Status {
+    let (status, control): (u16, u16);
+    unsafe {
+        clear_ts();
+        asm!("fnstsw ax", out("ax") status, options(nomem, nostack, preserves_flags));
+        let mut word = 0u16;
+        asm!("fnstcw [{}]", in(reg) &mut word, options(nostack, preserves_flags));
+        control = word;
+    }
+    X87Status { status, control }
+}
+
+// MXCSR, for the #XM report
+pub fn mxcsr() -> Mxcsr {
+    let mut value = 0u32;
+    unsafe {
+        clear_ts();
+        asm!("stmxcsr [{}]", in(reg) &mut value, options(nostack, preserves_flags));
+    }
+    Mxcsr(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fld1_fstp() -> u32 {
+        let mut value = 0u32;
+        unsafe {
+            asm!("fld1", "fstp dword ptr [{}]", in(reg) &mut value, options(nostack));
+        }
+        value
+    }
+
+    #[test_case]
+    fn first_use_claims_the_fpu() {
+        if !enabled() {
+            return;
+        }
+        assert_eq!(fld1_fstp(), 1.0f32.to_bits());
+        assert!(unsafe { OWNER == CURRENT });
+    }
+
+    #[test_case]
+    fn contexts_keep_their_registers() {
+        if !enabled() {
+            return;
+        }
+        static mut OTHER: FpuState = FpuState::new();
+        // The scheduler must not switch contexts behind the test's back
+        let interrupts = crate::idt::disable_interrupts();
+        let kernel = unsafe { CURRENT };
+        let other = core::ptr::addr_of_mut!(OTHER);
+
+        // Leave 2.0 on the kernel context's stack, switch away and back
+        unsafe { asm!("fld1", "fld1", "faddp", options(nomem, nostack)) };
+        switch_to(other);
+        assert_eq!(fld1_fstp(), 1.0f32.to_bits());
+        switch_to(kernel);
+        let mut value = 0u32;
+        unsafe { asm!("fstp dword ptr [{}]", in(reg) &mut value, options(nostack)) };
+        release(other);
+        crate::idt::restore_interrupts(interrupts);
+        assert_eq!(value, 2.0f32.to_bits());
+    }
+}
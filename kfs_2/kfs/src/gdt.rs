@@ -1,51 +1,12 @@
 // gdt.rs - Global Descriptor Table implementation
 
 use core::arch::asm;
-use crate::vga;
+use kfs_core::gdt::{Descriptor, GdtEntry};
+use crate::console;
+use crate::tss;
+use crate::nps::{self, Builtin};
 use crate::vga::Color;
 
-// GDT Entry structure (8 bytes)
-#[repr(C, packed)]
-#[derive(Copy, Clone)]
-pub struct GdtEntry {
-    limit_low: u16,    // Lower 16 bits of limit
-    base_low: u16,     // Lower 16 bits of base
-    base_middle: u8,   // Next 8 bits of base
-    access: u8,        // Access flags
-    granularity: u8,   // Granularity and limit bits 16-19
-    base_high: u8,     // Last 8 bits of base
-}
-
-impl GdtEntry {
-    // Create a null entry
-    const fn null() -> GdtEntry {
-        GdtEntry {
-            limit_low: 0,
-            base_low: 0,
-            base_middle: 0,
-            access: 0,
-            granularity: 0,
-            base_high: 0,
-        }
-    }
-
-    // Create a GDT entry
-    // base: Base address
-    // limit: Limit (max offset)
-    // access: Access byte
-    // gran: Granularity byte
-    const fn new(base: u32, limit: u32, access: u8, gran: u8) -> GdtEntry {
-        GdtEntry {
-            limit_low: (limit & 0xFFFF) as u16,
-            base_low: (base & 0xFFFF) as u16,
-            base_middle: ((base >> 16) & 0xFF) as u8,
-            access,
-            granularity: ((limit >> 16) & 0x0F) as u8 | (gran & 0xF0),
-            base_high: ((base >> 24) & 0xFF) as u8,
-        }
-    }
-}
-
 // GDT Pointer structure for LGDT instruction
 #[repr(C, packed)]
 struct GdtPointer {
@@ -53,117 +14,223 @@ struct GdtPointer {
     base: u32,   // Address of GDT
 }
 
-// The Global Descriptor Table (6 entries)
-// Must be placed at 0x800 according to subject
-static mut GDT: [GdtEntry; 6] = [
+// Fixed layout below, then slots handed out at run time by allocate()
+const FIXED_ENTRIES: usize = 9;
+const GDT_ENTRIES: usize = 16;
+
+// The subject wants the table at this physical address; init() copies the
+// layout below there and loads it with lgdt
+pub const GDT_ADDRESS: u32 = 0x800;
+const GDT_SIZE: usize = core::mem::size_of::<[GdtEntry; GDT_ENTRIES]>();
+
+// The Global Descriptor Table, as copied to GDT_ADDRESS
+const GDT_LAYOUT: [GdtEntry; FIXED_ENTRIES] = [
     // Null descriptor (required)
     GdtEntry::null(),
-    
-    // Kernel Code Segment (0x08)
-    // Base=0, Limit=0xFFFFF, Access=0x9A, Granularity=0xC
-    // Access: Present=1, DPL=0, Type=Code/Execute/Read
-    // Gran: 4KB pages, 32-bit
-    GdtEntry::new(0, 0xFFFFF, 0x9A, 0xC0),
-    
-    // Kernel Data Segment (0x10)
-    // Base=0, Limit=0xFFFFF, Access=0x92, Granularity=0xC
-    // Access: Present=1, DPL=0, Type=Data/Read/Write
-    GdtEntry::new(0, 0xFFFFF, 0x92, 0xC0),
-    
-    // User Code Segment (0x18)
-    // Base=0, Limit=0xFFFFF, Access=0xFA, Granularity=0xC
-    // Access: Present=1, DPL=3 (user), Type=Code/Execute/Read
-    GdtEntry::new(0, 0xFFFFF, 0xFA, 0xC0),
-    
-    // User Data Segment (0x20)
-    // Base=0, Limit=0xFFFFF, Access=0xF2, Granularity=0xC
-    // Access: Present=1, DPL=3 (user), Type=Data/Read/Write
-    GdtEntry::new(0, 0xFFFFF, 0xF2, 0xC0),
-    
-    // Task State Segment (0x28) - minimal for now
-    // Will be properly set up when we implement task switching
+
+    // Kernel Code (0x08) and Data (0x10): flat 4 GiB, ring 0
+    Descriptor::code(0, true, false).entry(),
+    Descriptor::data(0, true, false).entry(),
+
+    // User Code (0x18) and Data (0x20): the same range, ring 3
+    Descriptor::code(3, true, false).entry(),
+    Descriptor::data(3, true, false).entry(),
+
+    // Task State Segment (0x28) and the double fault TSS (0x30)
+    // Their bases are only known at run time, init() fills them in
+    GdtEntry::null(),
     GdtEntry::null(),
+
+    // Kernel Stack (0x38), loaded into SS and the TSS's ss0, and User Stack (0x40)
+    Descriptor::data(0, true, false).entry(),
+    Descriptor::data(3, true, false).entry(),
 ];
 
+// The live table at GDT_ADDRESS
+fn table() -> &'static mut [GdtEntry; GDT_ENTRIES] {
+    unsafe { &mut *(GDT_ADDRESS as *mut [GdtEntry; GDT_ENTRIES]) }
+}
+
+// Base and limit the CPU is actually using
+pub fn sgdt() -> (u32, u16) {
+    let mut pointer = GdtPointer { limit: 0, base: 0 };
+    unsafe {
+        asm!("sgdt [{}]", in(reg) &mut pointer, options(nostack, preserves_flags));
+    }
+    (pointer.base, pointer.limit)
+}
+
 // External assembly function to load GDT
 extern "C" {
     fn gdt_flush(gdt_ptr: *const GdtPointer);
 }
 
-// Initialize and load the GDT
-pub fn init() {
-    vga::writer().printc("[4/4] Initializing GDT...\n", Color::Yellow, Color::Black);
+// Load the table at GDT_ADDRESS and reload every segment register (but TR)
+pub fn reload() {
+    let gdt_ptr = GdtPointer {
+        limit: (GDT_SIZE - 1) as u16,
+        base: GDT_ADDRESS,
+    };
     unsafe {
-        let gdt_ptr = GdtPointer {
-            limit: (core::mem::size_of::<[GdtEntry; 6]>() - 1) as u16,
-            base: &raw const GDT as *const _ as u32,
-        };
-        
         gdt_flush(&gdt_ptr);
     }
-    vga::writer().printc("      GDT loaded!\n\n", Color::Green, Color::Black);
 }
 
-// Print kernel stack information
-pub fn print_stack() {
-    unsafe {
-        let esp: u32;
-        let ebp: u32;
-        
-        // Get current stack and base pointers
-        asm!(
-            "mov {0}, esp",
-            "mov {1}, ebp",
-            out(reg) esp,
-            out(reg) ebp,
-        );
-
-        println!("=== Kernel Stack Information ===");
-        println!("Stack Pointer (ESP): 0x{:08x}", esp);
-        println!("Base Pointer  (EBP): 0x{:08x}", ebp);
-        println!();
-        println!("Stack contents (top 16 dwords):");
-        
-        let stack_ptr = esp as *const u32;
-        for i in 0..16 {
-            let addr = esp + (i * 4);
-            let value = *stack_ptr.offset(i as isize);
-            println!("  0x{:08x}: 0x{:08x}", addr, value);
-        }
-        println!();
+// Put a descriptor in a free dynamic slot; returns its selector with RPL = DPL
+#[allow(dead_code)]
+pub fn allocate(descriptor: Descriptor) -> Option<u16> {
+    let entry = descriptor.entry();
+    let gdt = table();
+    let index = (FIXED_ENTRIES..GDT_ENTRIES).find(|&i| gdt[i].is_null())?;
+    gdt[index] = entry;
+    Some((index as u16) << 3 | entry.dpl() as u16)
+}
+
+// Release a slot from allocate(); the fixed layout can't be freed
+#[allow(dead_code)]
+pub fn free(selector: u16) -> bool {
+    let index = (selector >> 3) as usize;
+    if !(FIXED_ENTRIES..GDT_ENTRIES).contains(&index) {
+        return false;
     }
+    table()[index] = GdtEntry::null();
+    true
+}
+
+// Initialize and load the GDT
+pub fn init() {
+    console::info("[5/5] Initializing GDT...\n", Color::Yellow, Color::Black);
+    tss::init();
+    let gdt = table();
+    *gdt = [GdtEntry::null(); GDT_ENTRIES];
+    gdt[..FIXED_ENTRIES].copy_from_slice(&GDT_LAYOUT);
+    gdt[5] = Descriptor::tss(tss::tss()).entry();
+    gdt[6] = Descriptor::tss(tss::double_fault_tss()).entry();
+    reload();
+    tss::load();
+    console::info("      GDT loaded!\n\n", Color::Green, Color::Black);
 }
 
+const ENTRY_NAMES: [&str; FIXED_ENTRIES] = [
+    "Null Descriptor",
+    "Kernel Code",
+    "Kernel Data", 
+    "User Code",
+    "User Data",
+    "TSS",
+    "Double Fault TSS",
+    "Kernel Stack",
+    "User Stack",
+];
+
 // Print GDT information
 pub fn print_gdt() {
-    unsafe {
-        println!("=== Global Descriptor Table ===");
-        println!("GDT Address: 0x{:08x}", &raw const GDT as *const _ as u32);
-        println!("GDT Size: {} bytes", core::mem::size_of::<[GdtEntry; 6]>());
-        println!();
-        
-        let entries = [
-            "Null Descriptor",
-            "Kernel Code",
-            "Kernel Data", 
-            "User Code",
-            "User Data",
-            "TSS",
-        ];
-        
-        for (i, name) in entries.iter().enumerate() {
-            let entry = &GDT[i];
-            let base = (entry.base_low as u32) 
-                     | ((entry.base_middle as u32) << 16)
-                     | ((entry.base_high as u32) << 24);
-            let limit = (entry.limit_low as u32) 
-                      | (((entry.granularity & 0x0F) as u32) << 16);
-            
-            println!("[{}] {} (offset 0x{:02x}):", i, name, i * 8);
-            println!("    Base:  0x{:08x}", base);
-            println!("    Limit: 0x{:05x}", limit);
-            println!("    Access: 0x{:02x}", entry.access);
-            println!("    Gran:   0x{:02x}", entry.granularity);
+    println!("=== Global Descriptor Table ===");
+    let (base, limit) = sgdt();
+    println!("GDT Address: 0x{:08x}", GDT_ADDRESS);
+    println!("GDT Size: {} bytes", GDT_SIZE);
+    print!("SGDT: base 0x{:08x}, limit 0x{:04x} ", base, limit);
+    if base == GDT_ADDRESS && limit as usize == GDT_SIZE - 1 {
+        console::printc("(OK)\n", Color::Green, Color::Black);
+    } else {
+        console::printc("(MISMATCH)\n", Color::Red, Color::Black);
+    }
+    println!();
+    
+    for i in 0..GDT_ENTRIES {
+        if i < FIXED_ENTRIES || !table()[i].is_null() {
+            print_gdt_entry(i);
+        }
+    }
+}
+
+// Print a single GDT entry
+pub fn print_gdt_entry(i: usize) {
+    let entry = table()[i];
+    let name = ENTRY_NAMES.get(i).copied().unwrap_or("Dynamic");
+    println!("[{}] {} (selector 0x{:02x}): base 0x{:08x} limit 0x{:05x}", i, name, i * 8, entry.base(), entry.limit());
+    println!("    {} (access 0x{:02x}, flags 0x{:x})", entry.decode(), entry.access(), entry.flags() >> 4);
+}
+
+fn cmd_gdt(argv: &[&str]) {
+    match argv.get(1) {
+        Some(arg) => match nps::parse_number(arg) {
+            Some(i) if (i as usize) < GDT_ENTRIES => print_gdt_entry(i as usize),
+            _ => println!("gdt: index must be 0-{}", GDT_ENTRIES - 1),
+        },
+        None => print_gdt(),
+    }
+}
+
+fn complete_gdt(arg: usize, _prefix: &str, add: &mut dyn FnMut(&str)) {
+    if arg == 1 {
+        for i in (0..GDT_ENTRIES).filter(|&i| i < FIXED_ENTRIES || !table()[i].is_null()) {
+            let digits = [b'0' + (i / 10) as u8, b'0' + (i % 10) as u8];
+            let start = if i < 10 { 1 } else { 0 };
+            add(core::str::from_utf8(&digits[start..]).unwrap_or(""));
         }
     }
-}
\ No newline at end of file
+}
+
+static COMMANDS: [Builtin; 1] = [
+    Builtin { name: "gdt", usage: "gdt [index]", help: "Print GDT information", handler: cmd_gdt, complete: Some(complete_gdt) },
+];
+
+pub fn register_commands() {
+    nps::register_all(&COMMANDS);
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::ShouldPanic;
+
+    #[test_case]
+    fn kernel_segments_are_flat() {
+        let gdt = table();
+        let (code, data) = (gdt[1], gdt[2]);
+        for e in [code, data, gdt[7], gdt[8]] {
+            assert_eq!(e.base(), 0);
+            assert_eq!(e.limit(), 0xFFFFF);
+            assert_eq!(e.granularity(), 0xCF);
+        }
+        assert_eq!(code.access(), 0x9A);
+        assert_eq!(data.access(), 0x92);
+        assert_eq!(gdt[7].access(), 0x92);
+        assert_eq!(gdt[8].access(), 0xF2);
+    }
+
+    #[test_case]
+    fn loaded_from_0x800() {
+        assert_eq!(sgdt(), (GDT_ADDRESS, (GDT_SIZE - 1) as u16));
+    }
+
+    #[test_case]
+    fn tss_descriptors_point_at_the_segments() {
+        let (tss, df) = (table()[5], table()[6]);
+        assert_eq!(tss.base(), tss::tss() as *const _ as u32);
+        assert_eq!(df.base(), tss::double_fault_tss() as *const _ as u32);
+        assert_eq!(tss.limit(), 103);
+        // Loaded into TR, so marked busy
+        assert_eq!(tss.access(), 0x8B);
+        assert_eq!(df.access(), 0x89);
+    }
+
+    #[test_case]
+    fn dynamic_slots_are_reused() {
+        let selector = allocate(Descriptor::data(3, true, false).base(0x4000).limit(0xFFF).byte_granular()).unwrap();
+        assert_eq!(selector, (FIXED_ENTRIES as u16) << 3 | 3);
+        assert_eq!(table()[FIXED_ENTRIES].base(), 0x4000);
+        reload();
+        assert!(free(selector));
+        assert_eq!(allocate(Descriptor::code(0, true, false)), Some((FIXED_ENTRIES as u16) << 3));
+        assert!(free((FIXED_ENTRIES as u16) << 3));
+        assert!(!free(0x08));
+    }
+
+    fn entry_out_of_range() {
+        print_gdt_entry(GDT_ENTRIES);
+    }
+
+    #[test_case]
+    const ENTRY_OUT_OF_RANGE: ShouldPanic = ShouldPanic("gdt::tests::entry_out_of_range", entry_out_of_range);
+}
@@ -0,0 +1,384 @@
+// gfx.rs - VGA graphics modes (320x200x256 and 640x480x16) with a small drawing API
+//
+// There is no BIOS in protected mode, so modes are set by writing the full
+// VGA register set directly. Register dumps are the well known values for
+// the standard IBM modes (misc, sequencer, CRTC, graphics ctrl, attribute ctrl).
+
+use crate::fb;
+use crate::font;
+use crate::nps::{self, Builtin};
+use crate::hal::{Port, PortRead, PortWrite, ReadOnlyPort, WriteOnlyPort};
+use crate::vga;
+
+const MISC_WRITE: WriteOnlyPort<u8> = WriteOnlyPort::new(0x3C2);
+const SEQ_INDEX: Port<u8> = Port::new(0x3C4);
+const SEQ_DATA: Port<u8> = Port::new(0x3C5);
+const GC_INDEX: Port<u8> = Port::new(0x3CE);
+const GC_DATA: Port<u8> = Port::new(0x3CF);
+const CRTC_INDEX: Port<u8> = Port::new(0x3D4);
+const CRTC_DATA: Port<u8> = Port::new(0x3D5);
+const AC_INDEX: Port<u8> = Port::new(0x3C0);
+const INPUT_STATUS: ReadOnlyPort<u8> = ReadOnlyPort::new(0x3DA);
+const DAC_READ_INDEX: WriteOnlyPort<u8> = WriteOnlyPort::new(0x3C7);
+const DAC_WRITE_INDEX: WriteOnlyPort<u8> = WriteOnlyPort::new(0x3C8);
+const DAC_DATA: Port<u8> = Port::new(0x3C9);
+
+const GRAPHICS_MEMORY: *mut u8 = 0xA0000 as *mut u8;
+const TEXT_MEMORY: *mut u8 = 0xB8000 as *mut u8;
+const TEXT_BYTES: usize = 80 * vga::VGA_MAX_HEIGHT * 2;
+
+// misc, 5 sequencer, 25 CRTC, 9 graphics controller, 21 attribute controller
+type ModeRegs = [u8; 61];
+
+static MODE_320X200X256: ModeRegs = [
+    0x63,
+    0x03, 0x01, 0x0F, 0x00, 0x0E,
+    0x5F, 0x4F, 0x50, 0x82, 0x54, 0x80, 0xBF, 0x1F, 0x00, 0x41, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x9C, 0x0E, 0x8F, 0x28, 0x40, 0x96, 0xB9, 0xA3, 0xFF,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x40, 0x05, 0x0F, 0xFF,
+    0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A, 0x0B,
+    0x0C, 0x0D, 0x0E, 0x0F, 0x41, 0x00, 0x0F, 0x00, 0x00,
+];
+
+static MODE_640X480X16: ModeRegs = [
+    0xE3,
+    0x03, 0x01, 0x08, 0x00, 0x06,
+    0x5F, 0x4F, 0x50, 0x82, 0x54, 0x80, 0x0B, 0x3E, 0x00, 0x40, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0xEA, 0x0C, 0xDF, 0x28, 0x00, 0xE7, 0x04, 0xE3, 0xFF,
+    0x00, 0x00, 0x00, 0x00, 0x03, 0x00, 0x05, 0x0F, 0xFF,
+    0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x14, 0x07, 0x38, 0x39, 0x3A, 0x3B,
+    0x3C, 0x3D, 0x3E, 0x3F, 0x01, 0x00, 0x0F, 0x00, 0x00,
+];
+
+static MODE_80X25_TEXT: ModeRegs = [
+    0x67,
+    0x03, 0x00, 0x03, 0x00, 0x02,
+    0x5F, 0x4F, 0x50, 0x82, 0x55, 0x81, 0xBF, 0x1F, 0x00, 0x4F, 0x0D, 0x0E,
+    0x00, 0x00, 0x00, 0x50, 0x9C, 0x0E, 0x8F, 0x28, 0x1F, 0x96, 0xB9, 0xA3, 0xFF,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x0E, 0x00, 0xFF,
+    0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x14, 0x07, 0x38, 0x39, 0x3A, 0x3B,
+    0x3C, 0x3D, 0x3E, 0x3F, 0x0C, 0x00, 0x0F, 0x08, 0x00,
+];
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum Mode {
+    Chunky320x200,  // Mode 13h: one byte per pixel, 256 colors
+    Planar640x480,  // Mode 12h: four bit planes, 16 colors
+}
+
+// Everything needed to come back to exactly the text screen we left
+static mut SAVED_TEXT: [u8; TEXT_BYTES] = [0; TEXT_BYTES];
+static mut SAVED_DAC: [u8; 256 * 3] = [0; 256 * 3];
+
+fn write_regs(regs: &ModeRegs) {
+    let mut i = 0;
+
+    MISC_WRITE.write(regs[i]);
+    i += 1;
+
+    for index in 0..5 {
+        SEQ_INDEX.write(index);
+        SEQ_DATA.write(regs[i]);
+        i += 1;
+    }
+
+    // Unlock CRTC registers 0-7 (protect bit in 0x11, and keep 0x03 bit 7 set)
+    CRTC_INDEX.write(0x03);
+    CRTC_DATA.write(CRTC_DATA.read() | 0x80);
+    CRTC_INDEX.write(0x11);
+    CRTC_DATA.write(CRTC_DATA.read() & !0x80);
+
+    for index in 0..25u8 {
+        let mut value = regs[i];
+        match index {
+            0x03 => value |= 0x80,
+            0x11 => value &= !0x80,
+            _ => {}
+        }
+        CRTC_INDEX.write(index);
+        CRTC_DATA.write(value);
+        i += 1;
+    }
+
+    for index in 0..9 {
+        GC_INDEX.write(index);
+        GC_DATA.write(regs[i]);
+        i += 1;
+    }
+
+    // Attribute controller: reading 0x3DA resets the index/data flip-flop
+    for index in 0..21 {
+        INPUT_STATUS.read();
+        AC_INDEX.write(index);
+        AC_INDEX.write(regs[i]);
+        i += 1;
+    }
+
+    // Lock the palette and re-enable the display
+    INPUT_STATUS.read();
+    AC_INDEX.write(0x20);
+}
+
+// Palette entries are 6 bits per channel (0-63)
+pub fn set_palette(index: u8, r: u8, g: u8, b: u8) {
+    DAC_WRITE_INDEX.write(index);
+    DAC_DATA.write(r & 0x3F);
+    DAC_DATA.write(g & 0x3F);
+    DAC_DATA.write(b & 0x3F);
+}
+
+pub fn get_palette(index: u8) -> (u8, u8, u8) {
+    DAC_READ_INDEX.write(index);
+    (DAC_DATA.read(), DAC_DATA.read(), DAC_DATA.read())
+}
+
+pub struct Screen {
+    mode: Mode,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Screen {
+    pub fn put_pixel(&self, x: usize, y: usize, color: u8) {
+        if x >= self.width || y >= self.height {
+            return;
+        }
+        unsafe {
+            match self.mode {
+                Mode::Chunky320x200 => {
+                    *GRAPHICS_MEMORY.add(y * self.width + x) = color;
+                }
+                Mode::Planar640x480 => {
+                    let offset = (y * self.width + x) / 8;
+                    let bit = 0x80u8 >> (x % 8);
+                    for plane in 0..4u8 {
+                        SEQ_INDEX.write(0x02);
+                        SEQ_DATA.write(1 << plane);
+                        GC_INDEX.write(0x04);
+                        GC_DATA.write(plane);
+                        let byte = GRAPHICS_MEMORY.add(offset);
+                        if color & (1 << plane) != 0 {
+                            *byte |= bit;
+                        } else {
+                            *byte &= !bit;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn clear(&self, color: u8) {
+        self.fill_rect(0, 0, self.width, self.height, color);
+    }
+
+    // Bresenham, works in all octants
+    pub fn line(&self, x0: usize, y0: usize, x1: usize, y1: usize, color: u8) {
+        let (mut x, mut y) = (x0 as isize, y0 as isize);
+        let (x1, y1) = (x1 as isize, y1 as isize);
+        let dx = (x1 - x).abs();
+        let dy = -(y1 - y).abs();
+        let sx = if x < x1 { 1 } else { -1 };
+        let sy = if y < y1 { 1 } else { -1 };
+        let mut err = dx + dy;
+
+        loop {
+            self.put_pixel(x as usize, y as usize, color);
+            if x == x1 && y == y1 {
+                break;
+            }
+            let e2 = 2 * err;
+            if e2 >= dy {
+                err += dy;
+                x += sx;
+            }
+            if e2 <= dx {
+                err += dx;
+                y += sy;
+            }
+        }
+    }
+
+    pub fn rect(&self, x: usize, y: usize, w: usize, h: usize, color: u8) {
+        if w == 0 || h == 0 {
+            return;
+        }
+        self.line(x, y, x + w - 1, y, color);
+        self.line(x, y + h - 1, x + w - 1, y + h - 1, color);
+        self.line(x, y, x, y + h - 1, color);
+        self.line(x + w - 1, y, x + w - 1, y + h - 1, color);
+    }
+
+    pub fn fill_rect(&self, x: usize, y: usize, w: usize, h: usize, color: u8) {
+        for row in y..(y + h).min(self.height) {
+            for col in x..(x + w).min(self.width) {
+                self.put_pixel(col, row, color);
+            }
+        }
+    }
+
+    // Copy a w*h bitmap of color indexes; `transparent` pixels are skipped
+    pub fn blit(&self, x: usize, y: usize, w: usize, h: usize, pixels: &[u8], transparent: Option<u8>) {
+        for row in 0..h {
+            for col in 0..w {
+                let color = pixels[row * w + col];
+                if Some(color) != transparent {
+                    self.put_pixel(x + col, y + row, color);
+                }
+            }
+        }
+    }
+
+    // Render text with the active text mode font (bitmaps taken from font.rs)
+    pub fn draw_text(&self, x: usize, y: usize, text: &str, fg: u8, bg: Option<u8>) {
+        let Some(font) = font::find(font::current()) else {
+            return;
+        };
+        for (i, ch) in text.bytes().enumerate() {
+            let glyph = font.glyph(ch);
+            for (line, bits) in glyph.iter().enumerate() {
+                for col in 0..8 {
+                    let px = x + i * 8 + col;
+                    if bits & (0x80 >> col) != 0 {
+                        self.put_pixel(px, y + line, fg);
+                    } else if let Some(bg) = bg {
+                        self.put_pixel(px, y + line, bg);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Switch from text mode into a graphics mode, saving what's needed to come back
+pub fn enter(mode: Mode) -> Screen {
+    unsafe {
+        let text = &mut *core::ptr::addr_of_mut!(SAVED_TEXT);
+        for (i, byte) in text.iter_mut().enumerate() {
+            *byte = *TEXT_MEMORY.add(i);
+        }
+        let dac = &mut *core::ptr::addr_of_mut!(SAVED_DAC);
+        for index in 0..256 {
+            let (r, g, b) = get_palette(index as u8);
+            dac[index * 3] = r;
+            dac[index * 3 + 1] = g;
+            dac[index * 3 + 2] = b;
+        }
+
+        match mode {
+            Mode::Chunky320x200 => {
+                write_regs(&MODE_320X200X256);
+                Screen { mode, width: 320, height: 200 }
+            }
+            Mode::Planar640x480 => {
+                write_regs(&MODE_640X480X16);
+                Screen { mode, width: 640, height: 480 }
+            }
+        }
+    }
+}
+
+// Back to 80x25 text: registers, palette, font (plane 2 was overwritten) and screen contents
+pub fn leave() {
+    let (row, col) = vga::writer().cursor_position();
+    unsafe {
+        write_regs(&MODE_80X25_TEXT);
+
+        let dac = &*core::ptr::addr_of!(SAVED_DAC);
+        for index in 0..256 {
+            set_palette(index as u8, dac[index * 3], dac[index * 3 + 1], dac[index * 3 + 2]);
+        }
+    }
+
+    if let Some(current) = font::find(font::current()) {
+        font::load(&current);
+    }
+
+    unsafe {
+        let text = &*core::ptr::addr_of!(SAVED_TEXT);
+        for (i, byte) in text.iter().enumerate() {
+            *TEXT_MEMORY.add(i) = *byte;
+        }
+    }
+    vga::writer().set_cursor_position(row, col);
+}
+
+// `gfx` shell command: draw a test picture, wait for a key, restore text mode
+pub fn demo(mode: Mode) {
+    let screen = enter(mode);
+    let colors: usize = if mode == Mode::Chunky320x200 { 256 } else { 16 };
+
+    if mode == Mode::Chunky320x200 {
+        // Grey ramp in the upper half of the palette
+        for i in 0..64u8 {
+            set_palette(192 + i, i, i, i);
+        }
+    }
+
+    screen.clear(0);
+
+    // Color bars along the top
+    let bar = screen.width / colors.min(64);
+    for i in 0..colors.min(64) {
+        let color = if colors == 256 { (i * 4) as u8 } else { i as u8 };
+        screen.fill_rect(i * bar, 0, bar, screen.height / 8, color);
+    }
+
+    // Frame and diagonals
+    let (w, h) = (screen.width, screen.height);
+    screen.rect(0, 0, w, h, 15);
+    screen.line(0, h / 8, w - 1, h - 1, 14);
+    screen.line(w - 1, h / 8, 0, h - 1, 14);
+
+    // Nested rectangles
+    for i in 0..8 {
+        screen.rect(w / 4 + i * 4, h / 3 + i * 4, w / 2 - i * 8, h / 3 - i * 8, (9 + i % 6) as u8);
+    }
+
+    // A small 8x8 sprite (checker with transparent holes)
+    let mut sprite = [0u8; 64];
+    for (i, px) in sprite.iter_mut().enumerate() {
+        *px = if (i / 8 + i % 8) % 2 == 0 { 12 } else { 0 };
+    }
+    for i in 0..4 {
+        screen.blit(8 + i * 12, h - 20, 8, 8, &sprite, Some(0));
+    }
+
+    screen.draw_text(w / 2 - 44, h / 2 - 4, "KFS_2 gfx", 15, Some(1));
+    screen.draw_text(8, h - 36, "Press any key", 7, None);
+
+    crate::kb::read_key();
+    leave();
+}
+
+fn cmd_gfx(argv: &[&str]) {
+    if fb::active() {
+        println!("gfx: not available on the framebuffer console");
+        return;
+    }
+
+    let mode = match argv.get(1).copied() {
+        None | Some("13h") | Some("320") => Mode::Chunky320x200,
+        Some("12h") | Some("640") => Mode::Planar640x480,
+        _ => {
+            println!("Usage: gfx [13h|640]");
+            return;
+        }
+    };
+    demo(mode);
+}
+
+fn complete_gfx(arg: usize, _prefix: &str, add: &mut dyn FnMut(&str)) {
+    if arg == 1 {
+        add("13h");
+        add("640");
+    }
+}
+
+static COMMANDS: [Builtin; 1] = [
+    Builtin { name: "gfx", usage: "gfx [13h|640]", help: "Graphics mode demo", handler: cmd_gfx, complete: Some(complete_gfx) },
+];
+
+pub fn register_commands() {
+    nps::register_all(&COMMANDS);
+}
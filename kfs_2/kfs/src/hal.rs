@@ -0,0 +1,139 @@
+// hal.rs - The real hardware behind kfs_core's Hal, and the only place the
+// kernel executes in/out
+//
+// Drivers declare typed ports (kfs_core::port) and access them through the
+// PortRead/PortWrite traits below, so grepping for those traits finds every
+// piece of port I/O in the kernel.
+
+use kfs_core::hal::Hal;
+use kfs_core::port::{self, PortValue};
+
+pub use kfs_core::port::{Port, ReadOnlyPort, WriteOnlyPort};
+
+const VGA_BUFFER: *mut u16 = 0xb8000 as *mut u16;
+
+// The machine itself: in/out instructions and the text buffer at 0xB8000
+pub struct Hardware;
+
+impl Hal for Hardware {
+    fn outb(&mut self, port: u16, value: u8) {
+        unsafe {
+            core::arch::asm!(
+                "out dx, al",
+                in("dx") port,
+                in("al") value,
+                options(nomem, nostack, preserves_flags)
+            );
+        }
+    }
+
+    fn outw(&mut self, port: u16, value: u16) {
+        unsafe {
+            core::arch::asm!(
+                "out dx, ax",
+                in("dx") port,
+                in("ax") value,
+                options(nomem, nostack, preserves_flags)
+            );
+        }
+    }
+
+    fn outl(&mut self, port: u16, value: u32) {
+        unsafe {
+            core::arch::asm!(
+                "out dx, eax",
+                in("dx") port,
+                in("eax") value,
+                options(nomem, nostack, preserves_flags)
+            );
+        }
+    }
+
+    fn inb(&mut self, port: u16) -> u8 {
+        let value: u8;
+        unsafe {
+            core::arch::asm!(
+                "in al, dx",
+                out("al") value,
+                in("dx") port,
+                options(nomem, nostack, preserves_flags)
+            );
+        }
+        value
+    }
+
+    fn inw(&mut self, port: u16) -> u16 {
+        let value: u16;
+        unsafe {
+            core::arch::asm!(
+                "in ax, dx",
+                out("ax") value,
+                in("dx") port,
+                options(nomem, nostack, preserves_flags)
+            );
+        }
+        value
+    }
+
+    fn inl(&mut self, port: u16) -> u32 {
+        let value: u32;
+        unsafe {
+            core::arch::asm!(
+                "in eax, dx",
+                out("eax") value,
+                in("dx") port,
+                options(nomem, nostack, preserves_flags)
+            );
+        }
+        value
+    }
+
+    fn write_cell(&mut self, index: usize, cell: u16) {
+        unsafe { VGA_BUFFER.add(index).write_volatile(cell) }
+    }
+
+    fn read_cell(&mut self, index: usize) -> u16 {
+        unsafe { VGA_BUFFER.add(index).read_volatile() }
+    }
+}
+
+// Port access on the real machine: PORT.read() instead of PORT.read_with(&mut Hardware)
+pub trait PortRead<T> {
+    fn read(&self) -> T;
+}
+
+pub trait PortWrite<T> {
+    fn write(&self, value: T);
+}
+
+impl<T: PortValue> PortRead<T> for Port<T> {
+    #[inline]
+    fn read(&self) -> T {
+        self.read_with(&mut Hardware)
+    }
+}
+
+impl<T: PortValue> PortRead<T> for ReadOnlyPort<T> {
+    #[inline]
+    fn read(&self) -> T {
+        self.read_with(&mut Hardware)
+    }
+}
+
+impl<T: PortValue> PortWrite<T> for Port<T> {
+    #[inline]
+    fn write(&self, value: T) {
+        self.write_with(&mut Hardware, value)
+    }
+}
+
+impl<T: PortValue> PortWrite<T> for WriteOnlyPort<T> {
+    #[inline]
+    fn write(&self, value: T) {
+        self.write_with(&mut Hardware, value)
+    }
+}
+
+pub fn io_wait() {
+    port::io_wait_with(&mut Hardware);
+}
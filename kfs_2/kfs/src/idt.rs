@@ -1,7 +1,8 @@
 // idt.rs - Complete IDT with all exception handlers
 
 use core::arch::asm;
-use crate::vga;
+use crate::console;
+use crate::tss;
 use crate::vga::Color;
 
 // IDT entry structure
@@ -34,6 +35,21 @@ impl IdtEntry {
         self.zero = 0;
         self.type_attr = 0x8E;
     }
+
+    // Trap gate callable from ring 3 (int 0x80); interrupts stay enabled
+    fn set_user_trap(&mut self, handler: unsafe extern "C" fn()) {
+        self.set_handler(handler);
+        self.type_attr = 0xEF;
+    }
+
+    // Task gate: the CPU switches to the TSS `selector` names, the offset is unused
+    fn set_task_gate(&mut self, selector: u16) {
+        self.offset_low = 0;
+        self.offset_high = 0;
+        self.selector = selector;
+        self.zero = 0;
+        self.type_attr = 0x85;
+    }
 }
 
 #[repr(C, packed)]
@@ -49,6 +65,37 @@ impl Idt {
     }
 }
 
+// What the exc.asm stubs hand to Rust: pusha's registers, the data segment
+// registers of the interrupted code, then the frame the CPU pushed.
+// user_esp/user_ss are only there for traps from ring 3.
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+pub struct TrapFrame {
+    pub edi: u32,
+    pub esi: u32,
+    pub ebp: u32,
+    pub kernel_esp: u32,
+    pub ebx: u32,
+    pub edx: u32,
+    pub ecx: u32,
+    pub eax: u32,
+    pub gs: u32,
+    pub fs: u32,
+    pub es: u32,
+    pub ds: u32,
+    pub eip: u32,
+    pub cs: u32,
+    pub eflags: u32,
+    pub user_esp: u32,
+    pub user_ss: u32,
+}
+
+impl TrapFrame {
+    pub fn in_user_mode(&self) -> bool {
+        self.cs & 0x3 == 3
+    }
+}
+
 #[repr(C, packed)]
 struct IdtPointer {
     limit: u16,
@@ -59,36 +106,51 @@ static mut IDT: Idt = Idt::new();
 
 // Import ALL handlers
 extern "C" {
+    fn timer_pic_handler();
     fn kb_pic_handler();
     fn divide_by_zero_handler();
     fn invalid_opcode_handler();
-    fn double_fault_handler();
+    fn device_not_available_handler();
     fn general_protection_fault_handler();
     fn page_fault_handler();
+    fn x87_fpu_error_handler();
+    fn simd_exception_handler();
+    fn syscall_handler();
     fn default_interrupt_handler();
 }
 
 pub fn init() {
-    vga::writer().printc("[1/4] Initializing IDT...\n", Color::Yellow, Color::Black);
+    console::info("[1/5] Initializing IDT...\n", Color::Yellow, Color::Black);
     unsafe {
         // Exception handlers (0-31)
         IDT.entries[0].set_handler(divide_by_zero_handler);
         IDT.entries[6].set_handler(invalid_opcode_handler);
-        IDT.entries[8].set_handler(double_fault_handler);
+        IDT.entries[8].set_task_gate(tss::DOUBLE_FAULT_TSS_SELECTOR);
         IDT.entries[13].set_handler(general_protection_fault_handler);
         IDT.entries[14].set_handler(page_fault_handler);
+
+        // FPU: lazy context load, x87 and SSE errors
+        IDT.entries[7].set_handler(device_not_available_handler);
+        IDT.entries[16].set_handler(x87_fpu_error_handler);
+        IDT.entries[19].set_handler(simd_exception_handler);
         
         // Set default handler for ALL other interrupts (1-31, 32-255)
         // This catches timer, spurious interrupts, etc.
         for i in 1..256 {
-            if i != 0 && i != 6 && i != 8 && i != 13 && i != 14 && i != 33 {
+            if !matches!(i, 0 | 6 | 7 | 8 | 13 | 14 | 16 | 19 | 32 | 33 | 0x80) {
                 IDT.entries[i].set_handler(default_interrupt_handler);
             }
         }
         
+        // Timer interrupt (IRQ0 = interrupt 32)
+        IDT.entries[32].set_handler(timer_pic_handler);
+
         // Keyboard interrupt (IRQ1 = interrupt 33)
         IDT.entries[33].set_handler(kb_pic_handler);
 
+        // System calls
+        IDT.entries[0x80].set_user_trap(syscall_handler);
+
         // Load IDT
         let idt_ptr = IdtPointer {
             limit: (core::mem::size_of::<Idt>() - 1) as u16,
@@ -101,13 +163,72 @@ pub fn init() {
             options(readonly, nostack, preserves_flags)
         );
     }
-    vga::writer().printc("      IDT initialized!\n\n", Color::Green, Color::Black);
+    console::info("      IDT initialized!\n\n", Color::Green, Color::Black);
 }
 
 pub fn enable_interrupts() {
-    vga::writer().printc("[3/4] Initializing Interrupts...\n", Color::Yellow, Color::Black);
+    console::info("[4/5] Initializing Interrupts...\n", Color::Yellow, Color::Black);
     unsafe {
         asm!("sti", options(nomem, nostack));
     }
-    vga::writer().printc("      Interrupts loaded!\n\n", Color::Green, Color::Black);
+    console::info("      Interrupts loaded!\n\n", Color::Green, Color::Black);
+}
+
+const EFLAGS_IF: u32 = 1 << 9;
+
+// cli, returning whether interrupts were enabled before
+pub fn disable_interrupts() -> bool {
+    let eflags: u32;
+    unsafe {
+        asm!("pushfd", "pop {}", "cli", out(reg) eflags, options(nomem));
+    }
+    eflags & EFLAGS_IF != 0
+}
+
+// Undo disable_interrupts()
+pub fn restore_interrupts(enabled: bool) {
+    if enabled {
+        unsafe {
+            asm!("sti", options(nomem, nostack));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn handler_address_is_split() {
+        let mut entry = IdtEntry::new();
+        entry.set_handler(timer_pic_handler);
+        let addr = timer_pic_handler as *const () as u32;
+        assert_eq!({ entry.offset_low }, addr as u16);
+        assert_eq!({ entry.offset_high }, (addr >> 16) as u16);
+        assert_eq!({ entry.selector }, 0x08);
+        assert_eq!(entry.zero, 0);
+        // Present, DPL 0, 32-bit interrupt gate
+        assert_eq!(entry.type_attr, 0x8E);
+    }
+
+    #[test_case]
+    fn double_fault_uses_a_task_gate() {
+        let entry = unsafe { IDT.entries[8] };
+        assert_eq!({ entry.selector }, tss::DOUBLE_FAULT_TSS_SELECTOR);
+        assert_eq!(entry.type_attr, 0x85);
+    }
+
+    #[test_case]
+    fn syscall_gate_is_reachable_from_ring3() {
+        let entry = unsafe { IDT.entries[0x80] };
+        // Present, DPL 3, 32-bit trap gate
+        assert_eq!(entry.type_attr, 0xEF);
+    }
+
+    #[test_case]
+    fn timer_vector_is_installed() {
+        let entry = unsafe { IDT.entries[32] };
+        let addr = ({ entry.offset_high } as u32) << 16 | { entry.offset_low } as u32;
+        assert_eq!(addr, timer_pic_handler as *const () as u32);
+    }
 }
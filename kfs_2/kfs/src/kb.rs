@@ -1,62 +1,282 @@
+use crate::idt;
+use crate::keymap;
 use crate::pic;
+use crate::process;
+use crate::sync::WaitQueue;
+use crate::thread;
+use crate::hal::{Port, PortRead, PortWrite, ReadOnlyPort, WriteOnlyPort};
 
-const KEYBOARD_DATA_PORT: u16 = 0x60;
-const KEYBOARD_STATUS_PORT: u16 = 0x64;
-
-// Complete scan code to ASCII lookup table (DE QWERTZ, lowercase) --- NOT COMPLETE ---
-static SCANCODE_TO_ASCII: [u8; 128] = [
-    0,    27,  b'1', b'2', b'3', b'4', b'5', b'6',  // 0x00-0x07
-    b'7', b'8', b'9', b'0', b'-', b'=', 8,   b'\t', // 0x08-0x0F (backspace, tab)
-    b'q', b'w', b'e', b'r', b't', b'z', b'u', b'i', // 0x10-0x17
-    b'o', b'p', b'[', b']', b'\n', 0,   b'a', b's', // 0x18-0x1F (enter, ctrl)
-    b'd', b'f', b'g', b'h', b'j', b'k', b'l', b';', // 0x20-0x27
-    b'\'', b'`', 0,   b'\\', b'y', b'x', b'c', b'v', // 0x28-0x2F (shift)
-    b'b', b'n', b'm', b',', b'.', b'/', 0,   b'*',  // 0x30-0x37 (shift, *)
-    0,    b' ', 0,   0,   0,   0,   0,   0,          // 0x38-0x3F (alt, caps, F1-F5)
-    0,    0,   0,   0,   0,   0,   0,   0,           // 0x40-0x47 (F6-F10, num lock, scroll lock)
-    0,    0,   0,   0,   0,   0,   0,   0,           // 0x48-0x4F
-    0,    0,   0,   0,   0,   0,   0,   0,           // 0x50-0x57
-    0,    0,   0,   0,   0,   0,   0,   0,           // 0x58-0x5F
-    0,    0,   0,   0,   0,   0,   0,   0,           // 0x60-0x67
-    0,    0,   0,   0,   0,   0,   0,   0,           // 0x68-0x6F
-    0,    0,   0,   0,   0,   0,   0,   0,           // 0x70-0x77
-    0,    0,   0,   0,   0,   0,   0,   0,           // 0x78-0x7F
-];
-
-#[inline]
-unsafe fn inb(port: u16) -> u8 {
-    let value: u8;
-    core::arch::asm!(
-        "in al, dx",
-        out("al") value,
-        in("dx") port,
-        options(nomem, nostack, preserves_flags)
-    );
-    value
+// 0x64 is the status register when read and the controller command register when written
+const KEYBOARD_DATA_PORT: Port<u8> = Port::new(0x60);
+const KEYBOARD_STATUS_PORT: ReadOnlyPort<u8> = ReadOnlyPort::new(0x64);
+const CONTROLLER_COMMAND_PORT: WriteOnlyPort<u8> = WriteOnlyPort::new(0x64);
+
+const STATUS_OUTPUT_FULL: u8 = 0x01;
+const STATUS_INPUT_FULL: u8 = 0x02;
+
+const KEYBOARD_CMD_SET_LEDS: u8 = 0xED;
+const KEYBOARD_ACK: u8 = 0xFA;
+const CONTROLLER_CMD_RESET: u8 = 0xFE;
+
+const SCANCODE_CAPS_LOCK: u8 = 0x3A;
+const SCANCODE_NUM_LOCK: u8 = 0x45;
+const SCANCODE_SCROLL_LOCK: u8 = 0x46;
+const SCANCODE_CTRL: u8 = 0x1D;
+const SCANCODE_LEFT_SHIFT: u8 = 0x2A;
+const SCANCODE_RIGHT_SHIFT: u8 = 0x36;
+const SCANCODE_ALT: u8 = 0x38;
+const SCANCODE_EXTENDED: u8 = 0xE0;
+const SCANCODE_RELEASE: u8 = 0x80;
+
+// Decoded key press handed to the shell
+#[derive(Copy, Clone, PartialEq)]
+pub enum Key {
+    Char(u8),       // Printable ASCII, '\n', backspace (0x08), tab, escape (0x1B)
+    Ctrl(u8),       // Ctrl + lowercase letter
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    Insert,
+    Delete,
+    PageUp,
+    PageDown,
+}
+
+// Lock key state, toggled on key press
+static mut CAPS_LOCK: bool = false;
+static mut NUM_LOCK: bool = false;
+static mut SCROLL_LOCK: bool = false;
+
+// Modifier state, held while the key is down
+static mut CTRL: bool = false;
+static mut SHIFT: bool = false;
+static mut ALTGR: bool = false;
+// Set after an 0xE0 prefix byte; the next scancode is an extended key
+static mut EXTENDED: bool = false;
+
+// Navigation block: E0-prefixed keys, and the keypad while Num Lock is off
+fn navigation_key(scancode: u8) -> Option<Key> {
+    match scancode {
+        0x47 => Some(Key::Home),
+        0x48 => Some(Key::Up),
+        0x49 => Some(Key::PageUp),
+        0x4B => Some(Key::Left),
+        0x4D => Some(Key::Right),
+        0x4F => Some(Key::End),
+        0x50 => Some(Key::Down),
+        0x51 => Some(Key::PageDown),
+        0x52 => Some(Key::Insert),
+        0x53 => Some(Key::Delete),
+        _ => None,
+    }
+}
+
+// Keypad digits while Num Lock is on
+fn keypad_digit(scancode: u8) -> Option<u8> {
+    match scancode {
+        0x47 => Some(b'7'), 0x48 => Some(b'8'), 0x49 => Some(b'9'),
+        0x4B => Some(b'4'), 0x4C => Some(b'5'), 0x4D => Some(b'6'),
+        0x4F => Some(b'1'), 0x50 => Some(b'2'), 0x51 => Some(b'3'),
+        0x52 => Some(b'0'), 0x53 => Some(b'.'),
+        _ => None,
+    }
+}
+
+// Turn one scancode into a key press, updating modifier state on the way
+unsafe fn decode(scancode: u8) -> Option<Key> {
+    if scancode == SCANCODE_EXTENDED {
+        EXTENDED = true;
+        return None;
+    }
+    let extended = EXTENDED;
+    EXTENDED = false;
+
+    let released = scancode & SCANCODE_RELEASE != 0;
+    let code = scancode & !SCANCODE_RELEASE;
+
+    match code {
+        // Right Ctrl is E0 1D, left Ctrl is 1D
+        SCANCODE_CTRL => {
+            CTRL = !released;
+            return None;
+        }
+        // E0 2A / E0 AA are fake shifts sent around some extended keys
+        SCANCODE_LEFT_SHIFT | SCANCODE_RIGHT_SHIFT if !extended => {
+            SHIFT = !released;
+            return None;
+        }
+        // Right Alt (E0 38) is AltGr
+        SCANCODE_ALT if extended => {
+            ALTGR = !released;
+            return None;
+        }
+        _ => {}
+    }
+
+    if released {
+        return None;
+    }
+
+    if extended {
+        return navigation_key(code);
+    }
+
+    if let Some(digit) = keypad_digit(code) {
+        return if NUM_LOCK { Some(Key::Char(digit)) } else { navigation_key(code) };
+    }
+
+    let keymap = keymap::current();
+    let base = keymap.translate(code, false, false);
+    if CTRL && base.is_ascii_lowercase() {
+        return Some(Key::Ctrl(base));
+    }
+    let mut ascii = keymap.translate(code, SHIFT, ALTGR);
+    if ascii == 0 {
+        return None;
+    }
+    if CAPS_LOCK && ascii.is_ascii_alphabetic() {
+        // Caps Lock inverts the case Shift would have given
+        ascii ^= 0x20;
+    }
+    Some(Key::Char(ascii))
+}
+
+pub fn lock_state() -> (bool, bool, bool) {
+    unsafe { (CAPS_LOCK, NUM_LOCK, SCROLL_LOCK) }
+}
+
+// Mirror the lock state on the keyboard LEDs; the keyboard ACKs each byte
+unsafe fn update_leds() {
+    let leds = (SCROLL_LOCK as u8) | (NUM_LOCK as u8) << 1 | (CAPS_LOCK as u8) << 2;
+    wait_input_empty();
+    KEYBOARD_DATA_PORT.write(KEYBOARD_CMD_SET_LEDS);
+    if wait_ack() {
+        wait_input_empty();
+        KEYBOARD_DATA_PORT.write(leds);
+        wait_ack();
+    }
+}
+
+// Read the keyboard's 0xFA ACK so it never reaches kbhandler as a scancode.
+// Runs inside the keyboard interrupt, so poll for a bounded time.
+fn wait_ack() -> bool {
+    for _ in 0..100_000 {
+        if KEYBOARD_STATUS_PORT.read() & STATUS_OUTPUT_FULL != 0 {
+            return KEYBOARD_DATA_PORT.read() == KEYBOARD_ACK;
+        }
+    }
+    false
+}
+
+// The controller ignores writes until it has consumed the previous byte
+fn wait_input_empty() {
+    while KEYBOARD_STATUS_PORT.read() & STATUS_INPUT_FULL != 0 {}
+}
+
+// Pulse the CPU reset line through the keyboard controller
+pub fn reset_cpu() {
+    wait_input_empty();
+    CONTROLLER_COMMAND_PORT.write(CONTROLLER_CMD_RESET);
+}
+
+// Returns true if the scancode was a lock key
+unsafe fn handle_lock_key(scancode: u8) -> bool {
+    match scancode {
+        SCANCODE_CAPS_LOCK => CAPS_LOCK = !CAPS_LOCK,
+        SCANCODE_NUM_LOCK => NUM_LOCK = !NUM_LOCK,
+        SCANCODE_SCROLL_LOCK => SCROLL_LOCK = !SCROLL_LOCK,
+        _ => return false,
+    }
+    update_leds();
+    crate::status::refresh();
+    true
 }
 
 #[no_mangle]
 pub extern "C" fn kbhandler() {
     unsafe {
-        let status = inb(KEYBOARD_STATUS_PORT);
+        let status = KEYBOARD_STATUS_PORT.read();
         
-        if (status & 0x01) == 0 {
+        if (status & STATUS_OUTPUT_FULL) == 0 {
             pic::send_eoi(1);
             return;
         }
         
-        let scancode = inb(KEYBOARD_DATA_PORT);
+        let scancode = KEYBOARD_DATA_PORT.read();
         pic::send_eoi(1);
-        
-        if scancode < 128 && scancode != 0 {
-            let ascii = SCANCODE_TO_ASCII[scancode as usize];
-            
-            if ascii != 0 {
-                // Send to shell instead of printing directly
-                crate::nps::handle_input(ascii);
-            } else {
-                println!("{}", ascii);
+
+        // A late ACK that wait_ack() gave up on
+        if scancode == KEYBOARD_ACK {
+            return;
+        }
+
+        if !EXTENDED && handle_lock_key(scancode) {
+            return;
+        }
+
+        match decode(scancode) {
+            // Ctrl+C interrupts the foreground program instead of being typed
+            Some(Key::Ctrl(b'c')) if process::interrupt_foreground() => {}
+            Some(key) => push_key(key),
+            None => {}
+        }
+    }
+    // Let the shell run right away if it was waiting for this key
+    thread::preempt();
+}
+
+// Keys typed but not read yet, and the threads waiting for one
+const QUEUE_SIZE: usize = 64;
+static mut QUEUE: [Key; QUEUE_SIZE] = [Key::Char(0); QUEUE_SIZE];
+static mut QUEUE_HEAD: usize = 0;
+static mut QUEUE_LEN: usize = 0;
+static READERS: WaitQueue = WaitQueue::new();
+
+// Called from the interrupt; keys typed into a full queue are dropped
+unsafe fn push_key(key: Key) {
+    if QUEUE_LEN < QUEUE_SIZE {
+        QUEUE[(QUEUE_HEAD + QUEUE_LEN) % QUEUE_SIZE] = key;
+        QUEUE_LEN += 1;
+    }
+    READERS.wake_one();
+}
+
+// Wait for the next key press, or until `interrupted` says to give up
+// (checked whenever the thread wakes, e.g. for a signal)
+pub fn read_key_until(interrupted: impl Fn() -> bool) -> Option<Key> {
+    let interrupts = idt::disable_interrupts();
+    let key = unsafe {
+        loop {
+            if QUEUE_LEN > 0 {
+                let key = QUEUE[QUEUE_HEAD];
+                QUEUE_HEAD = (QUEUE_HEAD + 1) % QUEUE_SIZE;
+                QUEUE_LEN -= 1;
+                break Some(key);
             }
+            if interrupted() {
+                break None;
+            }
+            READERS.wait();
+        }
+    };
+    idt::restore_interrupts(interrupts);
+    key
+}
+
+pub fn read_key() -> Key {
+    read_key_until(|| false).unwrap()
+}
+
+// Next key as a terminal would send it; keys without a byte are skipped
+pub fn getchar_until(interrupted: impl Fn() -> bool) -> Option<u8> {
+    loop {
+        match read_key_until(&interrupted)? {
+            Key::Char(c) => return Some(c),
+            Key::Ctrl(c) => return Some(c & 0x1F),
+            _ => {}
         }
     }
 }
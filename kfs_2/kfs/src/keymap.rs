@@ -0,0 +1,67 @@
+// keymap.rs - Active keyboard layout, the keymap= option and the keymap command
+//
+// The tables themselves live in kfs_core::keymap.
+
+use kfs_core::keymap::{Keymap, DE, KEYMAPS};
+use crate::nps::{self, Builtin};
+
+static mut CURRENT: &Keymap = &DE;
+
+pub fn current() -> &'static Keymap {
+    unsafe { CURRENT }
+}
+
+pub fn find(name: &str) -> Option<&'static Keymap> {
+    KEYMAPS.iter().copied().find(|k| k.name == name)
+}
+
+pub fn select(name: &str) -> bool {
+    match find(name) {
+        Some(keymap) => {
+            unsafe {
+                CURRENT = keymap;
+            }
+            true
+        }
+        None => false,
+    }
+}
+
+fn setup_keymap(value: Option<&str>) -> bool {
+    value.is_some_and(select)
+}
+kernel_param!("keymap", "Keyboard layout (de, us)", setup_keymap);
+
+fn cmd_keymap(argv: &[&str]) {
+    let Some(name) = argv.get(1) else {
+        println!("Available keymaps:");
+        for keymap in KEYMAPS {
+            let marker = if keymap.name == current().name { '*' } else { ' ' };
+            println!(" {} {}", marker, keymap.name);
+        }
+        return;
+    };
+
+    if select(name) {
+        println!("Keymap switched to '{}'", name);
+        crate::status::refresh();
+    } else {
+        println!("keymap: unknown keymap '{}'", name);
+    }
+}
+
+fn complete_keymap(arg: usize, _prefix: &str, add: &mut dyn FnMut(&str)) {
+    if arg == 1 {
+        for keymap in KEYMAPS {
+            add(keymap.name);
+        }
+    }
+}
+
+static COMMANDS: [Builtin; 1] = [
+    Builtin { name: "keymap", usage: "keymap [name]", help: "List keymaps or switch to one", handler: cmd_keymap, complete: Some(complete_keymap) },
+];
+
+pub fn register_commands() {
+    nps::register_all(&COMMANDS);
+}
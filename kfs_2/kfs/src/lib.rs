@@ -2,32 +2,91 @@
 
 #![no_std]
 #![no_main]
+#![cfg_attr(test, feature(custom_test_frameworks))]
+#![cfg_attr(test, test_runner(crate::testing::runner))]
+#![cfg_attr(test, reexport_test_harness_main = "test_main")]
 
 use core::panic::PanicInfo;
 use crate::vga::Color;
-use crate::vga::writer;
 
 #[macro_use]
+mod console;
+#[macro_use]
+mod cmdline;
+mod hal;
+mod cpu;
+mod fpu;
 mod vga;
+mod serial;
 mod idt;
 mod pic;
 mod kb;
+mod keymap;
 mod exc;
 mod gdt;
+mod tss;
+mod user;
+mod syscall;
 mod nps;
+mod multiboot;
+mod font;
+mod gfx;
+mod fb;
+mod pit;
+mod rtc;
+mod status;
+mod symbols;
+mod mem;
+mod stack;
+mod thread;
+mod sync;
+mod paging;
+mod process;
+mod elf;
+#[cfg(test)]
+#[macro_use]
+mod testing;
 
+#[cfg(not(test))]
 #[panic_handler]
-fn panic(_info: &PanicInfo) -> ! {
-    writer().set_color(Color::Red, Color::Red);
-    writer().clear_screen();
+fn panic(info: &PanicInfo) -> ! {
+    console::set_color(Color::Red, Color::Red);
+    console::clear();
     println!("KERNEL PANIC!");
+    println!("{}", info);
     loop {}
 }
 
+// Test builds report the panic and go on with the next test
+#[cfg(test)]
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    testing::panicked(info)
+}
+
 fn init_and_print() {
+    // Use GRUB's linear framebuffer if we got one, VGA text mode otherwise.
+    // In text mode, save the BIOS font before anything else touches VGA memory.
+    if fb::init() {
+        if let Some(fb) = fb::writer().filter(|_| console::wanted(console::SCREEN)) {
+            console::register(fb);
+        }
+    } else {
+        font::init();
+        if console::wanted(console::SCREEN) {
+            console::register(vga::writer());
+        }
+    }
+
+    // Mirror everything to COM1 when a UART is present
+    if console::wanted(console::SERIAL) && serial::init() {
+        console::register(serial::port());
+    }
+
     // Clear screen
-    writer().clear_screen();
-    writer().set_cursor_visible(true);
+    console::clear();
+    console::set_cursor_visible(true);
+    status::init();
     
     
     // Welcome message
@@ -40,24 +99,44 @@ fn init_and_print() {
     
     idt::init();
     pic::remap();
+    pit::init(pit::hz());
     idt::enable_interrupts();
     gdt::init();
+    fpu::init();
+    paging::init();
+    thread::init();
     
     // Ready message
-    writer().printc("System initialized. Lets go!\n\n", Color::Green, Color::Black);
+    console::printc("System initialized. Lets go!\n\n", Color::Green, Color::Black);
     
     // Enable NPS shell
+    gdt::register_commands();
+    font::register_commands();
+    gfx::register_commands();
+    mem::register_commands();
+    stack::register_commands();
+    cpu::register_commands();
+    thread::register_commands();
+    sync::register_commands();
+    process::register_commands();
+    elf::register_commands();
+    user::register_commands();
+    syscall::register_commands();
+    cmdline::register_commands();
+    keymap::register_commands();
     nps::init();
 }
 
 #[no_mangle]
-pub extern "C" fn kernel_main() -> ! {
+pub extern "C" fn kernel_main(magic: u32, info_addr: u32) -> ! {
+    multiboot::init(magic, info_addr);
+    cmdline::init();
+    cpu::init();
     init_and_print();
-    
-    // Main loop
-    loop {
-        unsafe {
-            core::arch::asm!("hlt", options(nomem, nostack));
-        }
-    }
+
+    #[cfg(test)]
+    test_main();
+
+    // The boot flow is thread 0 now and stays on as the shell
+    nps::run()
 }
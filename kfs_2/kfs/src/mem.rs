@@ -0,0 +1,277 @@
+// mem.rs - Memory inspection commands: hexdump, peek, poke and memmap
+
+use crate::multiboot::{self, MemoryRegion};
+use crate::nps::{self, Builtin};
+use crate::paging;
+use crate::symbols;
+
+const DEFAULT_DUMP_LEN: u32 = 128;
+const BYTES_PER_LINE: u32 = 16;
+
+// Legacy video memory and BIOS ROMs between 640 KiB and 1 MiB
+const LOW_DEVICE_START: u32 = 0xA0000;
+const LOW_DEVICE_END: u32 = 0x100000;
+
+extern "C" {
+    static _kernel_start: u8;
+    static _kernel_end: u8;
+    static _text_start: u8;
+    static _text_end: u8;
+    static _rodata_start: u8;
+    static _rodata_end: u8;
+    static _data_start: u8;
+    static _data_end: u8;
+    static _bss_start: u8;
+    static _bss_end: u8;
+}
+
+fn contains(base: u64, length: u64, addr: u32, len: u32) -> bool {
+    base <= addr as u64 && addr as u64 + len as u64 <= base + length
+}
+
+// Can addr..addr+len be accessed? Everything but the user window is
+// identity mapped, so nothing else faults, but reads outside RAM and device
+// memory return garbage and writes get lost.
+pub fn is_accessible(addr: u32, len: u32) -> bool {
+    let Some(end) = addr.checked_add(len) else {
+        return false;
+    };
+    // The user window only holds what the current process mapped
+    if paging::enabled() && addr < paging::USER_TOP && end > paging::USER_BASE {
+        return paging::is_user_mapped(addr, len);
+    }
+    if contains(LOW_DEVICE_START as u64, (LOW_DEVICE_END - LOW_DEVICE_START) as u64, addr, len) {
+        return true;
+    }
+
+    let Some(info) = multiboot::info() else {
+        return true;    // Nothing known about the machine, trust the caller
+    };
+    if info.flags & multiboot::INFO_FRAMEBUFFER != 0 {
+        let size = info.framebuffer_pitch as u64 * info.framebuffer_height as u64;
+        if contains(info.framebuffer_addr, size, addr, len) {
+            return true;
+        }
+    }
+
+    if info.flags & multiboot::INFO_MEM_MAP != 0 {
+        let mut found = false;
+        multiboot::for_each_memory_region(|r| found |= contains(r.base, r.length, addr, len));
+        return found;
+    }
+    if info.flags & multiboot::INFO_MEMORY != 0 {
+        return contains(0, info.mem_lower as u64 * 1024, addr, len)
+            || contains(LOW_DEVICE_END as u64, info.mem_upper as u64 * 1024, addr, len);
+    }
+    true
+}
+
+fn print_symbol(addr: u32) {
+    if let Some(symbol) = symbols::lookup(addr) {
+        print!(" <{}+0x{:x}>", symbol.demangled(), addr - symbol.addr);
+    }
+}
+
+// Hex and ASCII, 16 bytes per line, with a label line wherever a symbol starts
+pub fn hexdump(addr: u32, len: u32) {
+    let end = addr.saturating_add(len);
+    if let Some(symbol) = symbols::lookup(addr).filter(|s| s.addr != addr) {
+        println!("<{}+0x{:x}>:", symbol.demangled(), addr - symbol.addr);
+    }
+
+    let mut line = addr;
+    while line < end {
+        let count = (end - line).min(BYTES_PER_LINE);
+        if let Some(symbol) = symbols::first_in(line, line + count) {
+            println!("<{}>:", symbol.demangled());
+        }
+
+        let bytes = unsafe { core::slice::from_raw_parts(line as *const u8, count as usize) };
+        print!("0x{:08x}  ", line);
+        for i in 0..BYTES_PER_LINE as usize {
+            match bytes.get(i) {
+                Some(byte) => print!("{:02x} ", byte),
+                None => print!("   "),
+            }
+        }
+        print!(" |");
+        for &byte in bytes {
+            let ch = if (0x20..0x7F).contains(&byte) { byte as char } else { '.' };
+            print!("{}", ch);
+        }
+        println!("|");
+
+        line = match line.checked_add(count) {
+            Some(next) => next,
+            None => break,
+        };
+    }
+}
+
+#[derive(Copy, Clone)]
+enum Width {
+    Byte,
+    Word,
+    Dword,
+}
+
+impl Width {
+    fn parse(arg: Option<&&str>) -> Option<Width> {
+        match arg.copied() {
+            None | Some("d") => Some(Width::Dword),
+            Some("w") => Some(Width::Word),
+            Some("b") => Some(Width::Byte),
+            _ => None,
+        }
+    }
+
+    fn bytes(self) -> u32 {
+        match self {
+            Width::Byte => 1,
+            Width::Word => 2,
+            Width::Dword => 4,
+        }
+    }
+}
+
+fn region_name(kind: u32) -> &'static str {
+    match kind {
+        multiboot::MEMORY_AVAILABLE => "available",
+        multiboot::MEMORY_ACPI_RECLAIMABLE => "ACPI reclaimable",
+        multiboot::MEMORY_ACPI_NVS => "ACPI NVS",
+        multiboot::MEMORY_BAD => "bad",
+        _ => "reserved",
+    }
+}
+
+fn print_section(name: &str, start: *const u8, end: *const u8) {
+    let (start, end) = (start as u32, end as u32);
+    println!("  {:<8} 0x{:08x}-0x{:08x} {:>6} KiB", name, start, end, (end - start).div_ceil(1024));
+}
+
+pub fn print_memmap() {
+    println!("=== Memory Map ===");
+    let mut any = false;
+    multiboot::for_each_memory_region(|r: MemoryRegion| {
+        any = true;
+        println!("  0x{:09x}-0x{:09x} {:>8} KiB  {}", r.base, r.base + r.length, r.length / 1024, region_name(r.kind));
+    });
+    if !any {
+        match multiboot::info() {
+            Some(info) if info.flags & multiboot::INFO_MEMORY != 0 => {
+                println!("  No BIOS map; lower {} KiB, upper {} KiB", { info.mem_lower }, { info.mem_upper });
+            }
+            _ => println!("  No memory information from the bootloader"),
+        }
+    }
+
+    println!("=== Kernel ===");
+    print_section("image", &raw const _kernel_start, &raw const _kernel_end);
+    print_section(".text", &raw const _text_start, &raw const _text_end);
+    print_section(".rodata", &raw const _rodata_start, &raw const _rodata_end);
+    print_section(".data", &raw const _data_start, &raw const _data_end);
+    print_section(".bss", &raw const _bss_start, &raw const _bss_end);
+
+    for i in 0..multiboot::module_count() {
+        if let Some(module) = multiboot::module(i) {
+            println!("  module   0x{:08x}-0x{:08x} {:>6} KiB  {}", module.start, module.end,
+                     (module.end - module.start).div_ceil(1024), module.name);
+        }
+    }
+}
+
+fn cmd_hexdump(argv: &[&str]) {
+    let addr = argv.get(1).and_then(|a| nps::parse_number(a));
+    let len = match argv.get(2) {
+        Some(arg) => nps::parse_number(arg),
+        None => Some(DEFAULT_DUMP_LEN),
+    };
+    let (Some(addr), Some(len)) = (addr, len) else {
+        println!("Usage: hexdump <addr> [len]");
+        return;
+    };
+    if !is_accessible(addr, len) {
+        println!("hexdump: 0x{:08x}+0x{:x} is not mapped", addr, len);
+        return;
+    }
+    hexdump(addr, len);
+}
+
+fn cmd_peek(argv: &[&str]) {
+    let addr = argv.get(1).and_then(|a| nps::parse_number(a));
+    let (Some(addr), Some(width)) = (addr, Width::parse(argv.get(2))) else {
+        println!("Usage: peek <addr> [b|w|d]");
+        return;
+    };
+    if !is_accessible(addr, width.bytes()) {
+        println!("peek: 0x{:08x} is not mapped", addr);
+        return;
+    }
+
+    unsafe {
+        match width {
+            Width::Byte => print!("0x{:08x}: 0x{:02x}", addr, (addr as *const u8).read_volatile()),
+            Width::Word => print!("0x{:08x}: 0x{:04x}", addr, (addr as *const u16).read_unaligned()),
+            Width::Dword => print!("0x{:08x}: 0x{:08x}", addr, (addr as *const u32).read_unaligned()),
+        }
+    }
+    print_symbol(addr);
+    println!();
+}
+
+fn cmd_poke(argv: &[&str]) {
+    let addr = argv.get(1).and_then(|a| nps::parse_number(a));
+    let value = argv.get(2).and_then(|a| nps::parse_number(a));
+    let (Some(addr), Some(value), Some(width)) = (addr, value, Width::parse(argv.get(3))) else {
+        println!("Usage: poke <addr> <value> [b|w|d]");
+        return;
+    };
+    if width.bytes() < 4 && value >> (width.bytes() * 8) != 0 {
+        println!("poke: 0x{:x} does not fit in {} byte(s)", value, width.bytes());
+        return;
+    }
+    if !is_accessible(addr, width.bytes()) {
+        println!("poke: 0x{:08x} is not mapped", addr);
+        return;
+    }
+
+    unsafe {
+        match width {
+            Width::Byte => (addr as *mut u8).write_volatile(value as u8),
+            Width::Word => (addr as *mut u16).write_unaligned(value as u16),
+            Width::Dword => (addr as *mut u32).write_unaligned(value),
+        }
+    }
+}
+
+fn cmd_memmap(_argv: &[&str]) {
+    print_memmap();
+    if paging::enabled() {
+        let free = paging::free_frames();
+        println!("\nFree page frames: {} ({} KiB)", free, free * 4);
+    }
+}
+
+// The width is the last argument of both peek and poke
+fn complete_peek(arg: usize, _prefix: &str, add: &mut dyn FnMut(&str)) {
+    if arg == 2 {
+        add("b");
+        add("w");
+        add("d");
+    }
+}
+
+fn complete_poke(arg: usize, prefix: &str, add: &mut dyn FnMut(&str)) {
+    complete_peek(arg - 1, prefix, add);
+}
+
+static COMMANDS: [Builtin; 4] = [
+    Builtin { name: "hexdump", usage: "hexdump <addr> [len]", help: "Dump memory as hex and ASCII", handler: cmd_hexdump, complete: None },
+    Builtin { name: "peek", usage: "peek <addr> [b|w|d]", help: "Read a byte, word or dword", handler: cmd_peek, complete: Some(complete_peek) },
+    Builtin { name: "poke", usage: "poke <addr> <value> [b|w|d]", help: "Write a byte, word or dword", handler: cmd_poke, complete: Some(complete_poke) },
+    Builtin { name: "memmap", usage: "memmap", help: "Show the memory map and kernel sections", handler: cmd_memmap, complete: None },
+];
+
+pub fn register_commands() {
+    nps::register_all(&COMMANDS);
+}
@@ -0,0 +1,211 @@
+// multiboot.rs - Multiboot (v1) information structure handed over by GRUB
+
+pub const BOOTLOADER_MAGIC: u32 = 0x2BADB002;
+
+// Flag bits telling which fields of the info structure are valid
+pub const INFO_MEMORY: u32 = 1 << 0;
+pub const INFO_CMDLINE: u32 = 1 << 2;
+pub const INFO_MODS: u32 = 1 << 3;
+pub const INFO_ELF_SHDR: u32 = 1 << 5;
+pub const INFO_MEM_MAP: u32 = 1 << 6;
+pub const INFO_FRAMEBUFFER: u32 = 1 << 12;
+
+// Multiboot information structure, layout as in the spec (section 3.3)
+#[repr(C, packed)]
+#[derive(Copy, Clone)]
+pub struct MultibootInfo {
+    pub flags: u32,
+    pub mem_lower: u32,
+    pub mem_upper: u32,
+    pub boot_device: u32,
+    pub cmdline: u32,
+    pub mods_count: u32,
+    pub mods_addr: u32,
+    pub syms: [u32; 4],         // ELF: section header count, entry size, address, string table index
+    pub mmap_length: u32,
+    pub mmap_addr: u32,
+    pub drives_length: u32,
+    pub drives_addr: u32,
+    pub config_table: u32,
+    pub boot_loader_name: u32,
+    pub apm_table: u32,
+    pub vbe_control_info: u32,
+    pub vbe_mode_info: u32,
+    pub vbe_mode: u16,
+    pub vbe_interface_seg: u16,
+    pub vbe_interface_off: u16,
+    pub vbe_interface_len: u16,
+    pub framebuffer_addr: u64,
+    pub framebuffer_pitch: u32,
+    pub framebuffer_width: u32,
+    pub framebuffer_height: u32,
+    pub framebuffer_bpp: u8,
+    pub framebuffer_type: u8,
+    pub color_info: [u8; 6],    // RGB: red/green/blue field position and mask size
+}
+
+// One entry of the module list (mods_addr points to an array of these)
+#[repr(C, packed)]
+#[derive(Copy, Clone)]
+struct ModuleEntry {
+    mod_start: u32,
+    mod_end: u32,
+    string: u32,
+    reserved: u32,
+}
+
+// A boot module loaded by GRUB (`module /boot/foo.psf foo` in grub.cfg)
+#[derive(Copy, Clone)]
+pub struct Module {
+    pub start: u32,
+    pub end: u32,
+    pub name: &'static str,
+}
+
+impl Module {
+    pub fn data(&self) -> &'static [u8] {
+        unsafe {
+            core::slice::from_raw_parts(self.start as *const u8, (self.end - self.start) as usize)
+        }
+    }
+}
+
+// Raw memory map entry; `size` doesn't count itself, so entries are size + 4 bytes apart
+#[repr(C, packed)]
+#[derive(Copy, Clone)]
+struct MemoryMapEntry {
+    size: u32,
+    base: u64,
+    length: u64,
+    kind: u32,
+}
+
+// Memory map region types
+pub const MEMORY_AVAILABLE: u32 = 1;
+pub const MEMORY_ACPI_RECLAIMABLE: u32 = 3;
+pub const MEMORY_ACPI_NVS: u32 = 4;
+pub const MEMORY_BAD: u32 = 5;
+
+#[derive(Copy, Clone)]
+pub struct MemoryRegion {
+    pub base: u64,
+    pub length: u64,
+    pub kind: u32,
+}
+
+static mut INFO: Option<&'static MultibootInfo> = None;
+
+// Remember the info structure; called first thing from kernel_main
+pub fn init(magic: u32, info_addr: u32) {
+    if magic != BOOTLOADER_MAGIC || info_addr == 0 {
+        return;
+    }
+    unsafe {
+        INFO = Some(&*(info_addr as *const MultibootInfo));
+    }
+}
+
+pub fn info() -> Option<&'static MultibootInfo> {
+    unsafe { *core::ptr::addr_of!(INFO) }
+}
+
+// Read a NUL-terminated string left in memory by the bootloader
+pub fn c_str(addr: u32) -> &'static str {
+    if addr == 0 {
+        return "";
+    }
+    unsafe {
+        let ptr = addr as *const u8;
+        let mut len = 0;
+        while *ptr.add(len) != 0 {
+            len += 1;
+        }
+        core::str::from_utf8(core::slice::from_raw_parts(ptr, len)).unwrap_or("")
+    }
+}
+
+pub fn module_count() -> usize {
+    match info() {
+        Some(info) if info.flags & INFO_MODS != 0 => info.mods_count as usize,
+        _ => 0,
+    }
+}
+
+pub fn module(index: usize) -> Option<Module> {
+    let info = info()?;
+    if index >= module_count() {
+        return None;
+    }
+    unsafe {
+        let entry = *(info.mods_addr as *const ModuleEntry).add(index);
+        Some(Module {
+            start: entry.mod_start,
+            end: entry.mod_end,
+            name: c_str(entry.string),
+        })
+    }
+}
+
+// Module by its command line name, or the last component of a path to it
+pub fn find_module(name: &str) -> Option<Module> {
+    let wanted = name.rsplit('/').next().unwrap_or(name);
+    (0..module_count()).filter_map(module).find(|m| m.name == name || m.name.rsplit('/').next() == Some(wanted))
+}
+
+// Call `f` with (start, end) of everything GRUB left in memory for us: this
+// structure, the command line, modules, the memory map and the ELF sections
+// the symbol table lives in. The frame allocator must stay away from them.
+pub fn for_each_boot_range(mut f: impl FnMut(u32, u32)) {
+    let Some(info) = info() else { return };
+    let start = info as *const MultibootInfo as u32;
+    f(start, start + core::mem::size_of::<MultibootInfo>() as u32);
+    let string = |addr: u32| (addr, addr + c_str(addr).len() as u32 + 1);
+
+    if info.flags & INFO_CMDLINE != 0 {
+        let (start, end) = string(info.cmdline);
+        f(start, end);
+    }
+    if info.flags & INFO_MODS != 0 {
+        f(info.mods_addr, info.mods_addr + info.mods_count * core::mem::size_of::<ModuleEntry>() as u32);
+        for index in 0..module_count() {
+            let entry = unsafe { *(info.mods_addr as *const ModuleEntry).add(index) };
+            f(entry.mod_start, entry.mod_end);
+            let (start, end) = string(entry.string);
+            f(start, end);
+        }
+    }
+    if info.flags & INFO_MEM_MAP != 0 {
+        f(info.mmap_addr, info.mmap_addr + info.mmap_length);
+    }
+    if info.flags & INFO_ELF_SHDR != 0 {
+        // Section headers: sh_addr at offset 12, sh_size at 20
+        let [count, entry_size, addr, _] = info.syms;
+        f(addr, addr + count * entry_size);
+        for index in 0..count {
+            let header = (addr + index * entry_size) as *const u32;
+            let (section, size) = unsafe { (*header.add(3), *header.add(5)) };
+            if section != 0 {
+                f(section, section + size);
+            }
+        }
+    }
+}
+
+// Walk the BIOS memory map (e820) GRUB passed along, if there is one
+pub fn for_each_memory_region(mut f: impl FnMut(MemoryRegion)) {
+    let Some(info) = info() else { return };
+    if info.flags & INFO_MEM_MAP == 0 {
+        return;
+    }
+    let end = info.mmap_addr + info.mmap_length;
+    let mut addr = info.mmap_addr;
+    while addr < end {
+        let entry = unsafe { *(addr as *const MemoryMapEntry) };
+        f(MemoryRegion {
+            base: entry.base,
+            length: entry.length,
+            kind: entry.kind,
+        });
+        addr += entry.size + 4;
+    }
+}
@@ -1,178 +1,723 @@
-use crate::gdt;
-use crate::vga;
+// nps.rs - NPS (Not a POSIX Shell): line input, tokenizer and command registry
+
+pub use kfs_core::shell::{parse_number, tokenize, MAX_ARGS};
+use crate::console;
+use crate::kb::Key;
 use crate::vga::Color;
 
-const MAX_COMMAND_LEN: usize = 64;
+const MAX_COMMAND_LEN: usize = 256;
+const HISTORY_SIZE: usize = 32;
+const PROMPT: &str = "> ";
+const MAX_COMMANDS: usize = 32;
+
+// A shell command. Modules implement this (usually through `Builtin`)
+// and hand a static instance to `register`.
+pub trait Command {
+    fn name(&self) -> &'static str;
+    // One-line synopsis shown by `help`, e.g. "stack [count]"
+    fn usage(&self) -> &'static str;
+    fn help(&self) -> &'static str;
+    // argv[0] is the command name itself
+    fn run(&self, argv: &[&str]);
+    // Tab completion for argument `arg` (1 = first argument), see `Completer`
+    fn complete(&self, _arg: usize, _prefix: &str, _add: &mut dyn FnMut(&str)) {}
+}
+
+// Reports every candidate for the word being completed through `add`.
+// The shell drops candidates that don't start with `prefix`, so a completer
+// may only use it to narrow down expensive lookups.
+pub type Completer = fn(arg: usize, prefix: &str, add: &mut dyn FnMut(&str));
+
+// The common case: a command backed by a plain function
+pub struct Builtin {
+    pub name: &'static str,
+    pub usage: &'static str,
+    pub help: &'static str,
+    pub handler: fn(&[&str]),
+    pub complete: Option<Completer>,
+}
+
+impl Command for Builtin {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn usage(&self) -> &'static str {
+        self.usage
+    }
+
+    fn help(&self) -> &'static str {
+        self.help
+    }
+
+    fn run(&self, argv: &[&str]) {
+        (self.handler)(argv)
+    }
+
+    fn complete(&self, arg: usize, prefix: &str, add: &mut dyn FnMut(&str)) {
+        if let Some(complete) = self.complete {
+            complete(arg, prefix, add);
+        }
+    }
+}
+
+static mut COMMANDS: [Option<&'static dyn Command>; MAX_COMMANDS] = [None; MAX_COMMANDS];
+
+fn commands() -> &'static mut [Option<&'static dyn Command>; MAX_COMMANDS] {
+    unsafe { &mut *core::ptr::addr_of_mut!(COMMANDS) }
+}
+
+// Add a command to the shell. A later registration with the same name replaces the earlier one.
+pub fn register(command: &'static dyn Command) {
+    for slot in commands().iter_mut() {
+        match slot {
+            Some(existing) if existing.name() == command.name() => {
+                *slot = Some(command);
+                return;
+            }
+            None => {
+                *slot = Some(command);
+                return;
+            }
+            _ => {}
+        }
+    }
+    println!("nps: command table full, '{}' not registered", command.name());
+}
+
+pub fn register_all(commands: &'static [Builtin]) {
+    for command in commands {
+        register(command);
+    }
+}
+
+pub fn for_each_command(mut f: impl FnMut(&'static dyn Command)) {
+    for command in commands().iter().flatten() {
+        f(*command);
+    }
+}
+
+pub fn find_command(name: &str) -> Option<&'static dyn Command> {
+    commands().iter().flatten().find(|c| c.name() == name).copied()
+}
+
+// One line of input (also used for history entries and the search query)
+#[derive(Copy, Clone)]
+struct Line {
+    bytes: [u8; MAX_COMMAND_LEN],
+    len: usize,
+}
+
+impl Line {
+    const fn empty() -> Line {
+        Line {
+            bytes: [0; MAX_COMMAND_LEN],
+            len: 0,
+        }
+    }
+
+    fn as_str(&self) -> &str {
+        core::str::from_utf8(&self.bytes[..self.len]).unwrap_or("")
+    }
+
+    fn insert(&mut self, at: usize, ch: u8) -> bool {
+        if self.len >= MAX_COMMAND_LEN || at > self.len {
+            return false;
+        }
+        self.bytes.copy_within(at..self.len, at + 1);
+        self.bytes[at] = ch;
+        self.len += 1;
+        true
+    }
+
+    // Remove bytes start..end
+    fn remove(&mut self, start: usize, end: usize) {
+        let end = end.min(self.len);
+        if start >= end {
+            return;
+        }
+        self.bytes.copy_within(end..self.len, start);
+        self.len -= end - start;
+    }
+
+    fn contains(&self, needle: &Line) -> bool {
+        needle.len == 0
+            || self.bytes[..self.len].windows(needle.len).any(|w| w == &needle.bytes[..needle.len])
+    }
+}
+
+// Command history for this session, oldest entries are dropped when full
+struct History {
+    entries: [Line; HISTORY_SIZE],
+    count: usize,
+    next: usize,
+}
+
+impl History {
+    const fn new() -> History {
+        History {
+            entries: [Line::empty(); HISTORY_SIZE],
+            count: 0,
+            next: 0,
+        }
+    }
+
+    fn push(&mut self, line: &Line) {
+        // Skip empty lines and repeats of the previous command
+        if line.as_str().trim().is_empty() || self.get(0).is_some_and(|last| last.as_str() == line.as_str()) {
+            return;
+        }
+        self.entries[self.next] = *line;
+        self.next = (self.next + 1) % HISTORY_SIZE;
+        self.count = (self.count + 1).min(HISTORY_SIZE);
+    }
+
+    // age 0 is the most recent command
+    fn get(&self, age: usize) -> Option<&Line> {
+        if age >= self.count {
+            return None;
+        }
+        Some(&self.entries[(self.next + HISTORY_SIZE - 1 - age) % HISTORY_SIZE])
+    }
+
+    // Most recent entry at or older than `from` containing `query`
+    fn search(&self, query: &Line, from: usize) -> Option<usize> {
+        (from..self.count).find(|&age| self.get(age).is_some_and(|entry| entry.contains(query)))
+    }
+}
+
+// Ctrl+R reverse incremental search state
+struct Search {
+    query: Line,
+    found: Option<usize>,   // History age of the current match
+}
 
 pub struct NPShell {
-    buffer: [u8; MAX_COMMAND_LEN],
-    pos: usize,
+    line: Line,
+    pos: usize,                 // Cursor position within the line
+    insert: bool,               // Insert (true) or overwrite mode
+    origin: (usize, usize),     // Screen position where the prompt starts
+    drawn: usize,               // Cells drawn by the last redraw (prompt + line)
+    history: History,
+    browsing: Option<usize>,    // History age shown by Up/Down
+    saved: Line,                // Line being edited before browsing/searching started
+    search: Option<Search>,
+    last_tab: bool,             // Previous key was Tab (a second one lists candidates)
 }
 
 impl NPShell {
     pub const fn new() -> NPShell {
         NPShell {
-            buffer: [0; MAX_COMMAND_LEN],
+            line: Line::empty(),
             pos: 0,
+            insert: true,
+            origin: (0, 0),
+            drawn: 0,
+            history: History::new(),
+            browsing: None,
+            saved: Line::empty(),
+            search: None,
+            last_tab: false,
         }
     }
 
-    pub fn show_prompt(&self) {
-        vga::writer().printc( "> ", Color::Green, Color::Black);
+    pub fn show_prompt(&mut self) {
+        self.origin = console::cursor_position();
+        self.drawn = 0;
+        self.redraw();
     }
 
-    pub fn handle_char(&mut self, ch: u8) {
-        match ch {
-            b'\n' => {
-                // Execute command
-                self.execute();
-                self.clear();
-                println!();
-                self.show_prompt();
+    fn prompt(&self) -> usize {
+        match &self.search {
+            Some(search) => {
+                let label = if search.found.is_some() || search.query.len == 0 {
+                    "(reverse-i-search)`"
+                } else {
+                    "(failed reverse-i-search)`"
+                };
+                console::printc(label, Color::Yellow, Color::Black);
+                console::write_string(search.query.as_str());
+                console::write_string("': ");
+                label.len() + search.query.len + 3
+            }
+            None => {
+                console::printc(PROMPT, Color::Green, Color::Black);
+                PROMPT.len()
+            }
+        }
+    }
+
+    // Repaint prompt and line from the origin, then put the cursor back.
+    // Handles lines that wrap over several rows and the screen scrolling under us.
+    fn redraw(&mut self) {
+        let (_, cols) = console::size();
+        console::set_cursor_position(self.origin.0, self.origin.1);
+
+        let prompt_len = self.prompt();
+        console::write_string(self.line.as_str());
+
+        // Blank what's left of a previous, longer line, plus one extra cell
+        // so the row holding the cursor exists even when the line ends a row
+        let drawn = prompt_len + self.line.len;
+        let painted = drawn.max(self.drawn) + 1;
+        for _ in drawn..painted {
+            console::write_string(" ");
+        }
+        self.drawn = drawn;
+
+        // If writing scrolled the screen, the prompt moved up by as many rows
+        let expected_row = self.origin.0 + (self.origin.1 + painted - 1) / cols;
+        let (actual_row, _) = console::cursor_position();
+        self.origin.0 = self.origin.0.saturating_sub(expected_row.saturating_sub(actual_row));
+
+        let offset = self.origin.1 + prompt_len + self.pos;
+        console::set_cursor_position(self.origin.0 + offset / cols, offset % cols);
+    }
+
+    fn set_line(&mut self, line: Line) {
+        self.line = line;
+        self.pos = line.len;
+    }
+
+    fn insert_char(&mut self, ch: u8) {
+        if !self.insert && self.pos < self.line.len {
+            self.line.bytes[self.pos] = ch;
+            self.pos += 1;
+        } else if self.line.insert(self.pos, ch) {
+            self.pos += 1;
+        }
+    }
+
+    // Start of the word before the cursor (Ctrl+W)
+    fn word_start(&self) -> usize {
+        let bytes = &self.line.bytes[..self.pos];
+        let end = bytes.iter().rposition(|&b| b != b' ').map_or(0, |i| i + 1);
+        bytes[..end].iter().rposition(|&b| b == b' ').map_or(0, |i| i + 1)
+    }
+
+    fn history_up(&mut self) {
+        let age = match self.browsing {
+            Some(age) => age + 1,
+            None => 0,
+        };
+        if let Some(entry) = self.history.get(age) {
+            if self.browsing.is_none() {
+                self.saved = self.line;
+            }
+            self.browsing = Some(age);
+            self.set_line(*entry);
+        }
+    }
+
+    fn history_down(&mut self) {
+        match self.browsing {
+            Some(0) => {
+                self.browsing = None;
+                self.set_line(self.saved);
+            }
+            Some(age) => {
+                if let Some(entry) = self.history.get(age - 1) {
+                    self.browsing = Some(age - 1);
+                    self.set_line(*entry);
+                }
+            }
+            None => {}
+        }
+    }
+
+    fn search_from(&mut self, from: usize) {
+        let Some(search) = &mut self.search else { return };
+        search.found = self.history.search(&search.query, from);
+        if let Some(entry) = search.found.and_then(|age| self.history.get(age)) {
+            self.line = *entry;
+            self.pos = entry.len;
+        }
+    }
+
+    // Keys while Ctrl+R is active. Returns false if the key ends the search
+    // and should then be handled as a normal editing key.
+    fn handle_search_key(&mut self, key: Key) -> bool {
+        let Some(search) = &mut self.search else { return false };
+        match key {
+            Key::Char(0x08) => {
+                if search.query.len > 0 {
+                    search.query.len -= 1;
+                }
+                self.search_from(0);
+            }
+            Key::Char(ch @ 0x20..=0x7E) => {
+                search.query.insert(search.query.len, ch);
+                let from = search.found.unwrap_or(0);
+                self.search_from(from);
+            }
+            Key::Ctrl(b'r') => {
+                let from = search.found.map_or(0, |age| age + 1);
+                self.search_from(from);
+            }
+            Key::Ctrl(b'g') | Key::Ctrl(b'c') | Key::Char(0x1B) => {
+                self.search = None;
+                self.set_line(self.saved);
+            }
+            _ => {
+                // Keep the match on the line and process the key normally
+                self.search = None;
+                self.redraw();
+                return false;
+            }
+        }
+        self.redraw();
+        true
+    }
+
+    pub fn handle_key(&mut self, key: Key) {
+        let double_tab = self.last_tab && key == Key::Char(b'\t');
+        self.last_tab = key == Key::Char(b'\t');
+
+        if self.search.is_some() && self.handle_search_key(key) {
+            return;
+        }
+
+        match key {
+            Key::Char(b'\n') => {
+                self.accept();
+                return;
             }
-            0x08 => {
+            Key::Char(0x08) => {
                 // Backspace
                 if self.pos > 0 {
+                    self.line.remove(self.pos - 1, self.pos);
                     self.pos -= 1;
-                    crate::vga::writer().backspace();
                 }
             }
-            0x20..=0x7E => {
-                // Printable character
-                if self.pos < MAX_COMMAND_LEN - 1 {
-                    self.buffer[self.pos] = ch;
-                    self.pos += 1;
-                    crate::print!("{}", ch as char);
+            Key::Char(b'\t') => {
+                if !self.complete(double_tab) {
+                    return;
                 }
             }
-            _ => {}
+            Key::Char(ch @ 0x20..=0x7E) => self.insert_char(ch),
+            Key::Left | Key::Ctrl(b'b') => self.pos = self.pos.saturating_sub(1),
+            Key::Right | Key::Ctrl(b'f') => self.pos = (self.pos + 1).min(self.line.len),
+            Key::Home | Key::Ctrl(b'a') => self.pos = 0,
+            Key::End | Key::Ctrl(b'e') => self.pos = self.line.len,
+            Key::Delete | Key::Ctrl(b'd') => self.line.remove(self.pos, self.pos + 1),
+            Key::Insert => self.insert = !self.insert,
+            Key::Up | Key::Ctrl(b'p') => self.history_up(),
+            Key::Down | Key::Ctrl(b'n') => self.history_down(),
+            Key::Ctrl(b'k') => self.line.len = self.pos,
+            Key::Ctrl(b'u') => {
+                self.line.remove(0, self.pos);
+                self.pos = 0;
+            }
+            Key::Ctrl(b'w') => {
+                let start = self.word_start();
+                self.line.remove(start, self.pos);
+                self.pos = start;
+            }
+            Key::Ctrl(b'r') => {
+                self.saved = self.line;
+                self.search = Some(Search { query: Line::empty(), found: None });
+            }
+            Key::Ctrl(b'c') => {
+                // Abandon the line
+                self.pos = self.line.len;
+                self.redraw();
+                println!("^C");
+                self.reset();
+                self.show_prompt();
+                return;
+            }
+            Key::Ctrl(b'l') => {
+                console::clear();
+                self.show_prompt();
+                return;
+            }
+            _ => return,
         }
+        self.redraw();
     }
 
-    fn clear(&mut self) {
-        self.buffer = [0; MAX_COMMAND_LEN];
-        self.pos = 0;
-    }
+    // Call `f` with each completion of the word from `start` to the cursor:
+    // command names for the first word, the command's completer after that
+    fn for_each_candidate(&self, start: usize, mut f: impl FnMut(&str)) {
+        let line = self.line.as_str();
+        let prefix = &line[start..self.pos];
+        let mut filter = |candidate: &str| {
+            if candidate.starts_with(prefix) {
+                f(candidate);
+            }
+        };
 
-    fn execute(&self) {
-        if self.pos == 0 {
-            return;
+        let mut words = line[..start].split_whitespace();
+        match words.next() {
+            None => for_each_command(|command| filter(command.name())),
+            Some(name) => {
+                if let Some(command) = find_command(name) {
+                    command.complete(1 + words.count(), prefix, &mut filter);
+                }
+            }
         }
+    }
 
-        // Get command as string
-        let cmd = core::str::from_utf8(&self.buffer[..self.pos]).unwrap_or("");
+    // Tab: extend the word under the cursor as far as all candidates agree.
+    // Returns true if the line changed and needs a redraw.
+    fn complete(&mut self, list: bool) -> bool {
+        let start = self.line.bytes[..self.pos].iter().rposition(|&b| b == b' ').map_or(0, |i| i + 1);
 
-        println!(); // Newline after command
+        let mut count = 0;
+        let mut common = Line::empty();
+        self.for_each_candidate(start, |candidate| {
+            if count == 0 {
+                for &b in candidate.as_bytes() {
+                    common.insert(common.len, b);
+                }
+            } else {
+                common.len = common.bytes[..common.len]
+                    .iter()
+                    .zip(candidate.bytes())
+                    .take_while(|(a, b)| **a == *b)
+                    .count();
+            }
+            count += 1;
+        });
 
-        match cmd.trim() {
-            "help" => self.cmd_help(),
-            "stack" => self.cmd_stack(),
-            "gdt" => self.cmd_gdt(),
-            "clear" => self.cmd_clear(),
-            "about" => self.cmd_about(),
-            "reboot" => self.cmd_reboot(),
-            "halt" => self.cmd_halt(),
-            "42" => self.cmd_42(),
-            "" => {},
-            _ => println!("Unknown command: '{}'. Type 'help' for commands.", cmd),
+        let typed = self.pos - start;
+        if count == 0 || (count > 1 && common.len <= typed && !list) {
+            return false;
+        }
+        if count > 1 && common.len <= typed {
+            self.list_candidates(start);
+            return false;
         }
-    }
 
-    fn cmd_help(&self) {
-        println!("Available commands:");
-        println!("  help   - Show this help message");
-        println!("  stack  - Print kernel stack information");
-        println!("  gdt    - Print GDT information");
-        println!("  42     - Print the mandatory 42");
-        println!("  clear  - Clear the screen");
-        println!("  about  - About this kernel");
-        println!("  halt   - Halt the CPU");
-        println!("  reboot - Reboot the system");
+        for &b in &common.bytes[typed..common.len] {
+            if self.line.insert(self.pos, b) {
+                self.pos += 1;
+            }
+        }
+        if count == 1 && self.line.bytes[..self.line.len].get(self.pos) != Some(&b' ') && self.line.insert(self.pos, b' ') {
+            self.pos += 1;
+        }
+        true
     }
 
-    fn cmd_stack(&self) {
-        gdt::print_stack();
+    // Double Tab: print all candidates in columns below the line, then a fresh prompt
+    fn list_candidates(&mut self, start: usize) {
+        let mut width = 0;
+        self.for_each_candidate(start, |candidate| width = width.max(candidate.len() + 2));
+        let per_row = (console::size().1 / width.max(1)).max(1);
+
+        let pos = self.pos;
+        self.pos = self.line.len;
+        self.redraw();
+        println!();
+
+        let mut column = 0;
+        self.for_each_candidate(start, |candidate| {
+            print!("{:<width$}", candidate, width = width);
+            column += 1;
+            if column == per_row {
+                println!();
+                column = 0;
+            }
+        });
+        if column != 0 {
+            println!();
+        }
+
+        self.pos = pos;
+        self.show_prompt();
     }
 
-    fn cmd_gdt(&self) {
-        gdt::print_gdt();
+    fn reset(&mut self) {
+        self.line = Line::empty();
+        self.pos = 0;
+        self.browsing = None;
+        self.search = None;
     }
 
-    fn cmd_clear(&self) {
-        crate::vga::writer().clear_screen();
-        crate::vga::writer().set_color(Color::LightBlue, Color::Black);
-        println!("NPS - Not a POSIX Shell - Type 'help' for commands");
-        crate::vga::writer().set_color(Color::White, Color::Black);
+    // Run `text` as if it had been typed at the prompt
+    pub fn type_line(&mut self, text: &str) {
+        for &b in text.as_bytes() {
+            self.line.insert(self.line.len, b);
+        }
+        self.pos = self.line.len;
+        self.accept();
     }
 
-    fn cmd_about(&self) {
-        println!("KFS_2 - Kernel From Scratch");
-        println!("===========================");
-        println!("");
-        println!("A bare-metal i386 kernel written in Rust");
-        println!("By Michael Naysmith");
-        println!("Features:");
-        println!("  - Custom GDT implementation");
-        println!("  - Interrupt handling (IDT + PIC)");
-        println!("  - Keyboard input");
-        println!("  - VGA text mode with colors");
-        println!("  - NPS Shell");
+    // Enter: run the line, remember it, show a fresh prompt
+    fn accept(&mut self) {
+        self.pos = self.line.len;
+        self.redraw();
+
+        let line = self.line;
+        self.history.push(&line);
+        self.reset();
+
+        if line.len > 0 {
+            println!(); // Newline after command
+            run_line(line.as_str());
+        }
+        println!();
+        self.show_prompt();
     }
+}
+
+// Tokenize and dispatch one command line
+pub fn run_line(line: &str) {
+    let mut scratch = [0u8; MAX_COMMAND_LEN];
+    let mut argv = [""; MAX_ARGS];
 
-    fn cmd_halt(&self) {
-        println!("Halting CPU...");
-        unsafe {
-            core::arch::asm!("cli; hlt", options(noreturn));
+    let argc = match tokenize(line, &mut scratch, &mut argv) {
+        Ok(argc) => argc,
+        Err(err) => {
+            println!("nps: {}", err);
+            return;
         }
+    };
+    if argc == 0 {
+        return;
     }
 
-    fn cmd_reboot(&self) {
-        println!("Rebooting...");
-        unsafe {
-            // Pulse the CPU reset line via keyboard controller
-            let mut port: u8;
-            loop {
-                core::arch::asm!("in al, dx", out("al") port, in("dx") 0x64u16);
-                if (port & 0x02) == 0 {
-                    break;
-                }
+    match find_command(argv[0]) {
+        Some(command) => command.run(&argv[..argc]),
+        None => println!("Unknown command: '{}'. Type 'help' for commands.", argv[0]),
+    }
+}
+
+fn cmd_help(argv: &[&str]) {
+    if let Some(name) = argv.get(1) {
+        match find_command(name) {
+            Some(command) => {
+                println!("Usage: {}", command.usage());
+                println!("  {}", command.help());
             }
-            core::arch::asm!("out dx, al", in("dx") 0x64u16, in("al") 0xFEu8);
-            
-            // If that didn't work, triple fault
-            core::arch::asm!("cli; hlt", options(noreturn));
+            None => println!("help: no such command: '{}'", name),
         }
+        return;
+    }
+
+    // Sorted by name so the listing doesn't depend on registration order
+    let mut sorted: [Option<&'static dyn Command>; MAX_COMMANDS] = [None; MAX_COMMANDS];
+    let mut count = 0;
+    for_each_command(|command| {
+        sorted[count] = Some(command);
+        count += 1;
+    });
+    sorted[..count].sort_unstable_by_key(|c| c.map_or("", |c| c.name()));
+
+    println!("Available commands:");
+    for command in sorted[..count].iter().flatten() {
+        println!("  {:<16} - {}", command.usage(), command.help());
+    }
+}
+
+fn cmd_clear(_argv: &[&str]) {
+    console::clear();
+    console::set_color(Color::LightBlue, Color::Black);
+    println!("NPS - Not a POSIX Shell - Type 'help' for commands");
+    console::set_color(Color::White, Color::Black);
+}
+
+fn cmd_about(_argv: &[&str]) {
+    println!("KFS_2 - Kernel From Scratch");
+    println!("===========================");
+    println!("");
+    println!("A bare-metal i386 kernel written in Rust");
+    println!("By Michael Naysmith");
+    println!("Features:");
+    println!("  - Custom GDT implementation");
+    println!("  - Interrupt handling (IDT + PIC)");
+    println!("  - Keyboard input");
+    println!("  - VGA text mode with colors");
+    println!("  - NPS Shell");
+}
+
+fn cmd_halt(_argv: &[&str]) {
+    println!("Halting CPU...");
+    unsafe {
+        core::arch::asm!("cli; hlt", options(noreturn));
+    }
+}
+
+fn cmd_reboot(_argv: &[&str]) {
+    println!("Rebooting...");
+    crate::kb::reset_cpu();
+    // If that didn't work, stop here
+    unsafe {
+        core::arch::asm!("cli; hlt", options(noreturn));
     }
+}
 
-    fn cmd_42(&self) {
-        println!("    === Printing 42 ===");
-        println!("");
-        println!("        :::      ::::::::");
-        println!("      :+:      :+:    :+:");
-        println!("    +:+ +:+         +:+");
-        println!("  +#+  +:+       +#+");
-        println!("+#+#+#+#+#+   +#+");
-        println!("     #+#    #+#");
-        println!("    ###   ########.fr");
+fn complete_command(arg: usize, _prefix: &str, add: &mut dyn FnMut(&str)) {
+    if arg == 1 {
+        for_each_command(|command| add(command.name()));
     }
 }
 
+fn cmd_42(_argv: &[&str]) {
+    println!("    === Printing 42 ===");
+    println!("");
+    println!("        :::      ::::::::");
+    println!("      :+:      :+:    :+:");
+    println!("    +:+ +:+         +:+");
+    println!("  +#+  +:+       +#+");
+    println!("+#+#+#+#+#+   +#+");
+    println!("     #+#    #+#");
+    println!("    ###   ########.fr");
+}
+
+static COMMANDS_BUILTIN: [Builtin; 6] = [
+    Builtin { name: "help", usage: "help [command]", help: "Show this help message", handler: cmd_help, complete: Some(complete_command) },
+    Builtin { name: "42", usage: "42", help: "Print the mandatory 42", handler: cmd_42, complete: None },
+    Builtin { name: "clear", usage: "clear", help: "Clear the screen", handler: cmd_clear, complete: None },
+    Builtin { name: "about", usage: "about", help: "About this kernel", handler: cmd_about, complete: None },
+    Builtin { name: "halt", usage: "halt", help: "Halt the CPU", handler: cmd_halt, complete: None },
+    Builtin { name: "reboot", usage: "reboot", help: "Reboot the system", handler: cmd_reboot, complete: None },
+];
+
 // Global shell instance
 static mut NPSHELL: NPShell = NPShell::new();
 
+// Command run once at the first prompt, set by the autorun= kernel parameter
+static mut AUTORUN: Option<&'static str> = None;
+
+fn setup_autorun(value: Option<&'static str>) -> bool {
+    match value {
+        Some(command) if !command.is_empty() => {
+            unsafe {
+                AUTORUN = Some(command);
+            }
+            true
+        }
+        _ => false,
+    }
+}
+kernel_param!("autorun", "Shell command to run once the prompt is up", setup_autorun);
+
 // Initialize shell
 pub fn init() {
-    vga::writer().printc("NPS - Not a POSIX Shell - Type 'help' for commands\n\n", Color::LightBlue, Color::Black);
+    register_all(&COMMANDS_BUILTIN);
+    console::printc("NPS - Not a POSIX Shell - Type 'help' for commands\n\n", Color::LightBlue, Color::Black);
     unsafe {
         let shell = &mut *core::ptr::addr_of_mut!(NPSHELL);
         shell.show_prompt();
+        if let Some(command) = AUTORUN {
+            shell.type_line(command);
+        }
     }
 }
 
 // Handle keyboard input for shell
-pub fn handle_input(ch: u8) {
+fn handle_input(key: Key) {
     unsafe {
         let shell = &mut *core::ptr::addr_of_mut!(NPSHELL);
-        shell.handle_char(ch);
+        shell.handle_key(key);
+    }
+}
+
+// The shell's thread: read keys and run commands, forever
+pub fn run() -> ! {
+    loop {
+        handle_input(crate::kb::read_key());
     }
 }
@@ -0,0 +1,425 @@
+// paging.rs - Page tables, physical frames and copy-on-write
+//
+// The kernel identity maps the whole 4 GiB with 4 MiB pages (CR4.PSE), so
+// physical addresses work as pointers everywhere and frames can be filled
+// and copied directly. The one exception is the user window at USER_BASE:
+// every address space has its own page table of 4 KiB pages there. fork()
+// shares those frames read-only, and the first write to one of them (from
+// either side) gets a private copy in handle_fault().
+//
+// The identity pages are supervisor only, except for the kernel's
+// .user_text section (the built-in programs, see user.asm): the first 4 MiB
+// use a page table of their own so those few pages can be mapped user
+// accessible and read-only.
+
+use core::arch::asm;
+use kfs_core::frames::FrameTable;
+use kfs_core::paging::{self, Entry, PageFault, ENTRIES, PAGE_SIZE, TABLE_SPAN};
+use kfs_core::paging::{COW, HUGE, PRESENT, USER, WRITABLE};
+use crate::console;
+use crate::cpu::{self, Feature};
+use crate::idt;
+use crate::multiboot;
+use crate::vga::Color;
+
+// Frames above this are never used (the reference counts live in the bss)
+const MAX_MEMORY: u64 = 512 * 1024 * 1024;
+const FRAME_COUNT: usize = (MAX_MEMORY / PAGE_SIZE as u64) as usize;
+
+pub const USER_BASE: u32 = 0x8000_0000;
+pub const USER_TOP: u32 = USER_BASE + TABLE_SPAN;
+// The stack grows down from USER_TOP into this much on demand
+pub const USER_STACK_MAX: u32 = 1024 * 1024;
+
+const CR0_WP: u32 = 1 << 16;        // Ring 0 writes honour read-only pages too (for COW)
+const CR0_PG: u32 = 1 << 31;
+const CR4_PSE: u32 = 1 << 4;
+
+#[repr(C, align(4096))]
+struct Table([Entry; ENTRIES]);
+
+static mut KERNEL_DIRECTORY: Table = Table([Entry::EMPTY; ENTRIES]);
+// Identity map of the first 4 MiB, where the kernel image is
+static mut LOW_TABLE: Table = Table([Entry::EMPTY; ENTRIES]);
+static mut FRAMES: FrameTable<FRAME_COUNT> = FrameTable::new();
+static mut ENABLED: bool = false;
+
+extern "C" {
+    static _kernel_end: u8;
+    static _user_text_start: u8;
+    static _user_text_end: u8;
+}
+
+// System calls run with interrupts on and can be preempted, so the frame
+// table is only ever touched with them off
+fn with_frames<R>(f: impl FnOnce(&mut FrameTable<FRAME_COUNT>) -> R) -> R {
+    let interrupts = idt::disable_interrupts();
+    let result = f(unsafe { &mut *core::ptr::addr_of_mut!(FRAMES) });
+    idt::restore_interrupts(interrupts);
+    result
+}
+
+// A page table or directory by its physical (= virtual) address
+fn table(addr: u32) -> &'static mut [Entry; ENTRIES] {
+    unsafe { &mut *(addr as *mut [Entry; ENTRIES]) }
+}
+
+fn user_directory_index() -> usize {
+    paging::directory_index(USER_BASE)
+}
+
+fn alloc_zeroed() -> Option<u32> {
+    let frame = with_frames(|frames| frames.alloc())?;
+    unsafe {
+        core::ptr::write_bytes(frame as *mut u8, 0, PAGE_SIZE as usize);
+    }
+    Some(frame)
+}
+
+fn invalidate(addr: u32) {
+    unsafe {
+        asm!("invlpg [{}]", in(reg) addr, options(nostack, preserves_flags));
+    }
+}
+
+fn read_cr3() -> u32 {
+    let cr3: u32;
+    unsafe {
+        asm!("mov {}, cr3", out(reg) cr3, options(nomem, nostack, preserves_flags));
+    }
+    cr3
+}
+
+fn add_memory() {
+    multiboot::for_each_memory_region(|region| {
+        if region.kind == multiboot::MEMORY_AVAILABLE {
+            with_frames(|frames| frames.add_region(region.base, region.base + region.length));
+        }
+    });
+    // Real mode leftovers, our GDT at 0x800 and the kernel image
+    with_frames(|frames| frames.reserve(0, &raw const _kernel_end as u64));
+    multiboot::for_each_boot_range(|start, end| with_frames(|frames| frames.reserve(start as u64, end as u64)));
+}
+
+pub fn init() {
+    let has_map = multiboot::info().is_some_and(|info| info.flags & multiboot::INFO_MEM_MAP != 0);
+    if !cpu::has(Feature::Pse) || !has_map {
+        console::info("      Paging disabled (needs PSE and a memory map)\n\n", Color::Yellow, Color::Black);
+        return;
+    }
+    add_memory();
+
+    unsafe {
+        let user_text = &raw const _user_text_start as u32..&raw const _user_text_end as u32;
+        assert!(user_text.end <= TABLE_SPAN, "paging: .user_text above 4 MiB");
+        let low_table = &mut *core::ptr::addr_of_mut!(LOW_TABLE);
+        for (index, entry) in low_table.0.iter_mut().enumerate() {
+            let addr = index as u32 * PAGE_SIZE;
+            let flags = if user_text.contains(&addr) { USER } else { WRITABLE };
+            *entry = Entry::new(addr, PRESENT | flags);
+        }
+
+        let directory = &mut *core::ptr::addr_of_mut!(KERNEL_DIRECTORY);
+        for (index, entry) in directory.0.iter_mut().enumerate() {
+            *entry = match index {
+                0 => Entry::new(low_table as *const Table as u32, PRESENT | WRITABLE | USER),
+                _ if index == user_directory_index() => continue,
+                _ => Entry::new(index as u32 * TABLE_SPAN, PRESENT | WRITABLE | HUGE),
+            };
+        }
+
+        let mut cr4: u32;
+        asm!("mov {}, cr4", out(reg) cr4, options(nomem, nostack, preserves_flags));
+        cr4 |= CR4_PSE;
+        asm!("mov cr4, {}", in(reg) cr4, options(nostack, preserves_flags));
+        asm!("mov cr3, {}", in(reg) directory as *const Table as u32, options(nostack, preserves_flags));
+        let mut cr0: u32;
+        asm!("mov {}, cr0", out(reg) cr0, options(nomem, nostack, preserves_flags));
+        cr0 |= CR0_PG | CR0_WP;
+        asm!("mov cr0, {}", in(reg) cr0, options(nostack, preserves_flags));
+        ENABLED = true;
+    }
+    console::info("      Paging enabled!\n\n", Color::Green, Color::Black);
+}
+
+pub fn enabled() -> bool {
+    unsafe { ENABLED }
+}
+
+// CR3 of the kernel's own address space, 0 without paging
+pub fn kernel_directory() -> u32 {
+    if !enabled() {
+        return 0;
+    }
+    core::ptr::addr_of!(KERNEL_DIRECTORY) as u32
+}
+
+// Switch address spaces; a no-op without paging
+pub fn load(directory: u32) {
+    if enabled() && directory != 0 {
+        unsafe {
+            asm!("mov cr3, {}", in(reg) directory, options(nostack, preserves_flags));
+        }
+    }
+}
+
+pub fn free_frames() -> usize {
+    with_frames(|frames| frames.free_frames())
+}
+
+// A directory whose user window has its own page table. This is only a
+// handle: whoever created it has to destroy() it.
+pub struct AddressSpace {
+    directory: u32,
+}
+
+impl AddressSpace {
+    pub fn new() -> Option<AddressSpace> {
+        if !enabled() {
+            return None;
+        }
+        let directory = alloc_zeroed()?;
+        let Some(user_table) = alloc_zeroed() else {
+            with_frames(|frames| frames.release(directory));
+            return None;
+        };
+        let entries = table(directory);
+        *entries = unsafe { (*core::ptr::addr_of!(KERNEL_DIRECTORY)).0 };
+        entries[user_directory_index()] = Entry::new(user_table, PRESENT | WRITABLE | USER);
+        Some(AddressSpace { directory })
+    }
+
+    pub fn directory(&self) -> u32 {
+        self.directory
+    }
+
+    fn user_table(&self) -> &'static mut [Entry; ENTRIES] {
+        table(table(self.directory)[user_directory_index()].addr())
+    }
+
+    fn entry(&self, addr: u32) -> Option<&'static mut Entry> {
+        if !(USER_BASE..USER_TOP).contains(&addr) {
+            return None;
+        }
+        Some(&mut self.user_table()[paging::table_index(addr)])
+    }
+
+    fn active(&self) -> bool {
+        read_cr3() == self.directory
+    }
+
+    // Back the page at `addr` with a fresh zeroed frame; returns the frame
+    pub fn map(&self, addr: u32, writable: bool) -> Option<u32> {
+        let entry = self.entry(addr)?;
+        if entry.present() {
+            return Some(entry.addr());
+        }
+        let frame = alloc_zeroed()?;
+        let flags = PRESENT | USER | if writable { WRITABLE } else { 0 };
+        *entry = Entry::new(frame, flags);
+        if self.active() {
+            invalidate(addr);
+        }
+        Some(frame)
+    }
+
+    pub fn unmap(&self, addr: u32) {
+        if let Some(entry) = self.entry(addr).filter(|e| e.present()) {
+            with_frames(|frames| frames.release(entry.addr()));
+            *entry = Entry::EMPTY;
+            if self.active() {
+                invalidate(addr);
+            }
+        }
+    }
+
+    pub fn is_mapped(&self, addr: u32) -> bool {
+        self.entry(addr).is_some_and(|e| e.present())
+    }
+
+    // For pages mapped read-only that another segment shares and writes to
+    pub fn make_writable(&self, addr: u32) {
+        if let Some(entry) = self.entry(addr).filter(|e| e.present()) {
+            *entry = entry.with(WRITABLE, 0);
+            if self.active() {
+                invalidate(addr);
+            }
+        }
+    }
+
+    // Call `f` with the frame memory behind addr..addr+len, page by page.
+    // Works whether or not this address space is the active one.
+    fn for_each_chunk(&self, addr: u32, len: u32, mut f: impl FnMut(*mut u8, usize, usize)) -> bool {
+        let Some(end) = addr.checked_add(len) else { return false };
+        let mut at = addr;
+        while at < end {
+            let Some(entry) = self.entry(at).filter(|e| e.present()) else { return false };
+            let offset = at % PAGE_SIZE;
+            let count = (PAGE_SIZE - offset).min(end - at);
+            f((entry.addr() + offset) as *mut u8, (at - addr) as usize, count as usize);
+            at += count;
+        }
+        true
+    }
+
+    // Copy into mapped pages, ignoring their write protection
+    pub fn write(&self, addr: u32, data: &[u8]) -> bool {
+        self.for_each_chunk(addr, data.len() as u32, |dest, done, count| unsafe {
+            core::ptr::copy_nonoverlapping(data[done..].as_ptr(), dest, count);
+        })
+    }
+
+    pub fn zero(&self, addr: u32, len: u32) -> bool {
+        self.for_each_chunk(addr, len, |dest, _, count| unsafe {
+            core::ptr::write_bytes(dest, 0, count);
+        })
+    }
+
+    // A copy sharing every user page copy-on-write
+    pub fn fork(&self) -> Option<AddressSpace> {
+        let child = AddressSpace::new()?;
+        let child_table = child.user_table();
+        for (index, entry) in self.user_table().iter_mut().enumerate() {
+            if entry.present() {
+                with_frames(|frames| frames.share(entry.addr()));
+                *entry = entry.shared();
+                child_table[index] = *entry;
+            }
+        }
+        // Our own writable entries just turned read-only
+        if self.active() {
+            load(self.directory);
+        }
+        Some(child)
+    }
+
+    // Resolve a write to a COW page: copy it unless we are its last user
+    fn copy_on_write(&self, addr: u32) -> bool {
+        let Some(entry) = self.entry(addr).filter(|e| e.present() && e.has(COW)) else {
+            return false;
+        };
+        let frame = entry.addr();
+        // Our own copy, unless nobody else uses the frame any more
+        let own = with_frames(|frames| {
+            if frames.refs(frame) == 1 {
+                return Some(frame);
+            }
+            let copy = frames.alloc()?;
+            unsafe {
+                core::ptr::copy_nonoverlapping(frame as *const u8, copy as *mut u8, PAGE_SIZE as usize);
+            }
+            frames.release(frame);
+            Some(copy)
+        });
+        let Some(own) = own else { return false };
+        *entry = Entry::new(own, entry.flags()).with(WRITABLE, COW);
+        invalidate(addr);
+        true
+    }
+
+    // Give back every frame, switching to the kernel's directory if needed
+    pub fn destroy(self) {
+        if self.active() {
+            load(kernel_directory());
+        }
+        let user_table = self.user_table();
+        for entry in user_table.iter().filter(|e| e.present()) {
+            with_frames(|frames| frames.release(entry.addr()));
+        }
+        with_frames(|frames| frames.release(user_table.as_ptr() as u32));
+        with_frames(|frames| frames.release(self.directory));
+    }
+}
+
+// The address space CR3 points to, unless it is the kernel's
+fn current() -> Option<AddressSpace> {
+    let directory = read_cr3();
+    (enabled() && directory != kernel_directory()).then_some(AddressSpace { directory })
+}
+
+// Pages of the user window in the current address space, or None if
+// addr..addr+len is not inside it
+fn user_pages(addr: u32, len: u32) -> Option<impl Iterator<Item = u32>> {
+    let end = addr.checked_add(len.max(1))?;
+    if addr < USER_BASE || end > USER_TOP {
+        return None;
+    }
+    Some((paging::align_down(addr)..end).step_by(PAGE_SIZE as usize))
+}
+
+fn grow_stack(space: &AddressSpace, page: u32) -> bool {
+    page >= USER_TOP - USER_STACK_MAX && space.map(page, true).is_some()
+}
+
+// Is addr..addr+len mapped in the current process's user window?
+pub fn is_user_mapped(addr: u32, len: u32) -> bool {
+    let (Some(space), Some(mut pages)) = (current(), user_pages(addr, len)) else { return false };
+    pages.all(|page| space.is_mapped(page))
+}
+
+// Could ring 3 write addr..addr+len? Grows the stack as a user write there
+// would. Copy-on-write pages get copied by the write itself (CR0.WP).
+pub fn user_writable(addr: u32, len: u32) -> bool {
+    let (Some(space), Some(mut pages)) = (current(), user_pages(addr, len)) else { return false };
+    pages.all(|page| match space.entry(page).filter(|e| e.present()) {
+        Some(entry) => entry.has(WRITABLE) || entry.has(COW),
+        None => grow_stack(&space, page),
+    })
+}
+
+// #PF: fix up copy-on-write and stack growth. False means a real fault.
+pub fn handle_fault(fault: &PageFault) -> bool {
+    let Some(space) = current() else { return false };
+    if !(USER_BASE..USER_TOP).contains(&fault.addr) {
+        return false;
+    }
+    let page = paging::align_down(fault.addr);
+    if fault.protection() {
+        fault.write() && space.copy_on_write(page)
+    } else {
+        grow_stack(&space, page)
+    }
+}
+
+pub fn fault_address() -> u32 {
+    let cr2: u32;
+    unsafe {
+        asm!("mov {}, cr2", out(reg) cr2, options(nomem, nostack, preserves_flags));
+    }
+    cr2
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn cow_pages_are_copied_on_write() {
+        if !enabled() {
+            return;
+        }
+        let parent = AddressSpace::new().unwrap();
+        let frame = parent.map(USER_BASE, true).unwrap();
+        unsafe { *(frame as *mut u32) = 1234 };
+
+        let child = parent.fork().unwrap();
+        assert_eq!(with_frames(|frames| frames.refs(frame)), 2);
+        assert!(!parent.entry(USER_BASE).unwrap().has(WRITABLE));
+
+        // The child writes first and gets its own copy; the parent keeps the original
+        assert!(child.copy_on_write(USER_BASE));
+        let copy = child.entry(USER_BASE).unwrap().addr();
+        assert_ne!(copy, frame);
+        assert_eq!(unsafe { *(copy as *const u32) }, 1234);
+        assert_eq!(with_frames(|frames| frames.refs(frame)), 1);
+        // Last user: no copy, the page just turns writable again
+        assert!(parent.copy_on_write(USER_BASE));
+        assert_eq!(parent.entry(USER_BASE).unwrap().addr(), frame);
+
+        // Directory, user table and one page each
+        let free = free_frames();
+        child.destroy();
+        parent.destroy();
+        assert_eq!(free_frames(), free + 6);
+    }
+}
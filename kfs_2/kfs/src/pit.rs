@@ -0,0 +1,119 @@
+// pit.rs - Programmable Interval Timer (channel 0 on IRQ0)
+
+use crate::console;
+use crate::idt::{self, TrapFrame};
+use crate::pic;
+use crate::process;
+use crate::status;
+use crate::sync::WaitQueue;
+use crate::thread;
+use crate::hal::{Port, PortWrite, WriteOnlyPort};
+use crate::vga::Color;
+
+const PIT_CHANNEL0: Port<u8> = Port::new(0x40);
+const PIT_COMMAND: WriteOnlyPort<u8> = WriteOnlyPort::new(0x43);
+const PIT_BASE_HZ: u32 = 1_193_182;
+
+// Channel 0, lobyte/hibyte access, mode 3 (square wave), binary
+const PIT_MODE: u8 = 0x36;
+
+pub const DEFAULT_HZ: u32 = 100;
+
+static mut TICKS: u64 = 0;
+static mut HZ: u32 = DEFAULT_HZ;
+
+// Sleeping threads, woken together once the earliest of their deadlines passes
+static SLEEPERS: WaitQueue = WaitQueue::new();
+static mut NEXT_WAKEUP: u64 = u64::MAX;
+
+fn setup_pit_hz(value: Option<&str>) -> bool {
+    match value.and_then(|v| v.parse::<u32>().ok()) {
+        Some(hz) if (19..=PIT_BASE_HZ).contains(&hz) => {
+            unsafe {
+                HZ = hz;
+            }
+            true
+        }
+        _ => false,
+    }
+}
+kernel_param!("pit_hz", "Timer interrupt frequency in Hz (19-1193182)", setup_pit_hz);
+
+pub fn init(hz: u32) {
+    console::info("[3/5] Initializing PIT...\n", Color::Yellow, Color::Black);
+    let hz = hz.clamp(19, PIT_BASE_HZ);
+    let divisor = PIT_BASE_HZ / hz;
+    unsafe {
+        HZ = hz;
+    }
+    PIT_COMMAND.write(PIT_MODE);
+    PIT_CHANNEL0.write((divisor & 0xFF) as u8);
+    PIT_CHANNEL0.write((divisor >> 8) as u8);
+    console::info("      PIT running!\n\n", Color::Green, Color::Black);
+}
+
+pub fn ticks() -> u64 {
+    unsafe { core::ptr::read_volatile(core::ptr::addr_of!(TICKS)) }
+}
+
+pub fn hz() -> u32 {
+    unsafe { HZ }
+}
+
+pub fn uptime_ms() -> u64 {
+    ticks() * 1000 / hz() as u64
+}
+
+// Sleep for `ms` or until `interrupted` says to stop (checked whenever the
+// thread wakes, e.g. for a signal); returns false if cut short
+pub fn sleep_ms_until(ms: u64, interrupted: impl Fn() -> bool) -> bool {
+    let end = ticks() + (ms * hz() as u64).div_ceil(1000);
+    let interrupts = idt::disable_interrupts();
+    let slept = loop {
+        if ticks() >= end {
+            break true;
+        }
+        if interrupted() {
+            break false;
+        }
+        unsafe {
+            NEXT_WAKEUP = NEXT_WAKEUP.min(end);
+        }
+        SLEEPERS.wait();
+    };
+    idt::restore_interrupts(interrupts);
+    slept
+}
+
+pub fn sleep_ms(ms: u64) {
+    sleep_ms_until(ms, || false);
+}
+
+#[no_mangle]
+pub extern "C" fn timer_handler(frame: &mut TrapFrame) {
+    unsafe {
+        TICKS += 1;
+    }
+    pic::send_eoi(0);
+
+    #[cfg(test)]
+    crate::testing::check_timeout();
+
+    // Refresh the status bar once per second
+    if ticks().is_multiple_of(hz() as u64) {
+        status::refresh();
+    }
+
+    unsafe {
+        if ticks() >= NEXT_WAKEUP {
+            NEXT_WAKEUP = u64::MAX;
+            SLEEPERS.wake_all();
+        }
+    }
+    thread::tick();
+
+    // A signal sent to a process that does not make system calls
+    if frame.in_user_mode() {
+        process::deliver_signals(frame);
+    }
+}
@@ -0,0 +1,520 @@
+// process.rs - User processes: fork, exit, wait and signals
+//
+// A process is a user address space (paging.rs) run by one kernel thread,
+// plus what the system calls work on: open files, the program break, signal
+// state and finally its exit status. The thread enters ring 3 through
+// return_to_user (user.asm) and comes back on its own kernel stack for every
+// trap.
+//
+// Processes form a tree through their parent pid, where 0 is the kernel
+// itself (the shell starts programs through user::run). An exited process
+// stays a zombie until its parent collects the status with wait(). Orphans
+// are handed to the kernel, which does not wait for them, so they are freed
+// as soon as they exit.
+//
+// Signals are only acted on when a process is about to return to ring 3:
+// after a system call, a fault, or a timer interrupt that hit user code.
+// A handler runs on the user stack with a SignalFrame above its argument and
+// returns into signal_return, whose sigreturn system call restores the
+// interrupted registers.
+
+use kfs_core::paging::PAGE_SIZE;
+use kfs_core::signal::{self, Action, SigSet, WaitStatus, NSIG, SIGCHLD, SIGINT, SIGSEGV, SIGTERM, SIG_DFL, SIG_IGN};
+use crate::idt::{self, TrapFrame};
+use crate::nps::{self, Builtin};
+use crate::paging::{self, AddressSpace, USER_TOP};
+use crate::syscall::{self, File, ECHILD, EAGAIN, EINTR, EINVAL, ENOMEM, EPERM, ESRCH, MAX_FILES};
+use crate::thread;
+
+const MAX_PROCESSES: usize = 16;
+
+const USER_CODE: u32 = 0x18 | 3;
+const USER_DATA: u32 = 0x20 | 3;
+const USER_STACK: u32 = 0x40 | 3;
+
+const EFLAGS_RESERVED: u32 = 0x2;
+const EFLAGS_IF: u32 = 0x200;
+// Arithmetic flags, TF and DF: all user code may set through sigreturn
+const EFLAGS_USER: u32 = 0xDD5;
+
+// waitpid() option: return 0 instead of blocking
+pub const WNOHANG: u32 = 1;
+
+#[derive(Copy, Clone, PartialEq)]
+enum State {
+    Alive,
+    Zombie,
+}
+
+pub struct Process {
+    pub pid: u32,
+    pub parent: u32,            // 0 for the kernel
+    group: u32,                 // Ctrl+C goes to the foreground group
+    name: &'static str,
+    pub uid: u32,
+    state: State,
+    tid: u32,
+    space: Option<AddressSpace>,
+    pub files: [Option<File>; MAX_FILES],
+    pub heap_start: u32,        // Lowest the break can go
+    pub brk: u32,
+    pub mmap_next: u32,
+    pending: SigSet,
+    blocked: SigSet,
+    handlers: [u32; NSIG as usize],
+    status: WaitStatus,
+    waiter: Option<u32>,        // Thread in wait() for this process
+    detached: bool,             // Nobody waits: free the slot on exit
+    frame: TrapFrame,           // Registers to enter ring 3 with
+}
+
+// What a handler finds on its stack: the return address, its argument, and
+// what sigreturn needs to resume the interrupted code
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct SignalFrame {
+    ret: u32,
+    signal: u32,
+    blocked: u32,
+    saved: TrapFrame,
+}
+
+static mut PROCESSES: [Option<Process>; MAX_PROCESSES] = [const { None }; MAX_PROCESSES];
+static mut NEXT_PID: u32 = 1;
+// Group of the program the shell waits for, 0 if none
+static mut FOREGROUND: u32 = 0;
+
+extern "C" {
+    fn return_to_user(frame: *const TrapFrame) -> !;
+    fn user_exit();
+    fn signal_return();
+}
+
+fn processes() -> &'static mut [Option<Process>; MAX_PROCESSES] {
+    unsafe { &mut *core::ptr::addr_of_mut!(PROCESSES) }
+}
+
+fn find(pid: u32) -> Option<&'static mut Process> {
+    processes().iter_mut().flatten().find(|p| p.pid == pid)
+}
+
+fn find_alive(pid: u32) -> Option<&'static mut Process> {
+    find(pid).filter(|p| p.state == State::Alive)
+}
+
+// The process the running thread belongs to; None in kernel threads
+pub fn current() -> Option<&'static mut Process> {
+    let tid = thread::current();
+    processes().iter_mut().flatten().find(|p| p.tid == tid && p.state == State::Alive)
+}
+
+fn new_process(name: &'static str, parent: u32, space: AddressSpace, frame: TrapFrame) -> Process {
+    let pid = unsafe {
+        NEXT_PID += 1;
+        NEXT_PID - 1
+    };
+    Process {
+        pid,
+        parent,
+        group: pid,
+        name,
+        uid: 0,
+        state: State::Alive,
+        tid: 0,
+        space: Some(space),
+        files: syscall::STDIO,
+        heap_start: syscall::HEAP_START,
+        brk: syscall::HEAP_START,
+        mmap_next: syscall::MMAP_START,
+        pending: SigSet::empty(),
+        blocked: SigSet::empty(),
+        handlers: [SIG_DFL; NSIG as usize],
+        status: WaitStatus::Exited(0),
+        waiter: None,
+        detached: false,
+        frame,
+    }
+}
+
+// First code of a process's thread
+fn process_main(pid: u32) {
+    let (directory, frame) = {
+        let process = find(pid).unwrap();
+        (process.space.as_ref().unwrap().directory(), process.frame)
+    };
+    thread::set_address_space(directory);
+    unsafe { return_to_user(&frame) }
+}
+
+// Give the process a slot and a thread; frees its memory if that fails
+fn start(mut process: Process) -> Option<u32> {
+    let interrupts = idt::disable_interrupts();
+    let pid = process.pid;
+    let slot = processes().iter().position(|p| p.is_none());
+    let tid = slot.and_then(|_| thread::spawn(process.name, thread::PRIORITY_NORMAL, process_main, pid));
+    let started = match (slot, tid) {
+        (Some(slot), Some(tid)) => {
+            thread::detach(tid);
+            process.tid = tid;
+            processes()[slot] = Some(process);
+            Some(pid)
+        }
+        _ => {
+            if let Some(space) = process.space.take() {
+                space.destroy();
+            }
+            None
+        }
+    };
+    idt::restore_interrupts(interrupts);
+    started
+}
+
+// Start a new process at `entry` with a fresh address space and an empty
+// stack at the top of the user window. Returning from `entry` calls exit.
+pub fn spawn(name: &'static str, entry: u32) -> Option<u32> {
+    let space = AddressSpace::new()?;
+    let return_address = (user_exit as *const () as u32).to_le_bytes();
+    let stack = USER_TOP - PAGE_SIZE;
+    if space.map(stack, true).is_none() || !space.write(USER_TOP - 4, &return_address) {
+        space.destroy();
+        return None;
+    }
+    start_program(name, space, entry, USER_TOP - 4, syscall::HEAP_START)
+}
+
+// Start a process in a prepared address space (see elf.rs), with the heap
+// growing from `heap_start`
+pub fn start_program(name: &'static str, space: AddressSpace, entry: u32, user_esp: u32, heap_start: u32) -> Option<u32> {
+    let frame = TrapFrame {
+        gs: USER_DATA,
+        fs: USER_DATA,
+        es: USER_DATA,
+        ds: USER_DATA,
+        eip: entry,
+        cs: USER_CODE,
+        eflags: EFLAGS_IF | EFLAGS_RESERVED,
+        user_esp,
+        user_ss: USER_STACK,
+        ..TrapFrame::default()
+    };
+    let parent = current().map_or(0, |p| p.pid);
+    let mut process = new_process(name, parent, space, frame);
+    process.heap_start = heap_start;
+    process.brk = heap_start;
+    start(process)
+}
+
+// Copy the current process; the child resumes from `frame` with eax = 0
+pub fn fork(frame: &TrapFrame) -> Result<u32, i32> {
+    let parent = current().ok_or(EPERM)?;
+    let space = parent.space.as_ref().and_then(|s| s.fork()).ok_or(ENOMEM)?;
+    let mut registers = *frame;
+    registers.eax = 0;
+    let mut child = new_process(parent.name, parent.pid, space, registers);
+    child.group = parent.group;
+    child.uid = parent.uid;
+    child.files = parent.files;
+    child.heap_start = parent.heap_start;
+    child.brk = parent.brk;
+    child.mmap_next = parent.mmap_next;
+    child.blocked = parent.blocked;
+    child.handlers = parent.handlers;
+    start(child).ok_or(EAGAIN)
+}
+
+// End the current process with `status` and never come back
+pub fn exit(status: WaitStatus) -> ! {
+    idt::disable_interrupts();
+    let process = current().expect("exit outside a process");
+    let (pid, parent) = (process.pid, process.parent);
+    thread::set_address_space(paging::kernel_directory());
+    if let Some(space) = process.space.take() {
+        space.destroy();
+    }
+    process.files = [None; MAX_FILES];
+    process.state = State::Zombie;
+    process.status = status;
+    if let Some(tid) = process.waiter {
+        thread::wake(tid);
+    }
+    let detached = process.detached;
+
+    // Children go to the kernel, which never waits for them
+    for slot in processes().iter_mut() {
+        if let Some(child) = slot.as_mut().filter(|p| p.parent == pid) {
+            child.parent = 0;
+            child.detached = true;
+            if child.state == State::Zombie {
+                *slot = None;
+            }
+        }
+    }
+    if let Some(parent) = find_alive(parent) {
+        parent.post(SIGCHLD);
+    }
+    if detached {
+        release(pid);
+    }
+    thread::exit(status.encode())
+}
+
+fn release(pid: u32) {
+    if let Some(slot) = processes().iter_mut().find(|p| p.as_ref().is_some_and(|p| p.pid == pid)) {
+        *slot = None;
+    }
+}
+
+// Nobody will wait for `pid`; forget it as soon as it exits
+pub fn detach(pid: u32) {
+    let interrupts = idt::disable_interrupts();
+    if let Some(process) = find(pid) {
+        process.detached = true;
+        if process.state == State::Zombie {
+            release(pid);
+        }
+    }
+    idt::restore_interrupts(interrupts);
+}
+
+// Wait for a child (any child for -1) to exit and collect its status.
+// Ok(None) means WNOHANG was given and no child has exited yet.
+pub fn wait(pid: i32, options: u32) -> Result<Option<(u32, WaitStatus)>, i32> {
+    let me = current().map_or(0, |p| p.pid);
+    let interrupts = idt::disable_interrupts();
+    let result = loop {
+        let mut children = false;
+        let mut exited = None;
+        for slot in processes().iter_mut() {
+            let Some(child) = slot.as_mut().filter(|p| p.parent == me && (pid == -1 || p.pid as i32 == pid)) else {
+                continue;
+            };
+            children = true;
+            if child.state == State::Zombie {
+                exited = Some((child.pid, child.status));
+                *slot = None;
+                break;
+            }
+            child.waiter = Some(thread::current());
+        }
+        if exited.is_some() {
+            break Ok(exited);
+        }
+        if !children {
+            break Err(ECHILD);
+        }
+        if options & WNOHANG != 0 {
+            break Ok(None);
+        }
+        if signal_pending() {
+            break Err(EINTR);
+        }
+        thread::block();
+    };
+    idt::restore_interrupts(interrupts);
+    result
+}
+
+impl Process {
+    pub fn space(&self) -> Option<&AddressSpace> {
+        self.space.as_ref()
+    }
+
+    // Mark `signal` pending unless it would be ignored anyway
+    fn post(&mut self, signal: u32) {
+        let handler = self.handlers[signal as usize];
+        let default_ignore = handler == SIG_DFL && signal::default_action(signal) == Action::Ignore;
+        if signal::catchable(signal) && (handler == SIG_IGN || default_ignore) {
+            return;
+        }
+        self.pending.add(signal);
+        // Cut short whatever it sleeps in; blocking calls check signal_pending()
+        if !self.blocked.contains(signal) {
+            thread::wake(self.tid);
+        }
+    }
+
+    // Set up the user stack so that the return to ring 3 enters `handler`
+    fn enter_handler(&mut self, frame: &mut TrapFrame, signal: u32, handler: u32) -> bool {
+        let size = core::mem::size_of::<SignalFrame>() as u32;
+        // Leave the argument 16-byte aligned, as after any call
+        let addr = (frame.user_esp.wrapping_sub(size) & !0xF).wrapping_sub(4);
+        if !paging::user_writable(addr, size) {
+            return false;
+        }
+        let signal_frame = SignalFrame {
+            ret: signal_return as *const () as u32,
+            signal,
+            blocked: self.blocked.0,
+            saved: *frame,
+        };
+        unsafe {
+            (addr as *mut SignalFrame).write(signal_frame);
+        }
+        // No nesting of the same signal while its handler runs
+        self.blocked.add(signal);
+        frame.eip = handler;
+        frame.user_esp = addr;
+        true
+    }
+}
+
+// Send `signal` (0 only checks the pid). Processes other than root may only
+// signal processes of their own user.
+pub fn kill(pid: u32, signal: u32) -> Result<(), i32> {
+    if signal != 0 && !signal::valid(signal) {
+        return Err(EINVAL);
+    }
+    let sender = current().map_or(0, |p| p.uid);
+    let interrupts = idt::disable_interrupts();
+    let result = match find_alive(pid) {
+        None => Err(ESRCH),
+        Some(target) if sender != 0 && sender != target.uid => Err(EPERM),
+        Some(target) => {
+            if signal != 0 {
+                target.post(signal);
+            }
+            Ok(())
+        }
+    };
+    idt::restore_interrupts(interrupts);
+    result
+}
+
+// Install a handler (or SIG_DFL/SIG_IGN) and return the previous one
+pub fn set_handler(signal: u32, handler: u32) -> Result<u32, i32> {
+    let process = current().ok_or(EPERM)?;
+    if !signal::catchable(signal) {
+        return Err(EINVAL);
+    }
+    let old = core::mem::replace(&mut process.handlers[signal as usize], handler);
+    if handler == SIG_IGN {
+        process.pending.remove(signal);
+    }
+    Ok(old)
+}
+
+// Should a blocking system call give up with EINTR?
+pub fn signal_pending() -> bool {
+    current().is_some_and(|p| p.pending.first_unblocked(p.blocked).is_some())
+}
+
+// A fault is not something to ignore: the signal is delivered even if the
+// program blocked or ignored it
+pub fn force_signal(signal: u32) {
+    if let Some(process) = current() {
+        if process.blocked.contains(signal) || process.handlers[signal as usize] == SIG_IGN {
+            process.blocked.remove(signal);
+            process.handlers[signal as usize] = SIG_DFL;
+        }
+        process.pending.add(signal);
+    }
+}
+
+// On the way back to ring 3: act on pending signals. Default actions that
+// end the process do not return; a handler gets entered by rewriting `frame`.
+pub fn deliver_signals(frame: &mut TrapFrame) {
+    let Some(process) = current() else { return };
+    let interrupts = idt::disable_interrupts();
+    while let Some(signal) = process.pending.first_unblocked(process.blocked) {
+        process.pending.remove(signal);
+        let handler = process.handlers[signal as usize];
+        if !signal::catchable(signal) || handler == SIG_DFL {
+            match signal::default_action(signal) {
+                // There is no job control, so stopping is ignored as well
+                Action::Ignore | Action::Stop => continue,
+                Action::Terminate | Action::CoreDump => exit(WaitStatus::killed_by(signal)),
+            }
+        }
+        if handler == SIG_IGN {
+            continue;
+        }
+        if !process.enter_handler(frame, signal, handler) {
+            exit(WaitStatus::killed_by(SIGSEGV));
+        }
+        break;
+    }
+    idt::restore_interrupts(interrupts);
+}
+
+// Return from a handler: signal_return made this call with esp just above
+// the handler's return address. Returns the restored eax.
+pub fn sigreturn(frame: &mut TrapFrame) -> i32 {
+    let Some(process) = current() else { return -EPERM };
+    let addr = frame.user_esp.wrapping_sub(4);
+    if !paging::is_user_mapped(addr, core::mem::size_of::<SignalFrame>() as u32) {
+        exit(WaitStatus::killed_by(SIGSEGV));
+    }
+    let signal_frame = unsafe { (addr as *const SignalFrame).read() };
+    let mut saved = signal_frame.saved;
+    // The frame came from user memory: only let it go back to ring 3
+    saved.cs = USER_CODE;
+    saved.user_ss = USER_STACK;
+    (saved.ds, saved.es, saved.fs, saved.gs) = (USER_DATA, USER_DATA, USER_DATA, USER_DATA);
+    saved.eflags = (saved.eflags & EFLAGS_USER) | EFLAGS_IF | EFLAGS_RESERVED;
+    process.blocked = SigSet(signal_frame.blocked);
+    *frame = saved;
+    saved.eax as i32
+}
+
+// The shell waits for `pid`; Ctrl+C goes to its group. 0 for none.
+pub fn set_foreground(pid: u32) {
+    let interrupts = idt::disable_interrupts();
+    unsafe {
+        FOREGROUND = find(pid).map_or(0, |p| p.group);
+    }
+    idt::restore_interrupts(interrupts);
+}
+
+// Ctrl+C from the keyboard interrupt; false if no program is in the foreground
+pub fn interrupt_foreground() -> bool {
+    let group = unsafe { FOREGROUND };
+    if group == 0 {
+        return false;
+    }
+    for process in processes().iter_mut().flatten() {
+        if process.group == group && process.state == State::Alive {
+            process.post(SIGINT);
+        }
+    }
+    true
+}
+
+fn cmd_ps(_argv: &[&str]) {
+    println!("  PID  PPID   UID   TID STATE     NAME");
+    let interrupts = idt::disable_interrupts();
+    for process in processes().iter().flatten() {
+        let state = match process.state {
+            State::Zombie => "zombie",
+            State::Alive => thread::state(process.tid).map_or("?", |s| s.name()),
+        };
+        println!(
+            "{:>5} {:>5} {:>5} {:>5} {:<9} {}",
+            process.pid, process.parent, process.uid, process.tid, state, process.name
+        );
+    }
+    idt::restore_interrupts(interrupts);
+}
+
+fn cmd_kill(argv: &[&str]) {
+    let pid = argv.get(1).and_then(|a| nps::parse_number(a));
+    let signal = argv.get(2).map_or(Some(SIGTERM), |a| nps::parse_number(a).or_else(|| signal::number(a)));
+    let (Some(pid), Some(signal)) = (pid, signal) else {
+        println!("Usage: kill <pid> [signal]");
+        return;
+    };
+    match kill(pid, signal) {
+        Ok(()) => {}
+        Err(ESRCH) => println!("kill: no process {}", pid),
+        Err(_) => println!("kill: invalid signal {}", signal),
+    }
+}
+
+static COMMANDS: [Builtin; 2] = [
+    Builtin { name: "ps", usage: "ps", help: "List user processes", handler: cmd_ps, complete: None },
+    Builtin { name: "kill", usage: "kill <pid> [signal]", help: "Send a signal (SIGTERM by default) to a process", handler: cmd_kill, complete: None },
+];
+
+pub fn register_commands() {
+    nps::register_all(&COMMANDS);
+}
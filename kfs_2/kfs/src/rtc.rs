@@ -0,0 +1,86 @@
+// rtc.rs - CMOS real time clock
+
+use crate::hal::{Port, PortRead, PortWrite, WriteOnlyPort};
+
+const CMOS_ADDRESS: WriteOnlyPort<u8> = WriteOnlyPort::new(0x70);
+const CMOS_DATA: Port<u8> = Port::new(0x71);
+
+const REG_SECONDS: u8 = 0x00;
+const REG_MINUTES: u8 = 0x02;
+const REG_HOURS: u8 = 0x04;
+const REG_DAY: u8 = 0x07;
+const REG_MONTH: u8 = 0x08;
+const REG_YEAR: u8 = 0x09;
+const REG_STATUS_A: u8 = 0x0A;
+const REG_STATUS_B: u8 = 0x0B;
+
+const STATUS_A_UPDATING: u8 = 0x80;
+const STATUS_B_24H: u8 = 0x02;
+const STATUS_B_BINARY: u8 = 0x04;
+
+#[derive(Copy, Clone, PartialEq)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+fn read(reg: u8) -> u8 {
+    // Bit 7 of the address port is the NMI disable bit, keep it clear
+    CMOS_ADDRESS.write(reg & 0x7F);
+    CMOS_DATA.read()
+}
+
+fn read_raw() -> DateTime {
+    while read(REG_STATUS_A) & STATUS_A_UPDATING != 0 {}
+    DateTime {
+        year: read(REG_YEAR) as u16,
+        month: read(REG_MONTH),
+        day: read(REG_DAY),
+        hour: read(REG_HOURS),
+        minute: read(REG_MINUTES),
+        second: read(REG_SECONDS),
+    }
+}
+
+fn from_bcd(value: u8) -> u8 {
+    (value & 0x0F) + (value >> 4) * 10
+}
+
+pub fn now() -> DateTime {
+    // Read until two consecutive reads agree so we never see a half-updated clock
+    let mut time = read_raw();
+    loop {
+        let again = read_raw();
+        if again == time {
+            break;
+        }
+        time = again;
+    }
+
+    let status_b = read(REG_STATUS_B);
+    let pm = time.hour & 0x80 != 0;
+    time.hour &= 0x7F;
+
+    if status_b & STATUS_B_BINARY == 0 {
+        time.second = from_bcd(time.second);
+        time.minute = from_bcd(time.minute);
+        time.hour = from_bcd(time.hour);
+        time.day = from_bcd(time.day);
+        time.month = from_bcd(time.month);
+        time.year = from_bcd(time.year as u8) as u16;
+    }
+
+    if status_b & STATUS_B_24H == 0 {
+        time.hour %= 12;
+        if pm {
+            time.hour += 12;
+        }
+    }
+
+    time.year += 2000;
+    time
+}
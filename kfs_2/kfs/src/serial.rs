@@ -0,0 +1,161 @@
+// serial.rs - 16550 UART console on COM1 (QEMU: -serial stdio)
+
+use crate::console::Console;
+use crate::hal::{Port, PortRead, PortWrite};
+use crate::vga::Color;
+
+const COM1: u16 = 0x3F8;
+
+// Register offsets from the base port
+const DATA: u16 = 0;
+const INT_ENABLE: u16 = 1;
+const FIFO_CTRL: u16 = 2;
+const LINE_CTRL: u16 = 3;
+const MODEM_CTRL: u16 = 4;
+const LINE_STATUS: u16 = 5;
+
+const LINE_STATUS_TX_EMPTY: u8 = 0x20;
+
+// ANSI color numbers in VGA color order
+const ANSI_COLORS: [u8; 8] = [0, 4, 2, 6, 1, 5, 3, 7];
+
+pub struct SerialPort {
+    base: u16,
+    row: usize,
+    column: usize,
+}
+
+impl SerialPort {
+    const fn new(base: u16) -> SerialPort {
+        SerialPort { base, row: 0, column: 0 }
+    }
+
+    fn reg(&self, offset: u16) -> Port<u8> {
+        Port::new(self.base + offset)
+    }
+
+    // 38400 baud, 8N1, FIFO on. Returns false if no UART answers the loopback test.
+    fn init(&mut self) -> bool {
+        self.reg(INT_ENABLE).write(0x00);   // No interrupts
+        self.reg(LINE_CTRL).write(0x80);    // DLAB on to set the divisor
+        self.reg(DATA).write(0x03);         // Divisor 3 -> 38400 baud
+        self.reg(INT_ENABLE).write(0x00);
+        self.reg(LINE_CTRL).write(0x03);    // 8 bits, no parity, one stop bit
+        self.reg(FIFO_CTRL).write(0xC7);    // Enable and clear FIFO, 14 byte threshold
+        self.reg(MODEM_CTRL).write(0x1E);   // Loopback mode for the self test
+
+        self.reg(DATA).write(0xAE);
+        if self.reg(DATA).read() != 0xAE {
+            return false;
+        }
+
+        self.reg(MODEM_CTRL).write(0x0F);   // Normal operation
+        true
+    }
+
+    fn send(&mut self, byte: u8) {
+        while self.reg(LINE_STATUS).read() & LINE_STATUS_TX_EMPTY == 0 {}
+        self.reg(DATA).write(byte);
+    }
+
+    fn send_str(&mut self, s: &str) {
+        for byte in s.bytes() {
+            self.send(byte);
+        }
+    }
+
+    fn send_number(&mut self, mut n: usize) {
+        let mut digits = [0u8; 20];
+        let mut len = 0;
+        loop {
+            digits[len] = b'0' + (n % 10) as u8;
+            len += 1;
+            n /= 10;
+            if n == 0 {
+                break;
+            }
+        }
+        while len > 0 {
+            len -= 1;
+            self.send(digits[len]);
+        }
+    }
+}
+
+impl Console for SerialPort {
+    fn write_byte(&mut self, byte: u8) {
+        match byte {
+            b'\n' => {
+                self.send(b'\r');
+                self.send(b'\n');
+                self.row += 1;
+                self.column = 0;
+            }
+            0x20..=0x7E => {
+                self.send(byte);
+                self.column += 1;
+            }
+            _ => {
+                self.send(b'?');
+                self.column += 1;
+            }
+        }
+    }
+
+    fn set_color(&mut self, fg: Color, bg: Color) {
+        let (fg, bg) = (fg as u8, bg as u8);
+        // Bright VGA colors (8-15) map to the ANSI 90-97 range
+        let fg_code = if fg >= 8 { 90 } else { 30 } + ANSI_COLORS[(fg & 7) as usize] as usize;
+        let bg_code = 40 + ANSI_COLORS[(bg & 7) as usize] as usize;
+        self.send_str("\x1b[");
+        self.send_number(fg_code);
+        self.send(b';');
+        self.send_number(bg_code);
+        self.send(b'm');
+    }
+
+    fn clear(&mut self) {
+        self.send_str("\x1b[2J\x1b[H");
+        self.row = 0;
+        self.column = 0;
+    }
+
+    fn backspace(&mut self) {
+        if self.column > 0 {
+            self.send_str("\x08 \x08");
+            self.column -= 1;
+        }
+    }
+
+    fn set_cursor_visible(&mut self, visible: bool) {
+        self.send_str(if visible { "\x1b[?25h" } else { "\x1b[?25l" });
+    }
+
+    fn set_cursor_position(&mut self, row: usize, col: usize) {
+        self.send_str("\x1b[");
+        self.send_number(row + 1);
+        self.send(b';');
+        self.send_number(col + 1);
+        self.send(b'H');
+        self.row = row;
+        self.column = col;
+    }
+
+    fn cursor_position(&self) -> (usize, usize) {
+        (self.row, self.column)
+    }
+
+    fn size(&self) -> (usize, usize) {
+        (25, 80)
+    }
+}
+
+static mut COM1_PORT: SerialPort = SerialPort::new(COM1);
+
+pub fn port() -> &'static mut SerialPort {
+    unsafe { &mut *core::ptr::addr_of_mut!(COM1_PORT) }
+}
+
+pub fn init() -> bool {
+    port().init()
+}
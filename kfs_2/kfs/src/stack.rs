@@ -0,0 +1,165 @@
+// stack.rs - Kernel stack inspection: bounds, EBP frame walk and high-water mark
+//
+// boot.asm fills the 64 KiB boot stack with STACK_FILL before switching to it,
+// and thread::spawn does the same for thread stacks, so the lowest dword that
+// no longer holds the pattern marks the deepest the stack has ever grown.
+// The target spec keeps frame pointers in every function, which makes [ebp]
+// the caller's ebp and [ebp + 4] the return address.
+
+use core::arch::asm;
+use crate::nps::{self, Builtin};
+use crate::symbols;
+use crate::thread;
+
+// Must match boot.asm
+pub const STACK_FILL: u32 = 0x5741434B;
+
+const DEFAULT_DUMP_COUNT: usize = 16;
+const MAX_FRAMES: usize = 32;
+
+// A stack and the registers to start inspecting it from
+#[derive(Copy, Clone)]
+pub struct Stack {
+    pub bottom: u32,
+    pub top: u32,
+    pub esp: u32,
+    pub ebp: u32,
+}
+
+impl Stack {
+    fn contains(&self, addr: u32, len: u32) -> bool {
+        addr >= self.bottom && addr.saturating_add(len) <= self.top
+    }
+
+    // Bytes between the top and the deepest dword ever written
+    pub fn high_water(&self) -> u32 {
+        let mut addr = self.bottom;
+        while addr < self.top && unsafe { *(addr as *const u32) } == STACK_FILL {
+            addr += 4;
+        }
+        self.top - addr
+    }
+
+    // Call `f` with (frame pointer, return address) from the innermost frame out
+    pub fn walk(&self, mut f: impl FnMut(u32, u32)) {
+        let mut ebp = self.ebp;
+        for _ in 0..MAX_FRAMES {
+            if ebp == 0 || !ebp.is_multiple_of(4) || !self.contains(ebp, 8) {
+                break;
+            }
+            let (next, ret) = unsafe { (*(ebp as *const u32), *((ebp + 4) as *const u32)) };
+            if ret == 0 {
+                break;
+            }
+            f(ebp, ret);
+            // Callers sit higher up; anything else means a corrupt chain
+            if next <= ebp {
+                break;
+            }
+            ebp = next;
+        }
+    }
+}
+
+// The running thread's stack as seen from the caller
+#[inline(always)]
+pub fn current() -> Stack {
+    let esp: u32;
+    let ebp: u32;
+    unsafe {
+        asm!(
+            "mov {0}, esp",
+            "mov {1}, ebp",
+            out(reg) esp,
+            out(reg) ebp,
+        );
+    }
+    let (bottom, top) = thread::current_stack();
+    Stack { bottom, top, esp, ebp }
+}
+
+// Offset of the saved ebp above a switched-out thread's esp (see thread.asm)
+const SAVED_EBP: u32 = 16;
+
+// Stack of thread `tid`; a switched-out thread is walked from where it called switch_context
+pub fn thread_stack(tid: u32) -> Option<Stack> {
+    if tid == thread::current() {
+        return Some(current());
+    }
+    let (bottom, top, esp) = thread::saved_stack(tid)?;
+    let ebp = unsafe { *((esp + SAVED_EBP) as *const u32) };
+    Some(Stack { bottom, top, esp, ebp })
+}
+
+fn print_address(addr: u32) {
+    match symbols::lookup(addr) {
+        Some(symbol) => println!("0x{:08x} <{}+0x{:x}>", addr, symbol.demangled(), addr - symbol.addr),
+        None => println!("0x{:08x}", addr),
+    }
+}
+
+// Print bounds, usage, the call chain and `count` dwords from ESP
+pub fn print_stack(stack: &Stack, count: usize) {
+    let size = stack.top - stack.bottom;
+    let used = stack.top.saturating_sub(stack.esp);
+    let high_water = stack.high_water();
+
+    println!("=== Kernel Stack Information ===");
+    println!("Bounds: 0x{:08x}-0x{:08x} ({} KiB)", stack.bottom, stack.top, size / 1024);
+    println!("ESP: 0x{:08x}  EBP: 0x{:08x}", stack.esp, stack.ebp);
+    println!("Used: {} bytes now, {} bytes at most ({}% of the stack)",
+             used, high_water, high_water * 100 / size);
+    println!();
+
+    println!("Call trace:");
+    let mut depth = 0;
+    stack.walk(|ebp, ret| {
+        print!("  #{:<2} [ebp 0x{:08x}] ", depth, ebp);
+        print_address(ret);
+        depth += 1;
+    });
+    if depth == 0 {
+        println!("  (no frames)");
+    }
+    println!();
+
+    println!("Stack contents (top {} dwords):", count);
+    for i in 0..count as u32 {
+        let addr = stack.esp + i * 4;
+        if !stack.contains(addr, 4) {
+            break;
+        }
+        let value = unsafe { *(addr as *const u32) };
+        print!("  0x{:08x}: ", addr);
+        print_address(value);
+    }
+}
+
+fn cmd_stack(argv: &[&str]) {
+    let count = match argv.get(1).map(|a| nps::parse_number(a)) {
+        None => Some(DEFAULT_DUMP_COUNT),
+        Some(Some(count)) if count > 0 => Some(count as usize),
+        Some(_) => None,
+    };
+    let tid = match argv.get(2) {
+        Some(arg) => nps::parse_number(arg),
+        None => Some(thread::current()),
+    };
+    let (Some(count), Some(tid)) = (count, tid) else {
+        println!("Usage: stack [count] [tid]");
+        return;
+    };
+
+    match thread_stack(tid) {
+        Some(stack) => print_stack(&stack, count),
+        None => println!("stack: no thread {}", tid),
+    }
+}
+
+static COMMANDS: [Builtin; 1] = [
+    Builtin { name: "stack", usage: "stack [count] [tid]", help: "Print a stack with its call trace", handler: cmd_stack, complete: None },
+];
+
+pub fn register_commands() {
+    nps::register_all(&COMMANDS);
+}
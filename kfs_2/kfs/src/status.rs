@@ -0,0 +1,99 @@
+// status.rs - Status bar on the top text row (clock, uptime, terminal, keyboard, memory)
+//
+// The VGA writer's scroll region starts below the bar, so normal output
+// never scrolls or clears it. Redrawn once a second from the timer interrupt.
+
+use core::fmt::{self, Write};
+use kfs_core::paging::PAGE_SIZE;
+use crate::fb;
+use crate::kb;
+use crate::keymap;
+use crate::multiboot;
+use crate::paging;
+use crate::pit;
+use crate::rtc;
+use crate::vga::{self, color_byte, Color, VGA_WIDTH};
+
+const STATUS_ROW: usize = 0;
+
+// Only one terminal exists until virtual terminals are implemented
+const ACTIVE_VT: usize = 1;
+
+static mut ENABLED: bool = false;
+
+extern "C" {
+    static _kernel_start: u8;
+    static _kernel_end: u8;
+}
+
+// Fixed size line buffer so the bar can be formatted without allocation
+struct Line {
+    buf: [u8; VGA_WIDTH],
+    len: usize,
+}
+
+impl Write for Line {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            if self.len < VGA_WIDTH {
+                self.buf[self.len] = byte;
+                self.len += 1;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Free frames once paging manages memory; before that, memory above 1 MiB
+// reported by the bootloader, minus what the kernel image occupies
+fn free_memory_kb() -> Option<u32> {
+    if paging::enabled() {
+        return Some(paging::free_frames() as u32 * (PAGE_SIZE / 1024));
+    }
+    let info = multiboot::info()?;
+    if info.flags & multiboot::INFO_MEMORY == 0 {
+        return None;
+    }
+    let kernel_kb = (&raw const _kernel_end as u32 - &raw const _kernel_start as u32) / 1024;
+    Some(info.mem_upper.saturating_sub(kernel_kb))
+}
+
+pub fn init() {
+    // The bar lives in text memory; the framebuffer console has no room reserved for it
+    if fb::active() {
+        return;
+    }
+    vga::writer().set_scroll_top(STATUS_ROW + 1);
+    unsafe {
+        ENABLED = true;
+    }
+    refresh();
+}
+
+pub fn refresh() {
+    if unsafe { !ENABLED } {
+        return;
+    }
+
+    let mut line = Line { buf: [b' '; VGA_WIDTH], len: 0 };
+    let now = rtc::now();
+    let uptime = pit::uptime_ms() / 1000;
+    let (caps, num, scroll) = kb::lock_state();
+
+    let _ = write!(line, " {:04}-{:02}-{:02} {:02}:{:02}:{:02} | up {}:{:02}:{:02} | VT{} | {} | {} {} {}",
+        now.year, now.month, now.day, now.hour, now.minute, now.second,
+        uptime / 3600, (uptime / 60) % 60, uptime % 60,
+        ACTIVE_VT, keymap::current().name,
+        if caps { "CAPS" } else { "caps" },
+        if num { "NUM" } else { "num" },
+        if scroll { "SCRL" } else { "scrl" });
+    if let Some(free) = free_memory_kb() {
+        let _ = write!(line, " | {} KiB free", free);
+    }
+
+    let color = color_byte(Color::Black, Color::LightGray);
+    let writer = vga::writer();
+    for (col, byte) in line.buf.iter().enumerate() {
+        writer.put_char_at(STATUS_ROW, col, *byte, color);
+    }
+}
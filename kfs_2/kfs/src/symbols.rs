@@ -0,0 +1,149 @@
+// symbols.rs - Kernel symbol lookup for annotating addresses
+//
+// When Multiboot flag bit 5 is set, GRUB hands over the kernel's ELF section
+// header table and has loaded every section, .symtab and .strtab included, so
+// the symbol table can be read straight from memory.
+
+use core::fmt;
+use core::mem::size_of;
+use crate::multiboot;
+
+const SHT_SYMTAB: u32 = 2;
+
+// Symbol types worth naming (untyped covers labels from the asm files)
+const STT_NOTYPE: u8 = 0;
+const STT_OBJECT: u8 = 1;
+const STT_FUNC: u8 = 2;
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct SectionHeader {
+    name: u32,
+    kind: u32,
+    flags: u32,
+    addr: u32,
+    offset: u32,
+    size: u32,
+    link: u32,
+    info: u32,
+    addralign: u32,
+    entsize: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct ElfSymbol {
+    name: u32,
+    value: u32,
+    size: u32,
+    info: u8,
+    other: u8,
+    shndx: u16,
+}
+
+#[derive(Copy, Clone)]
+pub struct Symbol {
+    pub name: &'static str,
+    pub addr: u32,
+    #[allow(dead_code)]
+    pub size: u32,
+}
+
+impl Symbol {
+    pub fn demangled(&self) -> Demangled {
+        Demangled(self.name)
+    }
+}
+
+// Symbol table and the address of its string table
+fn table() -> Option<(&'static [ElfSymbol], u32)> {
+    let info = multiboot::info()?;
+    if info.flags & multiboot::INFO_ELF_SHDR == 0 {
+        return None;
+    }
+    let [num, entry_size, addr, _] = info.syms;
+    if entry_size as usize != size_of::<SectionHeader>() || addr == 0 {
+        return None;
+    }
+
+    unsafe {
+        let headers = core::slice::from_raw_parts(addr as *const SectionHeader, num as usize);
+        let symtab = headers.iter().find(|h| h.kind == SHT_SYMTAB && h.addr != 0)?;
+        let strtab = headers.get(symtab.link as usize).filter(|h| h.addr != 0)?;
+        let count = symtab.size as usize / size_of::<ElfSymbol>();
+        Some((core::slice::from_raw_parts(symtab.addr as *const ElfSymbol, count), strtab.addr))
+    }
+}
+
+fn named(symbol: &ElfSymbol) -> bool {
+    let kind = symbol.info & 0xF;
+    symbol.name != 0 && symbol.value != 0 && matches!(kind, STT_NOTYPE | STT_OBJECT | STT_FUNC)
+}
+
+fn to_symbol(symbol: &ElfSymbol, strtab: u32) -> Symbol {
+    Symbol {
+        name: multiboot::c_str(strtab + symbol.name),
+        addr: symbol.value,
+        size: symbol.size,
+    }
+}
+
+// The function or object containing `addr`, or the closest label below it
+pub fn lookup(addr: u32) -> Option<Symbol> {
+    let (symbols, strtab) = table()?;
+    let mut best: Option<&ElfSymbol> = None;
+    for symbol in symbols.iter().filter(|s| named(s) && s.value <= addr) {
+        if symbol.size != 0 && addr - symbol.value >= symbol.size {
+            continue;
+        }
+        if best.is_none_or(|b| symbol.value > b.value) {
+            best = Some(symbol);
+        }
+    }
+    best.map(|s| to_symbol(s, strtab))
+}
+
+// The lowest symbol starting in start..end
+pub fn first_in(start: u32, end: u32) -> Option<Symbol> {
+    let (symbols, strtab) = table()?;
+    symbols
+        .iter()
+        .filter(|s| named(s) && s.value >= start && s.value < end)
+        .min_by_key(|s| s.value)
+        .map(|s| to_symbol(s, strtab))
+}
+
+// Prints legacy Rust mangled names (_ZN3kfs3nps8run_line17h0123456789abcdefE)
+// as kfs::nps::run_line; anything else is printed unchanged
+pub struct Demangled(&'static str);
+
+impl fmt::Display for Demangled {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let Some(mut rest) = self.0.strip_prefix("_ZN") else {
+            return f.write_str(self.0);
+        };
+
+        let mut first = true;
+        while let Some(digits) = rest.find(|c: char| !c.is_ascii_digit()).filter(|&n| n > 0) {
+            let len: usize = rest[..digits].parse().unwrap_or(0);
+            let Some(part) = rest.get(digits..digits + len) else {
+                break;
+            };
+            rest = &rest[digits + len..];
+
+            // The last component is a hash, not part of the path
+            if rest == "E" && part.len() == 17 && part.starts_with('h') {
+                break;
+            }
+            if !first {
+                f.write_str("::")?;
+            }
+            f.write_str(part)?;
+            first = false;
+        }
+        if first {
+            f.write_str(self.0)?;
+        }
+        Ok(())
+    }
+}
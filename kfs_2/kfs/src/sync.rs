@@ -0,0 +1,554 @@
+// sync.rs - Sleeping synchronization primitives built on the scheduler
+//
+// On a single CPU, turning interrupts off is all the locking these need
+// around their own state; threads that have to wait block in the scheduler
+// instead of spinning. Interrupt handlers may wake threads (WaitQueue::wake_*,
+// Semaphore::up) but never wait.
+//
+// Woken threads always check their condition again: a signal also wakes a
+// blocked thread, and another thread may get to a released mutex first.
+//
+// Debug builds remember which mutex every blocked thread waits for and panic
+// when a lock would close a cycle of threads waiting for each other.
+
+use core::cell::UnsafeCell;
+use core::marker::PhantomData;
+use core::ops::{Deref, DerefMut};
+use crate::idt;
+use crate::nps::{self, Builtin};
+use crate::pit;
+use crate::thread::{self, State, MAX_THREADS};
+
+struct Queue {
+    tids: [u32; MAX_THREADS],
+    len: usize,
+}
+
+impl Queue {
+    fn push(&mut self, tid: u32) {
+        // A thread waits in one queue at a time, so there is always room
+        if self.len < MAX_THREADS {
+            self.tids[self.len] = tid;
+            self.len += 1;
+        }
+    }
+
+    fn pop(&mut self) -> Option<u32> {
+        if self.len == 0 {
+            return None;
+        }
+        let tid = self.tids[0];
+        self.tids.copy_within(1..self.len, 0);
+        self.len -= 1;
+        Some(tid)
+    }
+
+    fn remove(&mut self, tid: u32) {
+        if let Some(i) = self.tids[..self.len].iter().position(|&t| t == tid) {
+            self.tids.copy_within(i + 1..self.len, i);
+            self.len -= 1;
+        }
+    }
+}
+
+// Threads waiting for something, woken in the order they started waiting
+pub struct WaitQueue {
+    queue: UnsafeCell<Queue>,
+}
+
+unsafe impl Sync for WaitQueue {}
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        WaitQueue { queue: UnsafeCell::new(Queue { tids: [0; MAX_THREADS], len: 0 }) }
+    }
+
+    // Only touched with interrupts disabled
+    #[allow(clippy::mut_from_ref)]
+    fn queue(&self) -> &mut Queue {
+        unsafe { &mut *self.queue.get() }
+    }
+
+    // Sleep until woken. Call with interrupts disabled after checking the
+    // condition to wait for, so a wake in between cannot be lost, and check
+    // it again afterwards.
+    pub fn wait(&self) {
+        let tid = thread::current();
+        self.queue().push(tid);
+        thread::block();
+        // Still queued if something else woke us
+        self.queue().remove(tid);
+    }
+
+    // Wake the longest waiting thread; false if none was asleep
+    pub fn wake_one(&self) -> bool {
+        let interrupts = idt::disable_interrupts();
+        let mut woken = false;
+        while let Some(tid) = self.queue().pop() {
+            // Threads already woken by something else recheck on their own
+            if thread::state(tid) == Some(State::Sleeping) {
+                thread::wake(tid);
+                woken = true;
+                break;
+            }
+        }
+        idt::restore_interrupts(interrupts);
+        woken
+    }
+
+    // Wake every waiting thread; returns how many were asleep
+    pub fn wake_all(&self) -> usize {
+        let interrupts = idt::disable_interrupts();
+        let mut woken = 0;
+        while let Some(tid) = self.queue().pop() {
+            if thread::state(tid) == Some(State::Sleeping) {
+                thread::wake(tid);
+                woken += 1;
+            }
+        }
+        idt::restore_interrupts(interrupts);
+        woken
+    }
+}
+
+struct Lock {
+    #[cfg_attr(not(debug_assertions), allow(dead_code))]
+    name: &'static str,         // For deadlock reports
+    owner: UnsafeCell<Option<u32>>,
+    waiters: WaitQueue,
+}
+
+impl Lock {
+    fn owner(&self) -> Option<u32> {
+        unsafe { *self.owner.get() }
+    }
+
+    fn set_owner(&self, owner: Option<u32>) {
+        unsafe {
+            *self.owner.get() = owner;
+        }
+    }
+
+    // Called with interrupts disabled
+    fn acquire(&self) {
+        let me = thread::current();
+        while self.owner().is_some() {
+            #[cfg(debug_assertions)]
+            deadlock::check(self, me);
+            #[cfg(debug_assertions)]
+            deadlock::set_waiting(me, Some(self));
+            self.waiters.wait();
+            #[cfg(debug_assertions)]
+            deadlock::set_waiting(me, None);
+        }
+        self.set_owner(Some(me));
+    }
+
+    // Called with interrupts disabled; the woken thread runs at the next switch
+    fn release(&self) {
+        self.set_owner(None);
+        self.waiters.wake_one();
+    }
+}
+
+// Mutual exclusion that puts contending threads to sleep. The owner is
+// tracked, for deadlock detection and for `owner()`.
+pub struct Mutex<T> {
+    lock: Lock,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Sync for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    pub const fn new(name: &'static str, value: T) -> Self {
+        Mutex { lock: Lock { name, owner: UnsafeCell::new(None), waiters: WaitQueue::new() }, data: UnsafeCell::new(value) }
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        let interrupts = idt::disable_interrupts();
+        self.lock.acquire();
+        idt::restore_interrupts(interrupts);
+        MutexGuard { mutex: self, _not_send: PhantomData }
+    }
+
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        let interrupts = idt::disable_interrupts();
+        let free = self.lock.owner().is_none();
+        if free {
+            self.lock.set_owner(Some(thread::current()));
+        }
+        idt::restore_interrupts(interrupts);
+        free.then_some(MutexGuard { mutex: self, _not_send: PhantomData })
+    }
+
+    // Thread holding the mutex
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn owner(&self) -> Option<u32> {
+        self.lock.owner()
+    }
+}
+
+// Access to a locked Mutex, unlocked on drop by the thread that locked it
+pub struct MutexGuard<'a, T> {
+    mutex: &'a Mutex<T>,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl<T> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<T> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<T> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        let interrupts = idt::disable_interrupts();
+        self.mutex.lock.release();
+        idt::restore_interrupts(interrupts);
+        // A higher priority waiter gets the mutex right away
+        thread::preempt();
+    }
+}
+
+// Counting semaphore
+pub struct Semaphore {
+    count: UnsafeCell<u32>,
+    waiters: WaitQueue,
+}
+
+unsafe impl Sync for Semaphore {}
+
+impl Semaphore {
+    pub const fn new(count: u32) -> Self {
+        Semaphore { count: UnsafeCell::new(count), waiters: WaitQueue::new() }
+    }
+
+    // Take one unit, sleeping until there is one
+    pub fn down(&self) {
+        let interrupts = idt::disable_interrupts();
+        unsafe {
+            while *self.count.get() == 0 {
+                self.waiters.wait();
+            }
+            *self.count.get() -= 1;
+        }
+        idt::restore_interrupts(interrupts);
+    }
+
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn try_down(&self) -> bool {
+        let interrupts = idt::disable_interrupts();
+        let taken = unsafe {
+            let count = &mut *self.count.get();
+            let taken = *count > 0;
+            if taken {
+                *count -= 1;
+            }
+            taken
+        };
+        idt::restore_interrupts(interrupts);
+        taken
+    }
+
+    // Give one unit back; also safe from interrupt handlers
+    pub fn up(&self) {
+        let interrupts = idt::disable_interrupts();
+        unsafe {
+            *self.count.get() += 1;
+        }
+        self.waiters.wake_one();
+        idt::restore_interrupts(interrupts);
+    }
+}
+
+// Condition variable, used together with a Mutex
+pub struct Condvar {
+    waiters: WaitQueue,
+}
+
+impl Condvar {
+    pub const fn new() -> Self {
+        Condvar { waiters: WaitQueue::new() }
+    }
+
+    // Unlock the mutex and sleep until notified, then lock it again. Wakeups
+    // can be spurious; see wait_while().
+    pub fn wait<'a, T>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        let mutex = guard.mutex;
+        // Unlocking and starting to wait must not be split by a switch, or
+        // a notify in between would be lost
+        let interrupts = idt::disable_interrupts();
+        core::mem::forget(guard);
+        mutex.lock.release();
+        self.waiters.wait();
+        mutex.lock.acquire();
+        idt::restore_interrupts(interrupts);
+        MutexGuard { mutex, _not_send: PhantomData }
+    }
+
+    // Wait for as long as `condition` holds
+    pub fn wait_while<'a, T>(&self, mut guard: MutexGuard<'a, T>, mut condition: impl FnMut(&mut T) -> bool) -> MutexGuard<'a, T> {
+        while condition(&mut guard) {
+            guard = self.wait(guard);
+        }
+        guard
+    }
+
+    pub fn notify_all(&self) {
+        self.waiters.wake_all();
+        thread::preempt();
+    }
+}
+
+// The dining philosophers, as a demo: forks are mutexes, and a semaphore
+// keeps one seat empty so that somebody can always pick up both forks
+const PHILOSOPHERS: usize = 5;
+
+struct Table {
+    dining: usize,
+    meals: [u32; PHILOSOPHERS],
+}
+
+static FORKS: [Mutex<()>; PHILOSOPHERS] = [const { Mutex::new("fork", ()) }; PHILOSOPHERS];
+static SEATS: Semaphore = Semaphore::new(PHILOSOPHERS as u32 - 1);
+static TABLE: Mutex<Table> = Mutex::new("table", Table { dining: 0, meals: [0; PHILOSOPHERS] });
+static FINISHED: Condvar = Condvar::new();
+static mut DINNER_END: u64 = 0;
+
+fn philosopher(seat: u32) {
+    let seat = seat as usize;
+    let mut meals = 0;
+    while pit::uptime_ms() < unsafe { DINNER_END } {
+        SEATS.down();
+        let left = FORKS[seat].lock();
+        let right = FORKS[(seat + 1) % PHILOSOPHERS].lock();
+        pit::sleep_ms(10 + 3 * seat as u64);
+        meals += 1;
+        drop(right);
+        drop(left);
+        SEATS.up();
+        pit::sleep_ms(5);
+    }
+    let mut table = TABLE.lock();
+    table.meals[seat] = meals;
+    table.dining -= 1;
+    FINISHED.notify_all();
+}
+
+fn cmd_dine(argv: &[&str]) {
+    let Some(seconds) = argv.get(1).map_or(Some(3), |a| nps::parse_number(a)) else {
+        println!("Usage: dine [seconds]");
+        return;
+    };
+    unsafe {
+        DINNER_END = pit::uptime_ms() + seconds as u64 * 1000;
+    }
+    *TABLE.lock() = Table { dining: 0, meals: [0; PHILOSOPHERS] };
+    for seat in 0..PHILOSOPHERS {
+        TABLE.lock().dining += 1;
+        match thread::spawn("philosopher", thread::PRIORITY_NORMAL, philosopher, seat as u32) {
+            Some(tid) => thread::detach(tid),
+            None => {
+                TABLE.lock().dining -= 1;
+                println!("dine: too many threads, philosopher {} stays home", seat);
+            }
+        }
+    }
+    let table = FINISHED.wait_while(TABLE.lock(), |table| table.dining > 0);
+    for (seat, meals) in table.meals.iter().enumerate() {
+        println!("philosopher {}: {} meals", seat, meals);
+    }
+}
+
+static COMMANDS: [Builtin; 1] = [
+    Builtin { name: "dine", usage: "dine [seconds]", help: "Run the dining philosophers on mutexes", handler: cmd_dine, complete: None },
+];
+
+pub fn register_commands() {
+    nps::register_all(&COMMANDS);
+}
+
+#[cfg(debug_assertions)]
+mod deadlock {
+    use super::Lock;
+    use crate::thread::MAX_THREADS;
+
+    // Mutex every blocked thread waits for
+    static mut WAITING: [Option<(u32, *const Lock)>; MAX_THREADS] = [None; MAX_THREADS];
+
+    fn waiting() -> &'static mut [Option<(u32, *const Lock)>; MAX_THREADS] {
+        unsafe { &mut *core::ptr::addr_of_mut!(WAITING) }
+    }
+
+    pub fn set_waiting(tid: u32, lock: Option<&Lock>) {
+        let waiting = waiting();
+        let slot = waiting.iter().position(|w| w.is_some_and(|(t, _)| t == tid));
+        match (slot, lock) {
+            (Some(slot), None) => waiting[slot] = None,
+            (Some(slot), Some(lock)) => waiting[slot] = Some((tid, lock)),
+            (None, Some(lock)) => {
+                if let Some(free) = waiting.iter_mut().find(|w| w.is_none()) {
+                    *free = Some((tid, lock));
+                }
+            }
+            (None, None) => {}
+        }
+    }
+
+    // Follow the owners from `lock`: each is either running or waits for
+    // another mutex. Coming back to `tid` means nobody in the chain can go on.
+    pub fn check(lock: &Lock, tid: u32) {
+        let mut next = lock as *const Lock;
+        for _ in 0..=MAX_THREADS {
+            let Some(owner) = (unsafe { (*next).owner() }) else { return };
+            if owner == tid {
+                let held = unsafe { (*next).name };
+                panic!("deadlock: thread {} waits for mutex {} while holding {}", tid, lock.name, held);
+            }
+            match waiting().iter().flatten().find(|(t, _)| *t == owner) {
+                Some(&(_, waits_for)) => next = waits_for,
+                None => return,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::sync::atomic::{AtomicU32, Ordering};
+
+    static COUNTER: Mutex<u32> = Mutex::new("counter", 0);
+    static ITEMS: Semaphore = Semaphore::new(0);
+    static READY: Mutex<bool> = Mutex::new("ready", false);
+    static CHANGED: Condvar = Condvar::new();
+    static QUEUE: WaitQueue = WaitQueue::new();
+    static WOKEN: AtomicU32 = AtomicU32::new(0);
+
+    fn increment(rounds: u32) {
+        for _ in 0..rounds {
+            let mut counter = COUNTER.lock();
+            let value = *counter;
+            // Give the other threads a chance to see the mutex taken
+            thread::yield_now();
+            *counter = value + 1;
+        }
+    }
+
+    fn produce(count: u32) {
+        for _ in 0..count {
+            pit::sleep_ms(5);
+            ITEMS.up();
+        }
+    }
+
+    fn set_ready(_arg: u32) {
+        pit::sleep_ms(10);
+        *READY.lock() = true;
+        CHANGED.notify_all();
+    }
+
+    fn wait_in_queue(_arg: u32) {
+        let interrupts = idt::disable_interrupts();
+        QUEUE.wait();
+        idt::restore_interrupts(interrupts);
+        WOKEN.fetch_add(1, Ordering::SeqCst);
+    }
+
+    #[test_case]
+    fn mutex_serializes_threads() {
+        *COUNTER.lock() = 0;
+        let tids = [0; 3].map(|_| thread::spawn("inc", thread::PRIORITY_NORMAL, increment, 20).unwrap());
+        for tid in tids {
+            thread::join(tid);
+        }
+        assert_eq!(*COUNTER.lock(), 60);
+        assert_eq!(COUNTER.owner(), None);
+        let guard = COUNTER.try_lock().unwrap();
+        assert_eq!(COUNTER.owner(), Some(thread::current()));
+        assert!(COUNTER.try_lock().is_none());
+        drop(guard);
+    }
+
+    #[test_case]
+    fn semaphore_waits_for_up() {
+        let tid = thread::spawn("producer", thread::PRIORITY_NORMAL, produce, 3).unwrap();
+        for _ in 0..3 {
+            ITEMS.down();
+        }
+        assert!(!ITEMS.try_down());
+        thread::join(tid);
+    }
+
+    #[test_case]
+    fn condvar_wakes_waiters() {
+        *READY.lock() = false;
+        let tid = thread::spawn("notify", thread::PRIORITY_NORMAL, set_ready, 0).unwrap();
+        let ready = CHANGED.wait_while(READY.lock(), |ready| !*ready);
+        assert!(*ready);
+        drop(ready);
+        thread::join(tid);
+    }
+
+    #[test_case]
+    fn wait_queue_wakes_one_or_all() {
+        WOKEN.store(0, Ordering::SeqCst);
+        let tids = [0; 3].map(|_| thread::spawn("waiter", thread::PRIORITY_NORMAL, wait_in_queue, 0).unwrap());
+        // Let all three block
+        pit::sleep_ms(20);
+        assert!(QUEUE.wake_one());
+        pit::sleep_ms(20);
+        assert_eq!(WOKEN.load(Ordering::SeqCst), 1);
+        assert_eq!(QUEUE.wake_all(), 2);
+        for tid in tids {
+            thread::join(tid);
+        }
+        assert_eq!(WOKEN.load(Ordering::SeqCst), 3);
+        assert!(!QUEUE.wake_one());
+    }
+
+    // Debug builds only: the mutexes stay locked, so each test has its own
+    #[cfg(debug_assertions)]
+    mod deadlock {
+        use super::*;
+        use crate::testing::ShouldPanicWith;
+
+        static RELOCKED: Mutex<()> = Mutex::new("relocked", ());
+        static FIRST: Mutex<()> = Mutex::new("first", ());
+        static SECOND: Mutex<()> = Mutex::new("second", ());
+
+        fn relock() {
+            let _held = RELOCKED.lock();
+            let _again = RELOCKED.lock();
+        }
+        #[test_case]
+        const RELOCK: ShouldPanicWith = ShouldPanicWith("sync::tests::deadlock::relock", "deadlock:", relock);
+
+        // Takes FIRST, then blocks on SECOND for good
+        fn lock_first_then_second(_arg: u32) {
+            let _first = FIRST.lock();
+            let _second = SECOND.lock();
+        }
+
+        fn opposite_order() {
+            let _second = SECOND.lock();
+            let tid = thread::spawn("first", thread::PRIORITY_NORMAL, lock_first_then_second, 0).unwrap();
+            while FIRST.owner() != Some(tid) || thread::state(tid) != Some(State::Sleeping) {
+                pit::sleep_ms(5);
+            }
+            let _first = FIRST.lock();
+        }
+        #[test_case]
+        const OPPOSITE_ORDER: ShouldPanicWith = ShouldPanicWith("sync::tests::deadlock::opposite_order", "deadlock:", opposite_order);
+    }
+}
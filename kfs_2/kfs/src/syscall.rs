@@ -0,0 +1,484 @@
+// syscall.rs - int 0x80 system calls
+//
+// The ABI is the one of i386 Linux: eax holds the number, ebx, ecx, edx, esi
+// and edi the arguments, and the result comes back in eax, with errors as
+// negative errno values. syscall.asm saves the user registers as a TrapFrame
+// and rust_syscall() looks the number up in SYSCALLS. Pending signals are
+// delivered on the way back.
+//
+// Files, the heap and mmap()ed memory belong to the calling process (see
+// process.rs). The heap and the mmap area sit at the bottom of the user
+// window and are mapped as soon as they are handed out.
+
+use kfs_core::paging::{align_up, PAGE_SIZE};
+use kfs_core::signal::WaitStatus;
+use crate::console;
+use crate::idt::TrapFrame;
+use crate::kb;
+use crate::multiboot;
+use crate::nps::{self, Builtin};
+use crate::paging::{self, USER_BASE, USER_STACK_MAX, USER_TOP};
+use crate::pit;
+use crate::process::{self, Process};
+
+pub const SYS_EXIT: u32 = 1;
+pub const SYS_FORK: u32 = 2;
+pub const SYS_READ: u32 = 3;
+pub const SYS_WRITE: u32 = 4;
+pub const SYS_OPEN: u32 = 5;
+pub const SYS_CLOSE: u32 = 6;
+pub const SYS_WAITPID: u32 = 7;
+pub const SYS_GETPID: u32 = 20;
+pub const SYS_SETUID: u32 = 23;
+pub const SYS_GETUID: u32 = 24;
+pub const SYS_KILL: u32 = 37;
+pub const SYS_BRK: u32 = 45;
+pub const SYS_SIGNAL: u32 = 48;
+pub const SYS_GETPPID: u32 = 64;
+pub const SYS_MMAP: u32 = 90;
+pub const SYS_SIGRETURN: u32 = 119;
+pub const SYS_NANOSLEEP: u32 = 162;
+
+pub const EPERM: i32 = 1;
+pub const ENOENT: i32 = 2;
+pub const ESRCH: i32 = 3;
+pub const EINTR: i32 = 4;
+pub const EBADF: i32 = 9;
+pub const ECHILD: i32 = 10;
+pub const EAGAIN: i32 = 11;
+pub const ENOMEM: i32 = 12;
+pub const EFAULT: i32 = 14;
+pub const EINVAL: i32 = 22;
+pub const EMFILE: i32 = 24;
+pub const EROFS: i32 = 30;
+pub const ENOSYS: i32 = 38;
+
+pub const MAX_FILES: usize = 8;
+const PATH_MAX: u32 = 256;
+const MAP_ANONYMOUS: u32 = 0x20;
+
+// User window layout: program image and heap, mmap area, then the stack
+// (paging.rs). Programs built into the kernel have no image in the window.
+pub const HEAP_START: u32 = USER_BASE;
+const HEAP_MAX: u32 = 1024 * 1024;
+pub const MMAP_START: u32 = HEAP_START + HEAP_MAX;
+const MMAP_END: u32 = USER_TOP - USER_STACK_MAX;
+
+struct Syscall {
+    number: u32,
+    name: &'static str,
+    args: usize,
+    handler: fn(&mut TrapFrame, &[u32; 5]) -> i32,
+}
+
+static SYSCALLS: [Syscall; 17] = [
+    Syscall { number: SYS_EXIT, name: "exit", args: 1, handler: sys_exit },
+    Syscall { number: SYS_FORK, name: "fork", args: 0, handler: sys_fork },
+    Syscall { number: SYS_READ, name: "read", args: 3, handler: sys_read },
+    Syscall { number: SYS_WRITE, name: "write", args: 3, handler: sys_write },
+    Syscall { number: SYS_OPEN, name: "open", args: 3, handler: sys_open },
+    Syscall { number: SYS_CLOSE, name: "close", args: 1, handler: sys_close },
+    Syscall { number: SYS_WAITPID, name: "waitpid", args: 3, handler: sys_waitpid },
+    Syscall { number: SYS_GETPID, name: "getpid", args: 0, handler: sys_getpid },
+    Syscall { number: SYS_SETUID, name: "setuid", args: 1, handler: sys_setuid },
+    Syscall { number: SYS_GETUID, name: "getuid", args: 0, handler: sys_getuid },
+    Syscall { number: SYS_KILL, name: "kill", args: 2, handler: sys_kill },
+    Syscall { number: SYS_BRK, name: "brk", args: 1, handler: sys_brk },
+    Syscall { number: SYS_SIGNAL, name: "signal", args: 2, handler: sys_signal },
+    Syscall { number: SYS_GETPPID, name: "getppid", args: 0, handler: sys_getppid },
+    Syscall { number: SYS_MMAP, name: "mmap", args: 1, handler: sys_mmap },
+    Syscall { number: SYS_SIGRETURN, name: "sigreturn", args: 0, handler: sys_sigreturn },
+    Syscall { number: SYS_NANOSLEEP, name: "nanosleep", args: 2, handler: sys_nanosleep },
+];
+
+#[derive(Copy, Clone)]
+pub enum File {
+    Tty,
+    Module { data: &'static [u8], offset: usize },
+}
+
+pub const STDIO: [Option<File>; MAX_FILES] = {
+    let mut files = [None; MAX_FILES];
+    files[0] = Some(File::Tty);
+    files[1] = Some(File::Tty);
+    files[2] = Some(File::Tty);
+    files
+};
+
+static mut STRACE: bool = false;
+
+// strace, strace=on or strace=off
+fn setup_strace(value: Option<&str>) -> bool {
+    unsafe {
+        match value {
+            Some("on") | None => STRACE = true,
+            Some("off") => STRACE = false,
+            _ => return false,
+        }
+    }
+    true
+}
+kernel_param!("strace", "Log every system call: on or off", setup_strace);
+
+#[no_mangle]
+pub extern "C" fn rust_syscall(frame: &mut TrapFrame) {
+    let number = frame.eax;
+    let args = [frame.ebx, frame.ecx, frame.edx, frame.esi, frame.edi];
+    let Some(call) = SYSCALLS.iter().find(|s| s.number == number) else {
+        if strace_enabled() {
+            printk!(console::LOG_INFO, "strace: syscall_{}() = -{}\n", number, ENOSYS);
+        }
+        frame.eax = (-ENOSYS) as u32;
+        return;
+    };
+
+    if strace_enabled() {
+        printk!(console::LOG_INFO, "strace: {}(", call.name);
+        for (i, arg) in args[..call.args].iter().enumerate() {
+            printk!(console::LOG_INFO, "{}{:#x}", if i == 0 { "" } else { ", " }, arg);
+        }
+        // exit does not return, so print its result up front
+        if number == SYS_EXIT {
+            printk!(console::LOG_INFO, ") = ?\n");
+        }
+    }
+    let result = (call.handler)(frame, &args);
+    if strace_enabled() {
+        printk!(console::LOG_INFO, ") = {}\n", result);
+    }
+    frame.eax = result as u32;
+    process::deliver_signals(frame);
+}
+
+fn strace_enabled() -> bool {
+    unsafe { STRACE }
+}
+
+// Only processes make system calls; int 0x80 is not reachable otherwise
+fn caller() -> &'static mut Process {
+    process::current().expect("system call outside a process")
+}
+
+// Buffers must be in the caller's own user window: a pointer into the
+// kernel is -EFAULT, however valid it is for us
+fn user_slice(addr: u32, len: u32) -> Option<&'static [u8]> {
+    if !paging::is_user_mapped(addr, len) {
+        return None;
+    }
+    Some(unsafe { core::slice::from_raw_parts(addr as *const u8, len as usize) })
+}
+
+fn user_slice_mut(addr: u32, len: u32) -> Option<&'static mut [u8]> {
+    if !paging::user_writable(addr, len) {
+        return None;
+    }
+    Some(unsafe { core::slice::from_raw_parts_mut(addr as *mut u8, len as usize) })
+}
+
+// NUL-terminated string from user memory
+fn user_str(addr: u32) -> Result<&'static str, i32> {
+    for len in 0..PATH_MAX {
+        let byte = user_slice(addr + len, 1).ok_or(EFAULT)?[0];
+        if byte == 0 {
+            let bytes = user_slice(addr, len).ok_or(EFAULT)?;
+            return core::str::from_utf8(bytes).map_err(|_| ENOENT);
+        }
+    }
+    Err(EINVAL)
+}
+
+fn file(fd: u32) -> Option<&'static mut File> {
+    caller().files.get_mut(fd as usize)?.as_mut()
+}
+
+fn sys_exit(_frame: &mut TrapFrame, args: &[u32; 5]) -> i32 {
+    process::exit(WaitStatus::Exited(args[0] as u8))
+}
+
+fn sys_fork(frame: &mut TrapFrame, _args: &[u32; 5]) -> i32 {
+    match process::fork(frame) {
+        Ok(pid) => pid as i32,
+        Err(errno) => -errno,
+    }
+}
+
+// Line-buffered terminal input: echo, backspace, ^D for end of file.
+// A signal ends the read early, or with EINTR if nothing was read yet.
+fn read_tty(buf: &mut [u8]) -> i32 {
+    let mut count = 0;
+    while count < buf.len() {
+        let Some(byte) = kb::getchar_until(process::signal_pending) else {
+            return if count == 0 { -EINTR } else { count as i32 };
+        };
+        match byte {
+            0x04 => break,
+            0x08 => {
+                if count > 0 {
+                    count -= 1;
+                    console::backspace();
+                }
+            }
+            byte => {
+                buf[count] = byte;
+                count += 1;
+                print!("{}", byte as char);
+                if byte == b'\n' {
+                    break;
+                }
+            }
+        }
+    }
+    count as i32
+}
+
+fn sys_read(_frame: &mut TrapFrame, args: &[u32; 5]) -> i32 {
+    let Some(file) = file(args[0]) else { return -EBADF };
+    let Some(buf) = user_slice_mut(args[1], args[2]) else { return -EFAULT };
+    match file {
+        File::Tty => read_tty(buf),
+        File::Module { data, offset } => {
+            let count = buf.len().min(data.len() - *offset);
+            buf[..count].copy_from_slice(&data[*offset..*offset + count]);
+            *offset += count;
+            count as i32
+        }
+    }
+}
+
+fn sys_write(_frame: &mut TrapFrame, args: &[u32; 5]) -> i32 {
+    let Some(file) = file(args[0]) else { return -EBADF };
+    let Some(buf) = user_slice(args[1], args[2]) else { return -EFAULT };
+    match file {
+        File::Tty => {
+            for &byte in buf {
+                print!("{}", byte as char);
+            }
+            buf.len() as i32
+        }
+        File::Module { .. } => -EBADF,
+    }
+}
+
+// Boot modules are the only files; they can be opened read-only
+fn sys_open(_frame: &mut TrapFrame, args: &[u32; 5]) -> i32 {
+    let path = match user_str(args[0]) {
+        Ok(path) => path,
+        Err(errno) => return -errno,
+    };
+    if args[1] & 0x3 != 0 {
+        return -EROFS;
+    }
+    let Some(module) = multiboot::find_module(path) else { return -ENOENT };
+    let files = &mut caller().files;
+    match files.iter().position(|f| f.is_none()) {
+        Some(fd) => {
+            files[fd] = Some(File::Module { data: module.data(), offset: 0 });
+            fd as i32
+        }
+        None => -EMFILE,
+    }
+}
+
+fn sys_close(_frame: &mut TrapFrame, args: &[u32; 5]) -> i32 {
+    match caller().files.get_mut(args[0] as usize) {
+        Some(slot @ Some(_)) => {
+            *slot = None;
+            0
+        }
+        _ => -EBADF,
+    }
+}
+
+// waitpid(pid or -1, status pointer or 0, options)
+fn sys_waitpid(_frame: &mut TrapFrame, args: &[u32; 5]) -> i32 {
+    if args[1] != 0 && user_slice_mut(args[1], 4).is_none() {
+        return -EFAULT;
+    }
+    match process::wait(args[0] as i32, args[2]) {
+        Ok(Some((pid, status))) => {
+            if let Some(out) = user_slice_mut(args[1], 4).filter(|_| args[1] != 0) {
+                out.copy_from_slice(&status.encode().to_le_bytes());
+            }
+            pid as i32
+        }
+        Ok(None) => 0,
+        Err(errno) => -errno,
+    }
+}
+
+fn sys_getpid(_frame: &mut TrapFrame, _args: &[u32; 5]) -> i32 {
+    caller().pid as i32
+}
+
+fn sys_getppid(_frame: &mut TrapFrame, _args: &[u32; 5]) -> i32 {
+    caller().parent as i32
+}
+
+fn sys_getuid(_frame: &mut TrapFrame, _args: &[u32; 5]) -> i32 {
+    caller().uid as i32
+}
+
+// Root may become anyone; everybody else can only keep their uid
+fn sys_setuid(_frame: &mut TrapFrame, args: &[u32; 5]) -> i32 {
+    let process = caller();
+    if process.uid != 0 && process.uid != args[0] {
+        return -EPERM;
+    }
+    process.uid = args[0];
+    0
+}
+
+// Single processes only: no process groups for pid 0 or negative pids
+fn sys_kill(_frame: &mut TrapFrame, args: &[u32; 5]) -> i32 {
+    match process::kill(args[0], args[1]) {
+        Ok(()) => 0,
+        Err(errno) => -errno,
+    }
+}
+
+// signal(signal, handler): returns the previous handler
+fn sys_signal(_frame: &mut TrapFrame, args: &[u32; 5]) -> i32 {
+    match process::set_handler(args[0], args[1]) {
+        Ok(old) => old as i32,
+        Err(errno) => -errno,
+    }
+}
+
+fn sys_sigreturn(frame: &mut TrapFrame, _args: &[u32; 5]) -> i32 {
+    process::sigreturn(frame)
+}
+
+// Map (or unmap) whole pages covering start..end of the user window
+fn map_range(start: u32, end: u32) -> bool {
+    let space = caller().space().unwrap();
+    let mut page = start;
+    while page < end {
+        if space.map(page, true).is_none() {
+            unmap_range(start, page);
+            return false;
+        }
+        page += PAGE_SIZE;
+    }
+    true
+}
+
+fn unmap_range(start: u32, end: u32) {
+    let space = caller().space().unwrap();
+    for page in (start..end).step_by(PAGE_SIZE as usize) {
+        space.unmap(page);
+    }
+}
+
+// Returns the new break, or the old one if it cannot move (as Linux does)
+fn sys_brk(_frame: &mut TrapFrame, args: &[u32; 5]) -> i32 {
+    let process = caller();
+    let (old, wanted) = (process.brk, args[0]);
+    if (process.heap_start..=MMAP_START).contains(&wanted) {
+        let mapped_end = |brk: u32| align_up(brk).unwrap();
+        if wanted > old && !map_range(mapped_end(old), mapped_end(wanted)) {
+            return old as i32;
+        }
+        unmap_range(mapped_end(wanted), mapped_end(old));
+        process.brk = wanted;
+    }
+    process.brk as i32
+}
+
+// old_mmap: ebx points to {addr, len, prot, flags, fd, offset}
+fn sys_mmap(_frame: &mut TrapFrame, args: &[u32; 5]) -> i32 {
+    let Some(bytes) = user_slice(args[0], 24) else { return -EFAULT };
+    let field = |i: usize| u32::from_le_bytes([bytes[i * 4], bytes[i * 4 + 1], bytes[i * 4 + 2], bytes[i * 4 + 3]]);
+    let (len, flags) = (field(1), field(3));
+    if len == 0 || flags & MAP_ANONYMOUS == 0 {
+        return -EINVAL;
+    }
+    let process = caller();
+    let addr = process.mmap_next;
+    let Some(end) = align_up(len).and_then(|len| addr.checked_add(len)) else { return -ENOMEM };
+    if end > MMAP_END || !map_range(addr, end) {
+        return -ENOMEM;
+    }
+    process.mmap_next = end;
+    addr as i32
+}
+
+// ebx points to {seconds, nanoseconds}; a signal cuts the sleep short
+fn sys_nanosleep(_frame: &mut TrapFrame, args: &[u32; 5]) -> i32 {
+    let Some(bytes) = user_slice(args[0], 8) else { return -EFAULT };
+    let seconds = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    let nanoseconds = u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
+    if nanoseconds >= 1_000_000_000 {
+        return -EINVAL;
+    }
+    let ms = seconds as u64 * 1000 + nanoseconds as u64 / 1_000_000;
+    if !pit::sleep_ms_until(ms, process::signal_pending) {
+        return -EINTR;
+    }
+    0
+}
+
+fn cmd_strace(argv: &[&str]) {
+    match argv.get(1).copied() {
+        None => println!("strace is {}", if strace_enabled() { "on" } else { "off" }),
+        Some(value @ ("on" | "off")) => {
+            setup_strace(Some(value));
+        }
+        Some(_) => println!("Usage: strace [on|off]"),
+    }
+}
+
+fn complete_strace(arg: usize, _prefix: &str, add: &mut dyn FnMut(&str)) {
+    if arg == 1 {
+        add("on");
+        add("off");
+    }
+}
+
+static COMMANDS: [Builtin; 1] = [
+    Builtin { name: "strace", usage: "strace [on|off]", help: "Log system calls made by user programs", handler: cmd_strace, complete: Some(complete_strace) },
+];
+
+pub fn register_commands() {
+    nps::register_all(&COMMANDS);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::user::{run_function, Exit};
+
+    // In user.asm
+    extern "C" {
+        fn user_heap_and_mmap();
+        fn user_files();
+        fn user_kernel_pointers();
+        fn user_exits_with_seven();
+        fn user_exit_and_kill();
+        fn user_drop_privileges();
+    }
+
+    #[test_case]
+    fn brk_and_mmap_hand_out_memory() {
+        assert_eq!(run_function(user_heap_and_mmap), Exit::Code(0));
+    }
+
+    #[test_case]
+    fn bad_arguments_give_errno() {
+        assert_eq!(run_function(user_files), Exit::Code(0));
+    }
+
+    #[test_case]
+    fn kernel_pointers_are_rejected() {
+        assert_eq!(run_function(user_kernel_pointers), Exit::Code(0));
+    }
+
+    #[test_case]
+    fn exit_and_kill_end_the_program() {
+        assert_eq!(run_function(user_exits_with_seven), Exit::Code(7));
+        assert_eq!(run_function(user_exit_and_kill), Exit::Killed("SIGTERM"));
+    }
+
+    #[test_case]
+    fn uids_limit_kill() {
+        assert_eq!(run_function(user_drop_privileges), Exit::Code(0));
+    }
+}
@@ -0,0 +1,265 @@
+// testing.rs - In-kernel test runner for `cargo test` builds
+//
+// Functions marked #[test_case] are collected by the compiler and run from
+// kernel_main after the normal boot sequence. Results go to COM1 and the run
+// ends by writing to QEMU's isa-debug-exit device, so a headless QEMU exits
+// with a status telling whether everything passed (see qemu-test.sh):
+//
+//   qemu-system-i386 -kernel <test binary> -append test=all -serial stdio \
+//       -display none -device isa-debug-exit,iobase=0xf4,iosize=0x04
+//
+// A test fails by panicking. Since panics can't unwind, the panic handler
+// reports the failure and carries on with the next test from inside the handler.
+
+use core::fmt::{self, Write};
+use core::panic::PanicInfo;
+use crate::console::Console;
+use crate::idt;
+use crate::pit;
+use crate::serial;
+use crate::hal::{PortWrite, WriteOnlyPort};
+
+const QEMU_EXIT_PORT: WriteOnlyPort<u32> = WriteOnlyPort::new(0xF4);
+
+// A test that runs longer than this is considered hung
+const TIMEOUT_MS: u64 = 5000;
+
+#[derive(Copy, Clone)]
+#[repr(u32)]
+pub enum ExitCode {
+    Success = 0x10,     // QEMU exits with (0x10 << 1) | 1 = 33
+    Failed = 0x11,      // 35
+}
+
+pub fn exit_qemu(code: ExitCode) -> ! {
+    QEMU_EXIT_PORT.write(code as u32);
+    // Not running under QEMU (or the device is missing): just stop
+    loop {
+        unsafe {
+            core::arch::asm!("cli; hlt", options(nomem, nostack));
+        }
+    }
+}
+
+struct Serial;
+
+impl Write for Serial {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        serial::port().write_string(s);
+        Ok(())
+    }
+}
+
+pub fn _serial_print(args: fmt::Arguments) {
+    let _ = Serial.write_fmt(args);
+}
+
+// Test output goes to COM1 only, whatever console= says
+macro_rules! serial_println {
+    () => ($crate::testing::_serial_print(format_args!("\n")));
+    ($($arg:tt)*) => ($crate::testing::_serial_print(format_args!("{}\n", format_args!($($arg)*))));
+}
+
+pub trait Testable {
+    fn name(&self) -> &'static str;
+    fn should_panic(&self) -> bool {
+        false
+    }
+    // Text the panic message has to contain
+    fn expected_message(&self) -> Option<&'static str> {
+        None
+    }
+    fn run(&self);
+}
+
+impl<T: Fn()> Testable for T {
+    fn name(&self) -> &'static str {
+        core::any::type_name::<T>()
+    }
+
+    fn run(&self) {
+        self()
+    }
+}
+
+// A test that passes only if it panics:
+//   #[test_case]
+//   const OUT_OF_RANGE: ShouldPanic = ShouldPanic("gdt::entry_out_of_range", entry_out_of_range);
+pub struct ShouldPanic(pub &'static str, pub fn());
+
+impl Testable for ShouldPanic {
+    fn name(&self) -> &'static str {
+        self.0
+    }
+
+    fn should_panic(&self) -> bool {
+        true
+    }
+
+    fn run(&self) {
+        (self.1)()
+    }
+}
+
+// Like ShouldPanic, but the panic message has to contain the given text:
+//   #[test_case]
+//   const RELOCK: ShouldPanicWith = ShouldPanicWith("sync::tests::deadlock::relock", "deadlock:", relock);
+#[cfg_attr(not(debug_assertions), allow(dead_code))]
+pub struct ShouldPanicWith(pub &'static str, pub &'static str, pub fn());
+
+impl Testable for ShouldPanicWith {
+    fn name(&self) -> &'static str {
+        self.0
+    }
+
+    fn should_panic(&self) -> bool {
+        true
+    }
+
+    fn expected_message(&self) -> Option<&'static str> {
+        Some(self.1)
+    }
+
+    fn run(&self) {
+        (self.2)()
+    }
+}
+
+// Formats a panic message into a fixed buffer, cutting it off when full
+struct MessageBuffer {
+    bytes: [u8; 256],
+    len: usize,
+}
+
+impl Write for MessageBuffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let count = s.len().min(self.bytes.len() - self.len);
+        self.bytes[self.len..self.len + count].copy_from_slice(&s.as_bytes()[..count]);
+        self.len += count;
+        Ok(())
+    }
+}
+
+fn message_contains(info: &PanicInfo, expected: &str) -> bool {
+    let mut buffer = MessageBuffer { bytes: [0; 256], len: 0 };
+    let _ = write!(buffer, "{}", info.message());
+    buffer.bytes[..buffer.len].windows(expected.len()).any(|w| w == expected.as_bytes())
+}
+
+// State of the run, kept in statics so the panic handler can resume it
+static mut TESTS: Option<&[&dyn Testable]> = None;
+static mut CURRENT: usize = 0;
+static mut PASSED: usize = 0;
+static mut FAILED: usize = 0;
+static mut DEADLINE: Option<u64> = None;
+
+// test=all (the default) or test=<substring of the test names>
+static mut FILTER: Option<&'static str> = None;
+
+fn setup_test(value: Option<&'static str>) -> bool {
+    match value {
+        Some("all") => true,
+        Some(filter) if !filter.is_empty() => {
+            unsafe {
+                FILTER = Some(filter);
+            }
+            true
+        }
+        _ => false,
+    }
+}
+kernel_param!("test", "Tests to run: all or a name filter (test builds only)", setup_test);
+
+fn selected(test: &dyn Testable) -> bool {
+    unsafe { FILTER.is_none_or(|filter| test.name().contains(filter)) }
+}
+
+pub fn runner(tests: &[&dyn Testable]) {
+    // The slice lives in test_main's frame, which is never unwound
+    let tests: &'static [&'static dyn Testable] = unsafe { core::mem::transmute(tests) };
+    let count = tests.iter().filter(|t| selected(**t)).count();
+    serial_println!("Running {} tests", count);
+    unsafe {
+        TESTS = Some(tests);
+    }
+    run_from(0);
+}
+
+// Run tests[start..], then report and exit
+fn run_from(start: usize) -> ! {
+    let tests = unsafe { TESTS.unwrap_or(&[]) };
+    for (i, test) in tests.iter().enumerate().skip(start) {
+        if !selected(*test) {
+            continue;
+        }
+        unsafe {
+            CURRENT = i;
+            DEADLINE = Some(pit::ticks() + TIMEOUT_MS * pit::hz() as u64 / 1000);
+        }
+        _serial_print(format_args!("{}... ", test.name()));
+        test.run();
+        unsafe {
+            DEADLINE = None;
+        }
+        if test.should_panic() {
+            serial_println!("[failed] (did not panic)");
+            unsafe { FAILED += 1 };
+        } else {
+            serial_println!("[ok]");
+            unsafe { PASSED += 1 };
+        }
+    }
+    finish()
+}
+
+fn finish() -> ! {
+    let (passed, failed) = unsafe { (PASSED, FAILED) };
+    serial_println!();
+    serial_println!("test result: {}. {} passed; {} failed", if failed == 0 { "ok" } else { "FAILED" }, passed, failed);
+    exit_qemu(if failed == 0 { ExitCode::Success } else { ExitCode::Failed })
+}
+
+// Called by the panic handler in test builds
+pub fn panicked(info: &PanicInfo) -> ! {
+    let tests = unsafe { TESTS.unwrap_or(&[]) };
+    let current = unsafe { CURRENT };
+    unsafe {
+        DEADLINE = None;
+    }
+
+    // The panic may have come with interrupts off; later tests need the timer
+    idt::restore_interrupts(true);
+
+    match tests.get(current) {
+        Some(test) if test.should_panic() && !test.expected_message().is_none_or(|m| message_contains(info, m)) => {
+            serial_println!("[failed] (wrong panic)");
+            serial_println!("    {}", info);
+            unsafe { FAILED += 1 };
+        }
+        Some(test) if test.should_panic() => {
+            serial_println!("[ok]");
+            unsafe { PASSED += 1 };
+        }
+        Some(_) => {
+            serial_println!("[failed]");
+            serial_println!("    {}", info);
+            unsafe { FAILED += 1 };
+        }
+        None => {
+            // Panic outside of any test, e.g. during boot
+            serial_println!("[failed] panic before tests started: {}", info);
+            exit_qemu(ExitCode::Failed);
+        }
+    }
+    run_from(current + 1)
+}
+
+// Called from the timer interrupt: a hung test can't be resumed, so end the run
+pub fn check_timeout() {
+    let expired = unsafe { DEADLINE.is_some_and(|deadline| pit::ticks() >= deadline) };
+    if expired {
+        serial_println!("[timeout] after {} ms", TIMEOUT_MS);
+        unsafe { FAILED += 1 };
+        finish();
+    }
+}
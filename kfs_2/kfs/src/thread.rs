@@ -0,0 +1,561 @@
+// thread.rs - Kernel threads and the preemptive scheduler
+//
+// Every thread has its own stack, and a switched-out thread's registers sit
+// on that stack where switch_context (thread.asm) pushed them, so a Thread
+// only needs to remember its esp. The boot flow becomes thread 0 ("main",
+// which goes on to run the shell) and an idle thread halts whenever nothing
+// else is ready.
+//
+// Scheduling is by strict priority with round robin inside a priority: the
+// PIT interrupt charges a tick to the running thread and switches to the next
+// ready thread of the same or higher priority once its time slice is used up.
+// Sleeping threads wait in a sync::WaitQueue (or for a process) until wake().
+//
+// Threads that run a user process (process.rs) also carry its page directory,
+// which is loaded whenever they are switched in.
+
+use crate::console;
+use crate::fpu::{self, FpuState};
+use crate::idt;
+use crate::nps::{self, Builtin};
+use crate::paging;
+use crate::pit;
+use crate::stack::{self, STACK_FILL};
+use crate::tss;
+use crate::vga::Color;
+
+pub const MAX_THREADS: usize = 16;
+const STACK_SIZE: usize = 16 * 1024;
+
+pub const PRIORITY_IDLE: u8 = 0;
+pub const PRIORITY_NORMAL: u8 = 10;
+pub const MAX_PRIORITY: u8 = 20;
+
+const DEFAULT_TIMESLICE_MS: u32 = 20;
+
+// Interrupts stay off until thread_main turns them on
+const INITIAL_EFLAGS: u32 = 0x2;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum State {
+    Running,
+    Ready,
+    Sleeping,
+    Zombie,
+}
+
+impl State {
+    pub fn name(&self) -> &'static str {
+        match self {
+            State::Running => "running",
+            State::Ready => "ready",
+            State::Sleeping => "sleeping",
+            State::Zombie => "zombie",
+        }
+    }
+}
+
+struct Thread {
+    tid: u32,
+    name: &'static str,
+    state: State,
+    priority: u8,
+    entry: fn(u32),
+    arg: u32,
+    esp: u32,               // Saved by switch_context while not running
+    kernel_stack: u32,      // TSS esp0 for traps from ring 3
+    cr3: u32,               // Page directory (0 before paging is on)
+    stack_bottom: u32,
+    stack_top: u32,
+    joining: Option<u32>,   // Thread this one waits to exit
+    detached: bool,         // Reaped on exit instead of by join()
+    exit_code: u32,
+    ticks: u64,             // Timer ticks spent running
+    slice_left: u32,
+    fpu: FpuState,
+}
+
+#[repr(C, align(16))]
+struct ThreadStack([u8; STACK_SIZE]);
+
+// Slot 0 runs on the boot stack, so it has no stack of its own here
+static mut THREADS: [Option<Thread>; MAX_THREADS] = [const { None }; MAX_THREADS];
+static mut STACKS: [ThreadStack; MAX_THREADS - 1] = [const { ThreadStack([0; STACK_SIZE]) }; MAX_THREADS - 1];
+static mut CURRENT: usize = 0;
+static mut NEXT_TID: u32 = 0;
+static mut STARTED: bool = false;
+static mut TIMESLICE_MS: u32 = DEFAULT_TIMESLICE_MS;
+
+extern "C" {
+    static stack_bottom: u8;
+    static stack_top: u8;
+    fn switch_context(old_esp: *mut u32, new_esp: u32);
+}
+
+fn setup_timeslice(value: Option<&str>) -> bool {
+    match value.and_then(|v| v.parse::<u32>().ok()) {
+        Some(ms) if ms > 0 => {
+            unsafe {
+                TIMESLICE_MS = ms;
+            }
+            true
+        }
+        _ => false,
+    }
+}
+kernel_param!("timeslice", "Scheduler time slice in milliseconds", setup_timeslice);
+
+fn threads() -> &'static mut [Option<Thread>; MAX_THREADS] {
+    unsafe { &mut *core::ptr::addr_of_mut!(THREADS) }
+}
+
+fn current_thread() -> &'static mut Thread {
+    unsafe { threads()[CURRENT].as_mut().unwrap() }
+}
+
+fn find(tid: u32) -> Option<usize> {
+    threads().iter().position(|t| t.as_ref().is_some_and(|t| t.tid == tid))
+}
+
+fn slice_ticks() -> u32 {
+    unsafe { (TIMESLICE_MS * pit::hz() / 1000).max(1) }
+}
+
+fn new_thread(tid: u32, name: &'static str, priority: u8, entry: fn(u32), arg: u32) -> Thread {
+    Thread {
+        tid,
+        name,
+        state: State::Ready,
+        priority,
+        entry,
+        arg,
+        esp: 0,
+        kernel_stack: 0,
+        cr3: paging::kernel_directory(),
+        stack_bottom: 0,
+        stack_top: 0,
+        joining: None,
+        detached: false,
+        exit_code: 0,
+        ticks: 0,
+        slice_left: 0,
+        fpu: FpuState::new(),
+    }
+}
+
+fn idle(_arg: u32) {
+    loop {
+        unsafe {
+            core::arch::asm!("hlt", options(nomem, nostack));
+        }
+    }
+}
+
+// Turn the boot flow into thread 0 and start preempting
+pub fn init() {
+    unsafe {
+        let mut main = new_thread(0, "main", PRIORITY_NORMAL, idle, 0);
+        main.state = State::Running;
+        main.kernel_stack = tss::kernel_stack();
+        main.stack_bottom = &raw const stack_bottom as u32;
+        main.stack_top = &raw const stack_top as u32;
+        main.slice_left = slice_ticks();
+        threads()[0] = Some(main);
+        CURRENT = 0;
+        NEXT_TID = 1;
+        fpu::switch_to(&mut current_thread().fpu);
+
+        spawn("idle", PRIORITY_IDLE, idle, 0);
+        STARTED = true;
+    }
+    console::info("      Scheduler running!\n\n", Color::Green, Color::Black);
+}
+
+pub fn started() -> bool {
+    unsafe { STARTED }
+}
+
+pub fn current() -> u32 {
+    if !started() {
+        return 0;
+    }
+    current_thread().tid
+}
+
+// First code of every new thread, entered from switch_context's ret
+extern "C" fn thread_main() -> ! {
+    let (entry, arg) = {
+        let thread = current_thread();
+        (thread.entry, thread.arg)
+    };
+    idt::restore_interrupts(true);
+    entry(arg);
+    exit(0)
+}
+
+// Start `entry(arg)` in a new thread; returns its tid
+pub fn spawn(name: &'static str, priority: u8, entry: fn(u32), arg: u32) -> Option<u32> {
+    let interrupts = idt::disable_interrupts();
+    let slot = (1..MAX_THREADS).find(|&slot| threads()[slot].is_none());
+    let tid = slot.map(|slot| unsafe {
+        let stack = core::ptr::addr_of_mut!(STACKS[slot - 1]) as *mut u32;
+        let words = STACK_SIZE / 4;
+        core::slice::from_raw_parts_mut(stack, words).fill(STACK_FILL);
+
+        // The frame switch_context pops: eflags, edi, esi, ebx, ebp, then
+        // the return into thread_main, which itself never returns (0)
+        let frame = [INITIAL_EFLAGS, 0, 0, 0, 0, thread_main as *const () as u32, 0];
+        let esp = stack.add(words - frame.len());
+        core::ptr::copy_nonoverlapping(frame.as_ptr(), esp, frame.len());
+
+        let tid = NEXT_TID;
+        NEXT_TID += 1;
+        let mut thread = new_thread(tid, name, priority.min(MAX_PRIORITY), entry, arg);
+        thread.esp = esp as u32;
+        thread.stack_bottom = stack as u32;
+        thread.stack_top = stack as u32 + STACK_SIZE as u32;
+        thread.kernel_stack = thread.stack_top;
+        threads()[slot] = Some(thread);
+        tid
+    });
+    idt::restore_interrupts(interrupts);
+    tid
+}
+
+// Pick the thread to run after the current one. A ready thread of the same
+// priority only wins when `rotate` is set, i.e. the current one gave up its turn.
+fn pick_next(rotate: bool) -> usize {
+    let current = unsafe { CURRENT };
+    let mut best: Option<(usize, u8)> = None;
+    for i in 1..=MAX_THREADS {
+        let slot = (current + i) % MAX_THREADS;
+        if let Some(thread) = &threads()[slot] {
+            if thread.state == State::Ready && best.is_none_or(|(_, p)| thread.priority > p) {
+                best = Some((slot, thread.priority));
+            }
+        }
+    }
+    let running = current_thread();
+    match best {
+        // The current thread is blocked or gone: anything ready beats it
+        Some((slot, _)) if running.state != State::Running => slot,
+        Some((slot, priority)) if priority > running.priority || (rotate && priority == running.priority) => slot,
+        _ => current,
+    }
+}
+
+// Free zombies nobody will join; never the current thread, whose stack is in use
+fn reap_detached() {
+    let current = unsafe { CURRENT };
+    for (slot, entry) in threads().iter_mut().enumerate() {
+        if slot != current && entry.as_ref().is_some_and(|t| t.detached && t.state == State::Zombie) {
+            *entry = None;
+        }
+    }
+}
+
+// Switch to the best runnable thread. Must be called with interrupts disabled.
+fn schedule(rotate: bool) {
+    reap_detached();
+    let previous = unsafe { CURRENT };
+    let next = pick_next(rotate);
+    if next == previous {
+        // Nobody else wants the CPU: start a new slice if this one is used up
+        let thread = current_thread();
+        if thread.slice_left == 0 {
+            thread.slice_left = slice_ticks();
+        }
+        return;
+    }
+
+    let threads = threads();
+    let old = threads[previous].as_mut().unwrap();
+    if old.state == State::Running {
+        old.state = State::Ready;
+    }
+    old.kernel_stack = tss::kernel_stack();
+    let old_esp = &mut old.esp as *mut u32;
+    let old_cr3 = old.cr3;
+
+    let new = threads[next].as_mut().unwrap();
+    new.state = State::Running;
+    new.slice_left = slice_ticks();
+    tss::set_kernel_stack(new.kernel_stack);
+    if new.cr3 != old_cr3 {
+        paging::load(new.cr3);
+    }
+    fpu::switch_to(&mut new.fpu);
+    unsafe {
+        CURRENT = next;
+        switch_context(old_esp, new.esp);
+    }
+}
+
+// Timer interrupt: account the tick and preempt
+pub fn tick() {
+    if !started() {
+        return;
+    }
+    let thread = current_thread();
+    thread.ticks += 1;
+    thread.slice_left = thread.slice_left.saturating_sub(1);
+    schedule(thread.slice_left == 0);
+}
+
+// Run a higher priority thread that became ready, e.g. after wake()
+pub fn preempt() {
+    if started() {
+        let interrupts = idt::disable_interrupts();
+        schedule(false);
+        idt::restore_interrupts(interrupts);
+    }
+}
+
+// Give the rest of the time slice to another thread of the same priority
+#[allow(dead_code)]
+pub fn yield_now() {
+    if started() {
+        let interrupts = idt::disable_interrupts();
+        schedule(true);
+        idt::restore_interrupts(interrupts);
+    }
+}
+
+// Sleep until wake(). Call with interrupts disabled after checking the
+// condition to wait for, so a wake() in between cannot be lost. Before the
+// scheduler runs this just waits for the next interrupt.
+pub fn block() {
+    if !started() {
+        unsafe {
+            core::arch::asm!("sti; hlt; cli", options(nomem, nostack));
+        }
+        return;
+    }
+    current_thread().state = State::Sleeping;
+    schedule(true);
+}
+
+pub fn wake(tid: u32) {
+    let interrupts = idt::disable_interrupts();
+    if let Some(slot) = find(tid) {
+        let thread = threads()[slot].as_mut().unwrap();
+        if thread.state == State::Sleeping {
+            thread.state = State::Ready;
+        }
+    }
+    idt::restore_interrupts(interrupts);
+}
+
+// End the current thread. Its slot stays as a zombie until join() or, for
+// detached threads, the next switch.
+pub fn exit(code: u32) -> ! {
+    idt::disable_interrupts();
+    let tid = {
+        let thread = current_thread();
+        thread.exit_code = code;
+        thread.state = State::Zombie;
+        fpu::release(&mut thread.fpu);
+        thread.tid
+    };
+    for thread in threads().iter_mut().flatten() {
+        if thread.joining == Some(tid) && thread.state == State::Sleeping {
+            thread.joining = None;
+            thread.state = State::Ready;
+        }
+    }
+    schedule(true);
+    unreachable!()
+}
+
+// Wait for thread `tid` to exit and return its exit code
+#[allow(dead_code)]
+pub fn join(tid: u32) -> Option<u32> {
+    let interrupts = idt::disable_interrupts();
+    let code = loop {
+        let Some(slot) = find(tid).filter(|_| tid != current()) else { break None };
+        let thread = threads()[slot].as_mut().unwrap();
+        if thread.state == State::Zombie {
+            let code = thread.exit_code;
+            threads()[slot] = None;
+            break Some(code);
+        }
+        if thread.detached {
+            break None;
+        }
+        current_thread().joining = Some(tid);
+        block();
+    };
+    idt::restore_interrupts(interrupts);
+    code
+}
+
+// Nobody will join `tid`; free it as soon as it exits
+pub fn detach(tid: u32) {
+    let interrupts = idt::disable_interrupts();
+    if let Some(slot) = find(tid) {
+        threads()[slot].as_mut().unwrap().detached = true;
+    }
+    idt::restore_interrupts(interrupts);
+}
+
+pub fn set_priority(tid: u32, priority: u8) -> bool {
+    let interrupts = idt::disable_interrupts();
+    let found = find(tid).map(|slot| threads()[slot].as_mut().unwrap().priority = priority.min(MAX_PRIORITY));
+    idt::restore_interrupts(interrupts);
+    preempt();
+    found.is_some()
+}
+
+// Run the current thread in another address space from now on
+pub fn set_address_space(cr3: u32) {
+    let interrupts = idt::disable_interrupts();
+    if started() {
+        current_thread().cr3 = cr3;
+    }
+    paging::load(cr3);
+    idt::restore_interrupts(interrupts);
+}
+
+pub fn state(tid: u32) -> Option<State> {
+    let interrupts = idt::disable_interrupts();
+    let state = find(tid).map(|slot| threads()[slot].as_ref().unwrap().state);
+    idt::restore_interrupts(interrupts);
+    state
+}
+
+// Stack bounds of the running thread (the boot stack before init)
+pub fn current_stack() -> (u32, u32) {
+    if !started() {
+        return (&raw const stack_bottom as u32, &raw const stack_top as u32);
+    }
+    let thread = current_thread();
+    (thread.stack_bottom, thread.stack_top)
+}
+
+// Bounds and saved esp of a thread that is not running
+pub fn saved_stack(tid: u32) -> Option<(u32, u32, u32)> {
+    let thread = threads()[find(tid)?].as_ref()?;
+    if thread.state == State::Running {
+        return None;
+    }
+    Some((thread.stack_bottom, thread.stack_top, thread.esp))
+}
+
+fn cmd_threads(_argv: &[&str]) {
+    println!("  TID NAME         STATE     PRIO   CPU ms  STACK");
+    let interrupts = idt::disable_interrupts();
+    for thread in threads().iter().flatten() {
+        let size = thread.stack_top - thread.stack_bottom;
+        let used = stack::Stack { bottom: thread.stack_bottom, top: thread.stack_top, esp: 0, ebp: 0 }.high_water();
+        println!(
+            "{:>5} {:<12} {:<9} {:>4} {:>8}  {}/{} KiB",
+            thread.tid,
+            thread.name,
+            thread.state.name(),
+            thread.priority,
+            thread.ticks * 1000 / pit::hz() as u64,
+            used.div_ceil(1024),
+            size / 1024
+        );
+    }
+    idt::restore_interrupts(interrupts);
+}
+
+fn cmd_nice(argv: &[&str]) {
+    let tid = argv.get(1).and_then(|a| nps::parse_number(a));
+    let priority = argv.get(2).and_then(|a| nps::parse_number(a)).filter(|&p| p <= MAX_PRIORITY as u32);
+    let (Some(tid), Some(priority)) = (tid, priority) else {
+        println!("Usage: nice <tid> <priority 0-{}>", MAX_PRIORITY);
+        return;
+    };
+    if !set_priority(tid, priority as u8) {
+        println!("nice: no thread {}", tid);
+    }
+}
+
+// Busy loop for the `spin` command, to watch preemption at work
+fn spin(seconds: u32) {
+    let end = pit::uptime_ms() + seconds as u64 * 1000;
+    let mut rounds: u64 = 0;
+    while pit::uptime_ms() < end {
+        rounds += 1;
+    }
+    println!("\nspin: thread {} done after {} rounds", current(), rounds);
+}
+
+fn cmd_spin(argv: &[&str]) {
+    let seconds = argv.get(1).map_or(Some(5), |a| nps::parse_number(a));
+    let priority = argv.get(2).map_or(Some(PRIORITY_NORMAL as u32), |a| nps::parse_number(a));
+    let (Some(seconds), Some(priority)) = (seconds, priority.filter(|&p| p <= MAX_PRIORITY as u32)) else {
+        println!("Usage: spin [seconds] [priority]");
+        return;
+    };
+    match spawn("spin", priority as u8, spin, seconds) {
+        Some(tid) => {
+            detach(tid);
+            println!("spin: started thread {}", tid);
+        }
+        None => println!("spin: too many threads"),
+    }
+}
+
+static COMMANDS: [Builtin; 3] = [
+    Builtin { name: "threads", usage: "threads", help: "List kernel threads", handler: cmd_threads, complete: None },
+    Builtin { name: "nice", usage: "nice <tid> <priority>", help: "Change the priority of a thread", handler: cmd_nice, complete: None },
+    Builtin { name: "spin", usage: "spin [seconds] [priority]", help: "Start a busy thread in the background", handler: cmd_spin, complete: None },
+];
+
+pub fn register_commands() {
+    nps::register_all(&COMMANDS);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+    static COUNTER: AtomicU32 = AtomicU32::new(0);
+    static STOP: AtomicBool = AtomicBool::new(false);
+
+    fn count_and_exit(arg: u32) {
+        COUNTER.fetch_add(arg, Ordering::SeqCst);
+        exit(arg * 2);
+    }
+
+    fn count_until_stopped(_arg: u32) {
+        while !STOP.load(Ordering::SeqCst) {
+            COUNTER.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test_case]
+    fn spawn_and_join() {
+        COUNTER.store(0, Ordering::SeqCst);
+        let tid = spawn("test", PRIORITY_NORMAL, count_and_exit, 21).unwrap();
+        assert_eq!(join(tid), Some(42));
+        assert_eq!(COUNTER.load(Ordering::SeqCst), 21);
+        // Reaped by join
+        assert_eq!(join(tid), None);
+    }
+
+    #[test_case]
+    fn busy_threads_are_preempted() {
+        COUNTER.store(0, Ordering::SeqCst);
+        STOP.store(false, Ordering::SeqCst);
+        let tid = spawn("busy", PRIORITY_NORMAL, count_until_stopped, 0).unwrap();
+        // Neither thread yields: both only make progress if the timer takes turns
+        while COUNTER.load(Ordering::SeqCst) == 0 {
+            core::hint::spin_loop();
+        }
+        STOP.store(true, Ordering::SeqCst);
+        assert_eq!(join(tid), Some(0));
+    }
+
+    #[test_case]
+    fn sleep_waits_for_the_timer() {
+        let start = pit::uptime_ms();
+        pit::sleep_ms(30);
+        assert!(pit::uptime_ms() - start >= 30);
+        assert_eq!(saved_stack(current()), None);
+    }
+}
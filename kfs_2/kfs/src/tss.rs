@@ -0,0 +1,105 @@
+// tss.rs - Task State Segments
+//
+// We don't use hardware task switching for scheduling; the main TSS only
+// tells the CPU which stack (ss0:esp0) to switch to when an interrupt or
+// system call arrives from ring 3, so the scheduler updates esp0 on every
+// context switch. The second TSS is the target of the double fault task
+// gate: a fresh stack and register set, so a kernel stack overflow still
+// gets its diagnostics printed instead of triple faulting.
+
+use core::arch::asm;
+use kfs_core::tss::Tss;
+
+pub const TSS_SELECTOR: u16 = 0x28;
+pub const DOUBLE_FAULT_TSS_SELECTOR: u16 = 0x30;
+
+const KERNEL_CODE: u32 = 0x08;
+const KERNEL_DATA: u32 = 0x10;
+const KERNEL_STACK: u32 = 0x38;
+const DOUBLE_FAULT_STACK_SIZE: usize = 4096;
+
+#[repr(C, align(16))]
+struct DoubleFaultStack([u8; DOUBLE_FAULT_STACK_SIZE]);
+
+static mut TSS: Tss = Tss::new();
+static mut DOUBLE_FAULT_TSS: Tss = Tss::new();
+static mut DOUBLE_FAULT_STACK: DoubleFaultStack = DoubleFaultStack([0; DOUBLE_FAULT_STACK_SIZE]);
+
+extern "C" {
+    static stack_top: u8;
+    fn double_fault_task();
+}
+
+pub fn tss() -> &'static Tss {
+    unsafe { &*core::ptr::addr_of!(TSS) }
+}
+
+pub fn double_fault_tss() -> &'static Tss {
+    unsafe { &*core::ptr::addr_of!(DOUBLE_FAULT_TSS) }
+}
+
+// Fill both segments; called by gdt::init before the descriptors are built
+pub fn init() {
+    let cr3: u32;
+    unsafe {
+        asm!("mov {}, cr3", out(reg) cr3, options(nomem, nostack, preserves_flags));
+
+        let tss = &mut *core::ptr::addr_of_mut!(TSS);
+        tss.ss0 = KERNEL_STACK;
+        tss.esp0 = &raw const stack_top as u32;
+
+        // Entered through the task gate with interrupts off, on its own stack
+        let df = &mut *core::ptr::addr_of_mut!(DOUBLE_FAULT_TSS);
+        df.cr3 = cr3;
+        df.eip = double_fault_task as *const () as u32;
+        df.eflags = 0x2;
+        df.esp = core::ptr::addr_of!(DOUBLE_FAULT_STACK) as u32 + DOUBLE_FAULT_STACK_SIZE as u32;
+        df.cs = KERNEL_CODE;
+        df.ss = KERNEL_STACK;
+        df.ds = KERNEL_DATA;
+        df.es = KERNEL_DATA;
+        df.fs = KERNEL_DATA;
+        df.gs = KERNEL_DATA;
+    }
+}
+
+// Load the task register; the TSS descriptor must already be in the GDT
+pub fn load() {
+    unsafe {
+        asm!("ltr {0:x}", in(reg) TSS_SELECTOR, options(nomem, nostack, preserves_flags));
+    }
+}
+
+// Stack used for the next ring 3 -> ring 0 transition
+pub fn set_kernel_stack(esp0: u32) {
+    unsafe {
+        (*core::ptr::addr_of_mut!(TSS)).esp0 = esp0;
+    }
+}
+
+pub fn kernel_stack() -> u32 {
+    tss().esp0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn task_register_holds_the_tss() {
+        let tr: u16;
+        unsafe {
+            asm!("str {0:x}", out(reg) tr, options(nomem, nostack, preserves_flags));
+        }
+        assert_eq!(tr, TSS_SELECTOR);
+        assert_eq!(tss().ss0, KERNEL_STACK);
+    }
+
+    #[test_case]
+    fn kernel_stack_can_be_switched() {
+        let saved = kernel_stack();
+        set_kernel_stack(0x1234_5670);
+        assert_eq!(kernel_stack(), 0x1234_5670);
+        set_kernel_stack(saved);
+    }
+}
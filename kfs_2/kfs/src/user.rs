@@ -0,0 +1,178 @@
+// user.rs - Running code at CPL3
+//
+// Every user program runs as a process (process.rs) in its own address
+// space, entered with an iret frame holding the user code (0x1B) and stack
+// (0x43) selectors. Faults from ring 3 end up in fault(), which turns them
+// into signals instead of halting the machine. Programs talk to the kernel
+// with int 0x80 (see syscall.rs); a plain function returning into user_exit
+// makes the exit system call with its return value.
+//
+// The built-in programs are assembly in the kernel's .user_text section
+// (user.asm), the only part of the image ring 3 can read or run.
+
+use kfs_core::signal::{self, WaitStatus};
+use crate::idt::TrapFrame;
+use crate::nps::{self, Builtin};
+use crate::process;
+use crate::symbols;
+use crate::vga::Color;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Exit {
+    Code(u32),
+    Killed(&'static str),
+    NotStarted,
+}
+
+// Run `entry` as a new process and wait for it to exit or be killed
+pub fn run(name: &'static str, entry: u32) -> Exit {
+    match process::spawn(name, entry) {
+        Some(pid) => wait_foreground(pid),
+        None => Exit::NotStarted,
+    }
+}
+
+// Wait for a process started from the shell, with Ctrl+C going to it
+pub fn wait_foreground(pid: u32) -> Exit {
+    process::set_foreground(pid);
+    let status = process::wait(pid as i32, 0);
+    process::set_foreground(0);
+    match status {
+        Ok(Some((_, WaitStatus::Exited(code)))) => Exit::Code(code as u32),
+        Ok(Some((_, WaitStatus::Signaled { signal, .. }))) => Exit::Killed(signal::name(signal).unwrap_or("unknown signal")),
+        _ => Exit::NotStarted,
+    }
+}
+
+#[cfg(test)]
+pub fn run_function(function: unsafe extern "C" fn()) -> Exit {
+    run("user", function as *const () as u32)
+}
+
+// Called by the exception handlers for traps with a ring 3 cs: report the
+// fault and send `signal`, which usually ends the program
+pub fn fault(frame: &mut TrapFrame, name: &'static str, signal: u32) {
+    let pid = process::current().map_or(0, |p| p.pid);
+    crate::console::printc("user: ", Color::LightRed, Color::Black);
+    print!("pid {}: {} at eip=0x{:08x}", pid, name, frame.eip);
+    if let Some(symbol) = symbols::lookup(frame.eip) {
+        print!(" <{}+0x{:x}>", symbol.demangled(), frame.eip - symbol.addr);
+    }
+    println!(" esp=0x{:08x}", frame.user_esp);
+    process::force_signal(signal);
+    process::deliver_signals(frame);
+}
+
+// Demo programs for the `usermode` command, in user.asm
+extern "C" {
+    fn user_sum();
+    fn user_privileged();
+    fn user_wild_jump();
+    fn user_hello();
+    fn user_echo();
+    fn user_fork();
+    fn user_signal();
+    fn user_spin();
+}
+
+const DEMOS: [(&str, unsafe extern "C" fn()); 8] = [
+    ("sum", user_sum),
+    ("gp", user_privileged),
+    ("ud", user_wild_jump),
+    ("hello", user_hello),
+    ("echo", user_echo),
+    ("fork", user_fork),
+    ("signal", user_signal),
+    ("spin", user_spin),
+];
+
+// usermode [demo] [&]: a trailing & runs it in the background
+fn cmd_usermode(argv: &[&str]) {
+    let background = argv.last() == Some(&"&");
+    let argv = if background { &argv[..argv.len() - 1] } else { argv };
+    let name = argv.get(1).copied().unwrap_or("sum");
+    let Some(&(name, program)) = DEMOS.iter().find(|(demo, _)| *demo == name).filter(|_| argv.len() <= 2) else {
+        println!("Usage: usermode [sum|gp|ud|hello|echo|fork|signal|spin] [&]");
+        return;
+    };
+    if background {
+        match process::spawn(name, program as *const () as u32) {
+            Some(pid) => {
+                process::detach(pid);
+                println!("[{}]", pid);
+            }
+            None => println!("usermode: cannot start a process"),
+        }
+        return;
+    }
+    match run(name, program as *const () as u32) {
+        Exit::Code(code) => println!("user program exited with {}", code),
+        Exit::Killed(reason) => println!("user program killed ({})", reason),
+        Exit::NotStarted => println!("usermode: cannot start a process"),
+    }
+}
+
+fn complete_usermode(arg: usize, _prefix: &str, add: &mut dyn FnMut(&str)) {
+    if arg == 1 {
+        for (name, _) in DEMOS.iter() {
+            add(name);
+        }
+    }
+}
+
+static COMMANDS: [Builtin; 1] = [
+    Builtin { name: "usermode", usage: "usermode [demo] [&]", help: "Run a demo program in ring 3", handler: cmd_usermode, complete: Some(complete_usermode) },
+];
+
+pub fn register_commands() {
+    nps::register_all(&COMMANDS);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    extern "C" {
+        fn user_cpl();
+    }
+
+    #[test_case]
+    fn runs_at_cpl3_and_returns() {
+        assert_eq!(run_function(user_cpl), Exit::Code(3));
+        assert_eq!(run_function(user_sum), Exit::Code(55));
+        assert_eq!(run_function(user_hello), Exit::Code(0));
+    }
+
+    #[test_case]
+    fn faults_kill_only_the_program() {
+        assert_eq!(run_function(user_privileged), Exit::Killed("SIGSEGV"));
+        assert_eq!(run_function(user_wild_jump), Exit::Killed("SIGILL"));
+        // The kernel is still fine and can go back to ring 3
+        assert_eq!(run_function(user_sum), Exit::Code(55));
+    }
+
+    #[test_case]
+    fn traps_leave_the_kernel_data_segment_loaded() {
+        // The program exits through int 0x80, and the thread that runs next
+        // must not inherit its ring 3 selector
+        assert_eq!(run_function(user_sum), Exit::Code(55));
+        let ds: u32;
+        unsafe {
+            core::arch::asm!("mov {0:e}, ds", out(reg) ds, options(nomem, nostack));
+        }
+        assert_eq!(ds & 0xFFFF, 0x10);
+    }
+
+    #[test_case]
+    fn fork_copies_pages_on_write() {
+        let free = crate::paging::free_frames();
+        assert_eq!(run_function(user_fork), Exit::Code(0));
+        // Both address spaces are gone again
+        assert_eq!(crate::paging::free_frames(), free);
+    }
+
+    #[test_case]
+    fn signals_reach_handlers() {
+        assert_eq!(run_function(user_signal), Exit::Code(0));
+    }
+}
@@ -0,0 +1,33 @@
+; syscall.asm - int 0x80 entry (DPL3 trap gate, interrupts stay enabled)
+;
+; Linux register ABI: eax = number, ebx ecx edx esi edi = arguments.
+; The Rust dispatcher stores the result in the saved eax, which popa then
+; hands back to the caller. The caller's data segment registers are saved
+; in the frame too, and the kernel's are loaded for the call.
+
+KERNEL_DATA equ 0x10
+
+section .text
+
+global syscall_handler
+extern rust_syscall
+syscall_handler:
+    push ds
+    push es
+    push fs
+    push gs
+    pusha
+    mov ax, KERNEL_DATA
+    mov ds, ax
+    mov es, ax
+    mov fs, ax
+    mov gs, ax
+    push esp            ; TrapFrame * (see idt.rs)
+    call rust_syscall
+    add esp, 4
+    popa
+    pop gs
+    pop fs
+    pop es
+    pop ds
+    iretd
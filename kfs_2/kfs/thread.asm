@@ -0,0 +1,27 @@
+; thread.asm - Kernel thread context switch
+;
+; A switched-out thread's stack holds, from its saved esp upwards: eflags,
+; edi, esi, ebx, ebp and the return address into the scheduler (see
+; thread.rs, which builds the same frame for new threads, and stack.rs).
+; The other registers are caller-saved in the C calling convention.
+
+section .text
+
+; void switch_context(u32 *old_esp, u32 new_esp)
+global switch_context
+switch_context:
+    mov eax, [esp + 4]  ; Where to save the old thread's esp
+    mov edx, [esp + 8]  ; The new thread's saved esp
+    push ebp
+    push ebx
+    push esi
+    push edi
+    pushfd              ; Keeps each thread's interrupt flag across the switch
+    mov [eax], esp
+    mov esp, edx
+    popfd
+    pop edi
+    pop esi
+    pop ebx
+    pop ebp
+    ret
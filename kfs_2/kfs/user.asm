@@ -0,0 +1,421 @@
+; user.asm - Entering ring 3 with iret, and the programs built into the kernel
+;
+; Ring 3 can only reach the kernel image's .user_text section, which paging.rs
+; maps user accessible and read-only; everything else is supervisor only. So
+; the built-in programs live here and keep their data on their own stack and
+; heap. Each one is entered with a return address into user_exit on the stack:
+; returning exits with eax.
+
+SYS_EXIT      equ 1
+SYS_FORK      equ 2
+SYS_READ      equ 3
+SYS_WRITE     equ 4
+SYS_OPEN      equ 5
+SYS_CLOSE     equ 6
+SYS_WAITPID   equ 7
+SYS_GETPID    equ 20
+SYS_SETUID    equ 23
+SYS_GETUID    equ 24
+SYS_KILL      equ 37
+SYS_BRK       equ 45
+SYS_SIGNAL    equ 48
+SYS_GETPPID   equ 64
+SYS_MMAP      equ 90
+SYS_SIGRETURN equ 119
+
+EPERM  equ 1
+ENOENT equ 2
+ESRCH  equ 3
+EBADF  equ 9
+ECHILD equ 10
+EFAULT equ 14
+EINVAL equ 22
+ENOSYS equ 38
+
+SIGKILL equ 9
+SIGUSR1 equ 10
+SIGTERM equ 15
+SIG_IGN equ 1
+
+HEAP_MAX      equ 1024 * 1024
+MAP_PRIVATE   equ 0x02
+MAP_ANONYMOUS equ 0x20
+
+; SYSCALL number [, ebx [, ecx [, edx]]]; the result is in eax
+%macro SYSCALL 1-4
+    mov eax, %1
+%if %0 > 1
+    mov ebx, %2
+%endif
+%if %0 > 2
+    mov ecx, %3
+%endif
+%if %0 > 3
+    mov edx, %4
+%endif
+    int 0x80
+%endmacro
+
+; PRINT message: write a message declared with a .len to stdout
+%macro PRINT 1
+    mov esi, %1
+    mov ecx, %1.len
+    call print
+%endmacro
+
+; FAIL_IF condition, code: return `code` from a program with a .return label
+%macro FAIL_IF 2
+    j%-1 %%ok
+    mov eax, %2
+    jmp .return
+%%ok:
+%endmacro
+
+section .text
+
+; void return_to_user(const TrapFrame *frame)
+; Leaves through the frame exactly like a trap handler would (see TrapFrame
+; in idt.rs), data segment registers included. The frame must be a ring 3
+; one and must not live below the stack space still in use. Never returns.
+global return_to_user
+return_to_user:
+    mov esp, [esp+4]
+    popa
+    pop gs
+    pop fs
+    pop es
+    pop ds
+    iretd
+
+section .user_text progbits alloc exec nowrite align=16
+
+; Programs return here: exit(eax)
+global user_exit
+user_exit:
+    mov ebx, eax
+    mov eax, SYS_EXIT
+    int 0x80
+    jmp user_exit
+
+; Signal handlers return here; the signal frame built by process.rs is on
+; the stack right above the handler's argument
+global signal_return
+signal_return:
+    mov eax, SYS_SIGRETURN
+    int 0x80
+    jmp signal_return
+
+; System calls only take buffers in the process's own memory, so copy the
+; message to the stack first. esi = message, ecx = length (at most 256).
+print:
+    push ebp
+    mov ebp, esp
+    sub esp, 256
+    mov edx, ecx
+    mov edi, esp
+    rep movsb
+    mov ecx, esp
+    SYSCALL SYS_WRITE, 1, ecx, edx
+    leave
+    ret
+
+hello_message: db "Hello from ring 3!", 10
+.len equ $ - hello_message
+child_message: db "child: exiting with 42", 10
+.len equ $ - child_message
+parent_message: db "parent: child exited with 42, my copy is untouched", 10
+.len equ $ - parent_message
+handler_message: db "handler: caught a signal", 10
+.len equ $ - handler_message
+no_such_file: db "/no/such/file", 0
+.len equ $ - no_such_file
+
+; Demo programs for the `usermode` command
+
+global user_sum
+user_sum:
+    xor eax, eax
+    mov ecx, 10
+.add:
+    add eax, ecx
+    loop .add
+    ret
+
+; cli is privileged: #GP
+global user_privileged
+user_privileged:
+    cli
+    xor eax, eax
+    ret
+
+global user_wild_jump
+user_wild_jump:
+    ud2
+
+global user_hello
+user_hello:
+    PRINT hello_message
+    SYSCALL SYS_EXIT, 0
+    mov eax, 1
+    ret
+
+; Copy lines from stdin to stdout until ^D
+global user_echo
+user_echo:
+    push ebp
+    mov ebp, esp
+    sub esp, 80
+.next:
+    mov ecx, esp
+    SYSCALL SYS_READ, 0, ecx, 80
+    test eax, eax
+    jle .return
+    mov edx, eax
+    mov ecx, esp
+    SYSCALL SYS_WRITE, 1, ecx, edx
+    jmp .next
+.return:
+    leave
+    ret
+
+; The child writes to a copy-on-write page; the parent must not see it
+global user_fork
+user_fork:
+    push ebp
+    mov ebp, esp
+    push dword 1                ; [ebp-4]: the value both sides start with
+    push dword 0                ; [ebp-8]: the child's exit status
+    SYSCALL SYS_FORK
+    test eax, eax
+    jnz .parent
+    mov dword [ebp-4], 2
+    PRINT child_message
+    mov eax, 42
+    jmp .return
+.parent:
+    mov esi, eax
+    lea ecx, [ebp-8]
+    SYSCALL SYS_WAITPID, esi, ecx, 0
+    cmp eax, esi
+    FAIL_IF ne, 1
+    cmp dword [ebp-8], 42 << 8
+    FAIL_IF ne, 2
+    cmp dword [ebp-4], 1
+    FAIL_IF ne, 3
+    PRINT parent_message
+    xor eax, eax
+.return:
+    leave
+    ret
+
+; Catch a signal sent to ourselves, then ignore it. The handler notes the
+; signal in the first word of the heap.
+global user_signal
+user_signal:
+    push ebp
+    mov ebp, esp
+    SYSCALL SYS_BRK, 0
+    mov esi, eax
+    lea ebx, [esi+4]
+    SYSCALL SYS_BRK, ebx
+    mov dword [esi], 0
+    SYSCALL SYS_SIGNAL, SIGUSR1, on_signal
+    SYSCALL SYS_GETPID
+    mov edi, eax
+    ; The handler runs on the way back from kill, which still returns 0
+    SYSCALL SYS_KILL, edi, SIGUSR1
+    test eax, eax
+    FAIL_IF nz, 1
+    cmp dword [esi], SIGUSR1
+    FAIL_IF ne, 1
+    SYSCALL SYS_SIGNAL, SIGUSR1, SIG_IGN
+    cmp eax, on_signal
+    FAIL_IF ne, 2
+    mov dword [esi], 0
+    SYSCALL SYS_KILL, edi, SIGUSR1
+    cmp dword [esi], 0
+    FAIL_IF ne, 3
+    SYSCALL SYS_SIGNAL, SIGKILL, on_signal
+    cmp eax, -EINVAL
+    FAIL_IF ne, 4
+    xor eax, eax
+.return:
+    leave
+    ret
+
+; void on_signal(int signal); the heap has a single word, just below the break
+on_signal:
+    SYSCALL SYS_BRK, 0
+    mov ecx, [esp+4]
+    mov [eax-4], ecx
+    PRINT handler_message
+    ret
+
+; Spin until Ctrl+C (or `kill`)
+global user_spin
+user_spin:
+    pause
+    jmp user_spin
+
+; Programs for the kernel tests (user.rs, syscall.rs)
+
+global user_cpl
+user_cpl:
+    mov eax, cs
+    and eax, 3
+    ret
+
+global user_heap_and_mmap
+user_heap_and_mmap:
+    push ebp
+    mov ebp, esp
+    sub esp, 24
+    SYSCALL SYS_BRK, 0
+    mov esi, eax
+    lea edi, [esi+100]
+    SYSCALL SYS_BRK, edi
+    cmp eax, edi
+    FAIL_IF ne, 1
+    ; Too far: the break stays where it was
+    lea ebx, [esi+HEAP_MAX+1]
+    SYSCALL SYS_BRK, ebx
+    cmp eax, edi
+    FAIL_IF ne, 2
+    mov byte [esi+99], 1
+    ; old_mmap {addr, len, prot, flags, fd, offset}
+    mov dword [esp], 0
+    mov dword [esp+4], 100
+    mov dword [esp+8], 3
+    mov dword [esp+12], MAP_ANONYMOUS | MAP_PRIVATE
+    mov dword [esp+16], -1
+    mov dword [esp+20], 0
+    mov ecx, esp
+    SYSCALL SYS_MMAP, ecx
+    test eax, 0xFFF
+    FAIL_IF nz, 3
+    mov dword [eax], 0xCAFE
+    ; Shrinking the heap unmaps it again
+    SYSCALL SYS_BRK, esi
+    SYSCALL SYS_WRITE, 1, esi, 1
+    cmp eax, -EFAULT
+    FAIL_IF ne, 4
+    xor eax, eax
+.return:
+    leave
+    ret
+
+global user_files
+user_files:
+    push ebp
+    mov ebp, esp
+    sub esp, 16
+    mov esi, no_such_file
+    mov edi, esp
+    mov ecx, no_such_file.len
+    rep movsb
+    mov ecx, esp
+    SYSCALL SYS_OPEN, ecx, 0, 0
+    cmp eax, -ENOENT
+    FAIL_IF ne, 1
+    SYSCALL SYS_CLOSE, 7
+    cmp eax, -EBADF
+    FAIL_IF ne, 2
+    SYSCALL SYS_WRITE, 7, 0, 0
+    cmp eax, -EBADF
+    FAIL_IF ne, 2
+    SYSCALL SYS_WRITE, 1, 0xFFFFFFF0, 32
+    cmp eax, -EFAULT
+    FAIL_IF ne, 3
+    SYSCALL 999
+    cmp eax, -ENOSYS
+    FAIL_IF ne, 4
+    SYSCALL SYS_WAITPID, -1, 0, 0
+    cmp eax, -ECHILD
+    FAIL_IF ne, 5
+    xor eax, eax
+.return:
+    leave
+    ret
+
+; The kernel image is only mapped for the kernel, .user_text included
+global user_kernel_pointers
+user_kernel_pointers:
+    push ebp
+    mov ebp, esp
+    SYSCALL SYS_WRITE, 1, 0x100000, 4
+    cmp eax, -EFAULT
+    FAIL_IF ne, 1
+    SYSCALL SYS_READ, 0, 0x100000, 4
+    cmp eax, -EFAULT
+    FAIL_IF ne, 2
+    SYSCALL SYS_WRITE, 1, hello_message, hello_message.len
+    cmp eax, -EFAULT
+    FAIL_IF ne, 3
+    xor eax, eax
+.return:
+    leave
+    ret
+
+global user_exits_with_seven
+user_exits_with_seven:
+    SYSCALL SYS_EXIT, 7
+    xor eax, eax
+    ret
+
+global user_exit_and_kill
+user_exit_and_kill:
+    SYSCALL SYS_GETPID
+    mov esi, eax
+    SYSCALL SYS_KILL, esi, 0
+    test eax, eax
+    jnz .fail
+    SYSCALL SYS_KILL, 4242, SIGKILL
+    cmp eax, -ESRCH
+    jne .fail
+    SYSCALL SYS_KILL, esi, SIGTERM
+    xor eax, eax
+    ret
+.fail:
+    SYSCALL SYS_EXIT, 1
+
+; Only root may take another uid, and only processes of the same user can be signalled
+global user_drop_privileges
+user_drop_privileges:
+    push ebp
+    mov ebp, esp
+    push dword 0                ; [ebp-4]: the child's exit status
+    SYSCALL SYS_GETPID
+    mov esi, eax
+    SYSCALL SYS_FORK
+    test eax, eax
+    jnz .parent
+    SYSCALL SYS_SETUID, 1000
+    test eax, eax
+    FAIL_IF nz, 1
+    SYSCALL SYS_GETUID
+    cmp eax, 1000
+    FAIL_IF ne, 1
+    SYSCALL SYS_SETUID, 0
+    cmp eax, -EPERM
+    FAIL_IF ne, 2
+    SYSCALL SYS_GETPPID
+    cmp eax, esi
+    FAIL_IF ne, 2
+    SYSCALL SYS_KILL, esi, 0
+    cmp eax, -EPERM
+    FAIL_IF ne, 3
+    xor eax, eax
+    jmp .return
+.parent:
+    ; Root can signal anyone
+    mov edi, eax
+    SYSCALL SYS_KILL, edi, 0
+    test eax, eax
+    FAIL_IF nz, 4
+    lea ecx, [ebp-4]
+    SYSCALL SYS_WAITPID, edi, ecx, 0
+    mov eax, [ebp-4]
+    shr eax, 8
+.return:
+    leave
+    ret
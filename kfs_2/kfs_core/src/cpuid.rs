@@ -0,0 +1,439 @@
+// cpuid.rs - Decoding CPUID results
+//
+// The kernel's cpu module executes the instruction; everything here only
+// turns the raw register values into vendor/brand strings, the processor
+// signature, feature flags and cache descriptions.
+
+// Registers returned by one CPUID leaf
+#[derive(Copy, Clone, Default, PartialEq, Debug)]
+pub struct Regs {
+    pub eax: u32,
+    pub ebx: u32,
+    pub ecx: u32,
+    pub edx: u32,
+}
+
+pub const LEAF_VENDOR: u32 = 0;
+pub const LEAF_FEATURES: u32 = 1;
+pub const LEAF_CACHE_PARAMS: u32 = 4;
+pub const LEAF_EXTENDED_FEATURES: u32 = 7;
+pub const LEAF_EXT_MAX: u32 = 0x8000_0000;
+pub const LEAF_EXT_FEATURES: u32 = 0x8000_0001;
+pub const LEAF_BRAND: u32 = 0x8000_0002;   // Three leaves, 16 bytes each
+pub const LEAF_AMD_L1_CACHE: u32 = 0x8000_0005;
+pub const LEAF_AMD_L2_CACHE: u32 = 0x8000_0006;
+
+// Leaf 0: "GenuineIntel" is spread over ebx, edx, ecx (in that order)
+pub fn vendor(regs: &Regs) -> [u8; 12] {
+    let mut vendor = [0; 12];
+    vendor[0..4].copy_from_slice(&regs.ebx.to_le_bytes());
+    vendor[4..8].copy_from_slice(&regs.edx.to_le_bytes());
+    vendor[8..12].copy_from_slice(&regs.ecx.to_le_bytes());
+    vendor
+}
+
+// Leaves 0x80000002-0x80000004: 48 bytes of NUL padded ASCII
+pub fn brand(leaves: &[Regs; 3]) -> [u8; 48] {
+    let mut brand = [0; 48];
+    for (i, regs) in leaves.iter().enumerate() {
+        for (j, word) in [regs.eax, regs.ebx, regs.ecx, regs.edx].iter().enumerate() {
+            let at = i * 16 + j * 4;
+            brand[at..at + 4].copy_from_slice(&word.to_le_bytes());
+        }
+    }
+    brand
+}
+
+// Printable part of a vendor or brand buffer: up to the first NUL, without
+// the leading spaces Intel uses to right-align the brand string
+pub fn as_str(bytes: &[u8]) -> &str {
+    let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    core::str::from_utf8(&bytes[..len]).unwrap_or("").trim()
+}
+
+// Family/model/stepping from leaf 1 eax, with the extended fields folded in
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Signature {
+    pub family: u32,
+    pub model: u32,
+    pub stepping: u32,
+}
+
+impl Signature {
+    pub fn decode(eax: u32) -> Signature {
+        let stepping = eax & 0xF;
+        let base_model = (eax >> 4) & 0xF;
+        let base_family = (eax >> 8) & 0xF;
+        let ext_model = (eax >> 16) & 0xF;
+        let ext_family = (eax >> 20) & 0xFF;
+
+        let family = if base_family == 0xF { base_family + ext_family } else { base_family };
+        let model = if base_family == 0x6 || base_family == 0xF {
+            ext_model << 4 | base_model
+        } else {
+            base_model
+        };
+        Signature { family, model, stepping }
+    }
+}
+
+// The feature words we keep, one per register of interest
+#[derive(Copy, Clone)]
+enum Word {
+    Leaf1Edx,
+    Leaf1Ecx,
+    Leaf7Ebx,
+    Leaf7Ecx,
+    ExtEdx,
+    ExtEcx,
+}
+
+const WORDS: usize = 6;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Feature {
+    Fpu,
+    Vme,
+    Pse,
+    Tsc,
+    Msr,
+    Pae,
+    Cx8,
+    Apic,
+    Sep,
+    Mtrr,
+    Pge,
+    Cmov,
+    Pat,
+    Clflush,
+    Mmx,
+    Fxsr,
+    Sse,
+    Sse2,
+    Htt,
+    Sse3,
+    Ssse3,
+    Fma,
+    Cx16,
+    Sse41,
+    Sse42,
+    X2apic,
+    Popcnt,
+    Aes,
+    Xsave,
+    Osxsave,
+    Avx,
+    Rdrand,
+    Hypervisor,
+    Fsgsbase,
+    Avx2,
+    Smep,
+    Erms,
+    Rdseed,
+    Smap,
+    Umip,
+    Syscall,
+    Nx,
+    Pdpe1gb,
+    Rdtscp,
+    Lm,
+    Lahf,
+}
+
+impl Feature {
+    pub const ALL: [Feature; 46] = [
+        Feature::Fpu, Feature::Vme, Feature::Pse, Feature::Tsc, Feature::Msr, Feature::Pae,
+        Feature::Cx8, Feature::Apic, Feature::Sep, Feature::Mtrr, Feature::Pge, Feature::Cmov,
+        Feature::Pat, Feature::Clflush, Feature::Mmx, Feature::Fxsr, Feature::Sse, Feature::Sse2,
+        Feature::Htt, Feature::Sse3, Feature::Ssse3, Feature::Fma, Feature::Cx16, Feature::Sse41,
+        Feature::Sse42, Feature::X2apic, Feature::Popcnt, Feature::Aes, Feature::Xsave,
+        Feature::Osxsave, Feature::Avx, Feature::Rdrand, Feature::Hypervisor, Feature::Fsgsbase,
+        Feature::Avx2, Feature::Smep, Feature::Erms, Feature::Rdseed, Feature::Smap, Feature::Umip,
+        Feature::Syscall, Feature::Nx, Feature::Pdpe1gb, Feature::Rdtscp, Feature::Lm, Feature::Lahf,
+    ];
+
+    // Name as in Linux's /proc/cpuinfo flags
+    pub fn name(self) -> &'static str {
+        match self {
+            Feature::Fpu => "fpu",
+            Feature::Vme => "vme",
+            Feature::Pse => "pse",
+            Feature::Tsc => "tsc",
+            Feature::Msr => "msr",
+            Feature::Pae => "pae",
+            Feature::Cx8 => "cx8",
+            Feature::Apic => "apic",
+            Feature::Sep => "sep",
+            Feature::Mtrr => "mtrr",
+            Feature::Pge => "pge",
+            Feature::Cmov => "cmov",
+            Feature::Pat => "pat",
+            Feature::Clflush => "clflush",
+            Feature::Mmx => "mmx",
+            Feature::Fxsr => "fxsr",
+            Feature::Sse => "sse",
+            Feature::Sse2 => "sse2",
+            Feature::Htt => "ht",
+            Feature::Sse3 => "pni",
+            Feature::Ssse3 => "ssse3",
+            Feature::Fma => "fma",
+            Feature::Cx16 => "cx16",
+            Feature::Sse41 => "sse4_1",
+            Feature::Sse42 => "sse4_2",
+            Feature::X2apic => "x2apic",
+            Feature::Popcnt => "popcnt",
+            Feature::Aes => "aes",
+            Feature::Xsave => "xsave",
+            Feature::Osxsave => "osxsave",
+            Feature::Avx => "avx",
+            Feature::Rdrand => "rdrand",
+            Feature::Hypervisor => "hypervisor",
+            Feature::Fsgsbase => "fsgsbase",
+            Feature::Avx2 => "avx2",
+            Feature::Smep => "smep",
+            Feature::Erms => "erms",
+            Feature::Rdseed => "rdseed",
+            Feature::Smap => "smap",
+            Feature::Umip => "umip",
+            Feature::Syscall => "syscall",
+            Feature::Nx => "nx",
+            Feature::Pdpe1gb => "pdpe1gb",
+            Feature::Rdtscp => "rdtscp",
+            Feature::Lm => "lm",
+            Feature::Lahf => "lahf_lm",
+        }
+    }
+
+    fn location(self) -> (Word, u32) {
+        match self {
+            Feature::Fpu => (Word::Leaf1Edx, 0),
+            Feature::Vme => (Word::Leaf1Edx, 1),
+            Feature::Pse => (Word::Leaf1Edx, 3),
+            Feature::Tsc => (Word::Leaf1Edx, 4),
+            Feature::Msr => (Word::Leaf1Edx, 5),
+            Feature::Pae => (Word::Leaf1Edx, 6),
+            Feature::Cx8 => (Word::Leaf1Edx, 8),
+            Feature::Apic => (Word::Leaf1Edx, 9),
+            Feature::Sep => (Word::Leaf1Edx, 11),
+            Feature::Mtrr => (Word::Leaf1Edx, 12),
+            Feature::Pge => (Word::Leaf1Edx, 13),
+            Feature::Cmov => (Word::Leaf1Edx, 15),
+            Feature::Pat => (Word::Leaf1Edx, 16),
+            Feature::Clflush => (Word::Leaf1Edx, 19),
+            Feature::Mmx => (Word::Leaf1Edx, 23),
+            Feature::Fxsr => (Word::Leaf1Edx, 24),
+            Feature::Sse => (Word::Leaf1Edx, 25),
+            Feature::Sse2 => (Word::Leaf1Edx, 26),
+            Feature::Htt => (Word::Leaf1Edx, 28),
+            Feature::Sse3 => (Word::Leaf1Ecx, 0),
+            Feature::Ssse3 => (Word::Leaf1Ecx, 9),
+            Feature::Fma => (Word::Leaf1Ecx, 12),
+            Feature::Cx16 => (Word::Leaf1Ecx, 13),
+            Feature::Sse41 => (Word::Leaf1Ecx, 19),
+            Feature::Sse42 => (Word::Leaf1Ecx, 20),
+            Feature::X2apic => (Word::Leaf1Ecx, 21),
+            Feature::Popcnt => (Word::Leaf1Ecx, 23),
+            Feature::Aes => (Word::Leaf1Ecx, 25),
+            Feature::Xsave => (Word::Leaf1Ecx, 26),
+            Feature::Osxsave => (Word::Leaf1Ecx, 27),
+            Feature::Avx => (Word::Leaf1Ecx, 28),
+            Feature::Rdrand => (Word::Leaf1Ecx, 30),
+            Feature::Hypervisor => (Word::Leaf1Ecx, 31),
+            Feature::Fsgsbase => (Word::Leaf7Ebx, 0),
+            Feature::Avx2 => (Word::Leaf7Ebx, 5),
+            Feature::Smep => (Word::Leaf7Ebx, 7),
+            Feature::Erms => (Word::Leaf7Ebx, 9),
+            Feature::Rdseed => (Word::Leaf7Ebx, 18),
+            Feature::Smap => (Word::Leaf7Ebx, 20),
+            Feature::Umip => (Word::Leaf7Ecx, 2),
+            Feature::Syscall => (Word::ExtEdx, 11),
+            Feature::Nx => (Word::ExtEdx, 20),
+            Feature::Pdpe1gb => (Word::ExtEdx, 26),
+            Feature::Rdtscp => (Word::ExtEdx, 27),
+            Feature::Lm => (Word::ExtEdx, 29),
+            Feature::Lahf => (Word::ExtEcx, 0),
+        }
+    }
+}
+
+// Feature bits collected from leaves 1, 7 and 0x80000001
+#[derive(Copy, Clone, Default, PartialEq, Debug)]
+pub struct Features {
+    words: [u32; WORDS],
+}
+
+impl Features {
+    pub const fn none() -> Features {
+        Features { words: [0; WORDS] }
+    }
+
+    // Leaves the CPU doesn't implement are passed as Regs::default()
+    pub fn new(leaf1: &Regs, leaf7: &Regs, ext: &Regs) -> Features {
+        let mut words = [0; WORDS];
+        words[Word::Leaf1Edx as usize] = leaf1.edx;
+        words[Word::Leaf1Ecx as usize] = leaf1.ecx;
+        words[Word::Leaf7Ebx as usize] = leaf7.ebx;
+        words[Word::Leaf7Ecx as usize] = leaf7.ecx;
+        words[Word::ExtEdx as usize] = ext.edx;
+        words[Word::ExtEcx as usize] = ext.ecx;
+        Features { words }
+    }
+
+    pub fn has(&self, feature: Feature) -> bool {
+        let (word, bit) = feature.location();
+        self.words[word as usize] & (1 << bit) != 0
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum CacheKind {
+    Data,
+    Instruction,
+    Unified,
+}
+
+impl CacheKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            CacheKind::Data => "data",
+            CacheKind::Instruction => "instruction",
+            CacheKind::Unified => "unified",
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Cache {
+    pub level: u32,
+    pub kind: CacheKind,
+    pub size_kb: u32,
+    pub line_size: u32,
+    pub ways: u32,      // 0 means fully associative
+}
+
+impl Cache {
+    // Intel leaf 4, one subleaf per cache; None once the list ends
+    pub fn from_leaf4(regs: &Regs) -> Option<Cache> {
+        let kind = match regs.eax & 0x1F {
+            1 => CacheKind::Data,
+            2 => CacheKind::Instruction,
+            3 => CacheKind::Unified,
+            _ => return None,
+        };
+        let level = (regs.eax >> 5) & 0x7;
+        let fully_associative = regs.eax & (1 << 9) != 0;
+        let line_size = (regs.ebx & 0xFFF) + 1;
+        let partitions = ((regs.ebx >> 12) & 0x3FF) + 1;
+        let ways = ((regs.ebx >> 22) & 0x3FF) + 1;
+        let sets = regs.ecx + 1;
+        Some(Cache {
+            level,
+            kind,
+            size_kb: ways * partitions * line_size * sets / 1024,
+            line_size,
+            ways: if fully_associative { 0 } else { ways },
+        })
+    }
+
+    // AMD leaf 0x80000005: L1 data in ecx, L1 instruction in edx
+    pub fn amd_l1(reg: u32, kind: CacheKind) -> Option<Cache> {
+        let size_kb = reg >> 24;
+        if size_kb == 0 {
+            return None;
+        }
+        let ways = (reg >> 16) & 0xFF;
+        Some(Cache {
+            level: 1,
+            kind,
+            size_kb,
+            line_size: reg & 0xFF,
+            ways: if ways == 0xFF { 0 } else { ways },
+        })
+    }
+
+    // AMD leaf 0x80000006 ecx: unified L2, associativity as a 4 bit code
+    pub fn amd_l2(ecx: u32) -> Option<Cache> {
+        let size_kb = ecx >> 16;
+        if size_kb == 0 {
+            return None;
+        }
+        let ways = match (ecx >> 12) & 0xF {
+            0x1 => 1,
+            0x2 => 2,
+            0x4 => 4,
+            0x6 => 8,
+            0x8 => 16,
+            0xA => 32,
+            0xB => 48,
+            0xC => 64,
+            0xD => 96,
+            0xE => 128,
+            _ => 0,     // 0xF: fully associative
+        };
+        Some(Cache { level: 2, kind: CacheKind::Unified, size_kb, line_size: ecx & 0xFF, ways })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vendor_is_ebx_edx_ecx() {
+        // What leaf 0 returns on an Intel CPU
+        let regs = Regs { eax: 0xD, ebx: 0x756E_6547, ecx: 0x6C65_746E, edx: 0x4965_6E69 };
+        assert_eq!(as_str(&vendor(&regs)), "GenuineIntel");
+    }
+
+    #[test]
+    fn brand_is_trimmed() {
+        let mut text = [0u8; 48];
+        text[..20].copy_from_slice(b"   QEMU Virtual CPU ");
+        let word = |i: usize| u32::from_le_bytes([text[i], text[i + 1], text[i + 2], text[i + 3]]);
+        let leaf = |n: usize| Regs { eax: word(n * 16), ebx: word(n * 16 + 4), ecx: word(n * 16 + 8), edx: word(n * 16 + 12) };
+        let brand = brand(&[leaf(0), leaf(1), leaf(2)]);
+        assert_eq!(as_str(&brand), "QEMU Virtual CPU");
+    }
+
+    #[test]
+    fn signature_folds_extended_fields() {
+        // Family 6 uses the extended model (Skylake: 06_5E, stepping 3)
+        assert_eq!(Signature::decode(0x0005_06E3), Signature { family: 6, model: 0x5E, stepping: 3 });
+        // Family 15 adds the extended family (Zen 2: 17h)
+        assert_eq!(Signature::decode(0x0083_0F10), Signature { family: 0x17, model: 0x31, stepping: 0 });
+        // The 486 ignores both
+        assert_eq!(Signature::decode(0x0001_0480), Signature { family: 4, model: 8, stepping: 0 });
+    }
+
+    #[test]
+    fn features_come_from_their_leaf() {
+        let leaf1 = Regs { edx: 1 << 0 | 1 << 25, ecx: 1 << 30, ..Regs::default() };
+        let ext = Regs { edx: 1 << 20, ..Regs::default() };
+        let features = Features::new(&leaf1, &Regs::default(), &ext);
+        assert!(features.has(Feature::Fpu));
+        assert!(features.has(Feature::Sse));
+        assert!(features.has(Feature::Rdrand));
+        assert!(features.has(Feature::Nx));
+        assert!(!features.has(Feature::Sse2));
+        assert!(!features.has(Feature::Smep));
+        assert!(!Features::none().has(Feature::Fpu));
+    }
+
+    #[test]
+    fn leaf4_cache_size() {
+        // 32 KiB, 8-way, 64 byte lines, 64 sets L1 data
+        let regs = Regs { eax: 0x121, ebx: 0x01C0_003F, ecx: 63, edx: 0 };
+        let cache = Cache::from_leaf4(&regs).unwrap();
+        assert_eq!(cache, Cache { level: 1, kind: CacheKind::Data, size_kb: 32, line_size: 64, ways: 8 });
+        assert_eq!(Cache::from_leaf4(&Regs::default()), None);
+    }
+
+    #[test]
+    fn amd_cache_descriptors() {
+        let l1d = Cache::amd_l1(0x2008_0140, CacheKind::Data).unwrap();
+        assert_eq!((l1d.size_kb, l1d.ways, l1d.line_size), (32, 8, 64));
+        let l2 = Cache::amd_l2(0x0200_6140).unwrap();
+        assert_eq!((l2.size_kb, l2.ways, l2.line_size), (512, 8, 64));
+        assert_eq!(Cache::amd_l2(0), None);
+    }
+}
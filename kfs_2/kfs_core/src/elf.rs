@@ -0,0 +1,306 @@
+// elf.rs - ELF32 executables: header checks, PT_LOAD segments, initial stack
+//
+// Only what it takes to start a static i386 program: the file header, the
+// program headers and the stack layout the System V ABI promises _start
+// (argc, argv, envp and the auxiliary vector, with the strings above them).
+// Everything is bounds checked, since the file comes from outside the kernel.
+
+use core::fmt;
+
+pub const PT_LOAD: u32 = 1;
+
+pub const PF_X: u32 = 1 << 0;
+pub const PF_W: u32 = 1 << 1;
+pub const PF_R: u32 = 1 << 2;
+
+// Auxiliary vector entries
+pub const AT_NULL: u32 = 0;
+pub const AT_PHDR: u32 = 3;
+pub const AT_PHENT: u32 = 4;
+pub const AT_PHNUM: u32 = 5;
+pub const AT_PAGESZ: u32 = 6;
+pub const AT_ENTRY: u32 = 9;
+pub const AT_UID: u32 = 11;
+pub const AT_EUID: u32 = 12;
+pub const AT_GID: u32 = 13;
+pub const AT_EGID: u32 = 14;
+
+const HEADER_SIZE: usize = 52;
+pub const PHDR_SIZE: usize = 32;
+
+const ELFCLASS32: u8 = 1;
+const ELFDATA2LSB: u8 = 1;
+const EV_CURRENT: u8 = 1;
+const ET_EXEC: u16 = 2;
+const EM_386: u16 = 3;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ElfError {
+    TooShort,
+    BadMagic,
+    NotElf32,
+    NotLittleEndian,
+    BadVersion,
+    NotExecutable,
+    WrongMachine,
+    BadProgramHeaders,
+    BadSegment,
+    NoSegments,
+}
+
+impl fmt::Display for ElfError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ElfError::TooShort => "file too short for an ELF header",
+            ElfError::BadMagic => "not an ELF file",
+            ElfError::NotElf32 => "not a 32-bit ELF file",
+            ElfError::NotLittleEndian => "not little endian",
+            ElfError::BadVersion => "unknown ELF version",
+            ElfError::NotExecutable => "not an executable",
+            ElfError::WrongMachine => "not an i386 program",
+            ElfError::BadProgramHeaders => "program headers out of bounds",
+            ElfError::BadSegment => "segment out of bounds",
+            ElfError::NoSegments => "nothing to load",
+        })
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Segment {
+    pub vaddr: u32,
+    pub offset: u32,
+    pub filesz: u32,
+    pub memsz: u32,
+    pub flags: u32,
+}
+
+impl Segment {
+    pub fn writable(&self) -> bool {
+        self.flags & PF_W != 0
+    }
+
+    // One past the last byte in memory (checked not to overflow by parse())
+    pub fn end(&self) -> u32 {
+        self.vaddr + self.memsz
+    }
+}
+
+pub struct Elf<'a> {
+    data: &'a [u8],
+    pub entry: u32,
+    phoff: u32,
+    phnum: u16,
+}
+
+fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]])
+}
+
+impl<'a> Elf<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Elf<'a>, ElfError> {
+        if data.len() < HEADER_SIZE {
+            return Err(ElfError::TooShort);
+        }
+        if data[..4] != *b"\x7fELF" {
+            return Err(ElfError::BadMagic);
+        }
+        if data[4] != ELFCLASS32 {
+            return Err(ElfError::NotElf32);
+        }
+        if data[5] != ELFDATA2LSB {
+            return Err(ElfError::NotLittleEndian);
+        }
+        if data[6] != EV_CURRENT || u32_at(data, 20) != EV_CURRENT as u32 {
+            return Err(ElfError::BadVersion);
+        }
+        if u16_at(data, 16) != ET_EXEC {
+            return Err(ElfError::NotExecutable);
+        }
+        if u16_at(data, 18) != EM_386 {
+            return Err(ElfError::WrongMachine);
+        }
+
+        let elf = Elf { data, entry: u32_at(data, 24), phoff: u32_at(data, 28), phnum: u16_at(data, 44) };
+        let table_end = (elf.phoff as usize).checked_add(elf.phnum as usize * PHDR_SIZE);
+        if u16_at(data, 42) as usize != PHDR_SIZE || table_end.is_none_or(|end| end > data.len()) {
+            return Err(ElfError::BadProgramHeaders);
+        }
+        for segment in elf.segments() {
+            let file_end = segment.offset.checked_add(segment.filesz);
+            if segment.filesz > segment.memsz
+                || file_end.is_none_or(|end| end as usize > data.len())
+                || segment.vaddr.checked_add(segment.memsz).is_none()
+            {
+                return Err(ElfError::BadSegment);
+            }
+        }
+        if elf.segments().next().is_none() {
+            return Err(ElfError::NoSegments);
+        }
+        Ok(elf)
+    }
+
+    pub fn phnum(&self) -> u32 {
+        self.phnum as u32
+    }
+
+    // The PT_LOAD program headers, in file order
+    pub fn segments(&self) -> impl Iterator<Item = Segment> + '_ {
+        (0..self.phnum as usize).filter_map(move |i| {
+            let header = &self.data[self.phoff as usize + i * PHDR_SIZE..][..PHDR_SIZE];
+            (u32_at(header, 0) == PT_LOAD).then(|| Segment {
+                offset: u32_at(header, 4),
+                vaddr: u32_at(header, 8),
+                filesz: u32_at(header, 16),
+                memsz: u32_at(header, 20),
+                flags: u32_at(header, 24),
+            })
+        })
+    }
+
+    // What the file holds for `segment`; the rest up to memsz is .bss
+    pub fn file_bytes(&self, segment: &Segment) -> &'a [u8] {
+        &self.data[segment.offset as usize..(segment.offset + segment.filesz) as usize]
+    }
+
+    // Where the program headers end up in memory, if a segment loads them
+    pub fn phdr_addr(&self) -> Option<u32> {
+        let size = self.phnum as u32 * PHDR_SIZE as u32;
+        self.segments()
+            .find(|s| s.offset <= self.phoff && self.phoff + size <= s.offset + s.filesz)
+            .map(|s| s.vaddr + (self.phoff - s.offset))
+    }
+}
+
+// Lay out the initial stack of a program just below `top`: argc, the argv
+// and envp pointer arrays, the auxv pairs (each list ends with a 0), then
+// the strings. `stack` is the memory right below `top`. Returns the esp to
+// start with, 16-byte aligned, or None if it does not fit.
+pub fn build_stack(stack: &mut [u8], top: u32, argv: &[&str], envp: &[&str], auxv: &[(u32, u32)]) -> Option<u32> {
+    let bottom = top.checked_sub(stack.len() as u32)?;
+    let strings = argv.iter().chain(envp).map(|s| s.len() as u32 + 1).sum::<u32>();
+    let strings_start = top.checked_sub(strings)?;
+    let words = 1 + argv.len() + 1 + envp.len() + 1 + 2 * (auxv.len() + 1);
+    let esp = (strings_start & !0xF).checked_sub(words as u32 * 4)? & !0xF;
+    if esp < bottom {
+        return None;
+    }
+
+    let put = |stack: &mut [u8], addr: u32, bytes: &[u8]| {
+        let at = (addr - bottom) as usize;
+        stack[at..at + bytes.len()].copy_from_slice(bytes);
+    };
+    put(stack, esp, &(argv.len() as u32).to_le_bytes());
+    let mut slot = esp + 4;
+    let mut string = strings_start;
+    for list in [argv, envp] {
+        for s in list {
+            put(stack, string, s.as_bytes());
+            put(stack, string + s.len() as u32, &[0]);
+            put(stack, slot, &string.to_le_bytes());
+            slot += 4;
+            string += s.len() as u32 + 1;
+        }
+        put(stack, slot, &0u32.to_le_bytes());
+        slot += 4;
+    }
+    for &(key, value) in auxv.iter().chain(&[(AT_NULL, 0)]) {
+        put(stack, slot, &key.to_le_bytes());
+        put(stack, slot + 4, &value.to_le_bytes());
+        slot += 8;
+    }
+    Some(esp)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A minimal executable: header, two program headers, 16 bytes of code
+    fn sample() -> Vec<u8> {
+        let mut data = vec![0u8; HEADER_SIZE + 2 * PHDR_SIZE + 16];
+        data[..7].copy_from_slice(b"\x7fELF\x01\x01\x01");
+        data[16..18].copy_from_slice(&ET_EXEC.to_le_bytes());
+        data[18..20].copy_from_slice(&EM_386.to_le_bytes());
+        data[20..24].copy_from_slice(&1u32.to_le_bytes());
+        data[24..28].copy_from_slice(&0x8000_0074u32.to_le_bytes());
+        data[28..32].copy_from_slice(&(HEADER_SIZE as u32).to_le_bytes());
+        data[42..44].copy_from_slice(&(PHDR_SIZE as u16).to_le_bytes());
+        data[44..46].copy_from_slice(&2u16.to_le_bytes());
+        // Text from offset 0 (with the headers), then .bss without file bytes
+        let text = [PT_LOAD, 0, 0x8000_0000, 0, 0x84, 0x84, PF_R | PF_X, 0x1000];
+        let bss = [PT_LOAD, 0x84, 0x8000_1000, 0, 0, 0x2000, PF_R | PF_W, 0x1000];
+        for (i, header) in [text, bss].iter().enumerate() {
+            for (j, field) in header.iter().enumerate() {
+                let at = HEADER_SIZE + i * PHDR_SIZE + j * 4;
+                data[at..at + 4].copy_from_slice(&field.to_le_bytes());
+            }
+        }
+        data
+    }
+
+    #[test]
+    fn parses_segments() {
+        let data = sample();
+        let elf = Elf::parse(&data).unwrap();
+        assert_eq!(elf.entry, 0x8000_0074);
+        let segments: Vec<Segment> = elf.segments().collect();
+        assert_eq!(segments.len(), 2);
+        assert!(!segments[0].writable() && segments[1].writable());
+        assert_eq!(segments[1].end(), 0x8000_3000);
+        assert_eq!(elf.file_bytes(&segments[0]).len(), 0x84);
+        assert_eq!(elf.phdr_addr(), Some(0x8000_0000 + HEADER_SIZE as u32));
+    }
+
+    #[test]
+    fn rejects_bad_files() {
+        let data = sample();
+        assert_eq!(Elf::parse(&data[..40]).err(), Some(ElfError::TooShort));
+        let mut bad = data.clone();
+        bad[0] = 0;
+        assert_eq!(Elf::parse(&bad).err(), Some(ElfError::BadMagic));
+        let mut bad = data.clone();
+        bad[4] = 2;
+        assert_eq!(Elf::parse(&bad).err(), Some(ElfError::NotElf32));
+        let mut bad = data.clone();
+        bad[18] = 62;
+        assert_eq!(Elf::parse(&bad).err(), Some(ElfError::WrongMachine));
+        // Program headers past the end of the file
+        let mut bad = data.clone();
+        bad[44] = 200;
+        assert_eq!(Elf::parse(&bad).err(), Some(ElfError::BadProgramHeaders));
+        // File bytes past the end, and more file than memory
+        let mut bad = data.clone();
+        bad[HEADER_SIZE + 16] = 0xFF;
+        assert_eq!(Elf::parse(&bad).err(), Some(ElfError::BadSegment));
+        assert_eq!(ElfError::WrongMachine.to_string(), "not an i386 program");
+    }
+
+    #[test]
+    fn initial_stack_layout() {
+        let top = 0x1000;
+        let mut stack = [0u8; 256];
+        let esp = build_stack(&mut stack, top, &["ls", "-l"], &["A=1"], &[(AT_PAGESZ, 4096)]).unwrap();
+        assert_eq!(esp % 16, 0);
+        let word = |addr: u32| u32_at(&stack, (addr - (top - 256)) as usize);
+        let string = |addr: u32| {
+            let at = (addr - (top - 256)) as usize;
+            let len = stack[at..].iter().position(|&b| b == 0).unwrap();
+            std::str::from_utf8(&stack[at..at + len]).unwrap().to_string()
+        };
+        assert_eq!(word(esp), 2);
+        assert_eq!(string(word(esp + 4)), "ls");
+        assert_eq!(string(word(esp + 8)), "-l");
+        assert_eq!(word(esp + 12), 0);
+        assert_eq!(string(word(esp + 16)), "A=1");
+        assert_eq!(word(esp + 20), 0);
+        assert_eq!((word(esp + 24), word(esp + 28)), (AT_PAGESZ, 4096));
+        assert_eq!((word(esp + 32), word(esp + 36)), (AT_NULL, 0));
+        // Too little room
+        assert_eq!(build_stack(&mut [0u8; 16], top, &["ls"], &[], &[]), None);
+    }
+}
@@ -0,0 +1,122 @@
+// fpu.rs - Decoding x87 and SSE status registers for #MF and #XM reports
+
+use core::fmt;
+
+// Exception flags, bits 0-5 in both FSW and MXCSR (masks: FCW bits 0-5, MXCSR bits 7-12)
+const EXCEPTIONS: [&str; 6] = [
+    "invalid operation",
+    "denormal operand",
+    "divide by zero",
+    "overflow",
+    "underflow",
+    "precision",
+];
+
+const FLAG_BITS: u32 = 0x3F;
+const FSW_STACK_FAULT: u16 = 1 << 6;
+const FSW_C1: u16 = 1 << 9;
+const MXCSR_MASK_SHIFT: u32 = 7;
+
+// Default state after FNINIT / reset: every exception masked
+pub const DEFAULT_FCW: u16 = 0x037F;
+pub const DEFAULT_MXCSR: u32 = 0x1F80;
+
+fn write_flags(f: &mut fmt::Formatter, flags: u32) -> fmt::Result {
+    let mut first = true;
+    for (bit, name) in EXCEPTIONS.iter().enumerate() {
+        if flags & (1 << bit) != 0 {
+            if !first {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", name)?;
+            first = false;
+        }
+    }
+    if first {
+        write!(f, "no exception")?;
+    }
+    Ok(())
+}
+
+// x87 status word (FNSTSW) together with the control word that masks it
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct X87Status {
+    pub status: u16,
+    pub control: u16,
+}
+
+impl X87Status {
+    // Exceptions that are raised and not masked, i.e. the ones that caused #MF
+    pub fn pending(&self) -> u32 {
+        (self.status & !self.control) as u32 & FLAG_BITS
+    }
+
+    // Register stack top (ST0 is physical register `top`)
+    pub fn top(&self) -> u16 {
+        (self.status >> 11) & 0x7
+    }
+}
+
+impl fmt::Display for X87Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_flags(f, self.pending())?;
+        if self.status & FSW_STACK_FAULT != 0 {
+            // C1 tells overflow (push onto a full stack) from underflow
+            write!(f, " (stack {})", if self.status & FSW_C1 != 0 { "overflow" } else { "underflow" })?;
+        }
+        write!(f, " [FSW={:#06x} FCW={:#06x} top={}]", self.status, self.control, self.top())
+    }
+}
+
+// SSE control/status register
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Mxcsr(pub u32);
+
+impl Mxcsr {
+    pub fn pending(&self) -> u32 {
+        self.0 & !(self.0 >> MXCSR_MASK_SHIFT) & FLAG_BITS
+    }
+}
+
+impl fmt::Display for Mxcsr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_flags(f, self.pending())?;
+        write!(f, " [MXCSR={:#010x}]", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn x87_divide_by_zero() {
+        // ZE raised with ZM cleared, ES and B set, top 7
+        let status = X87Status { status: 0xB884, control: DEFAULT_FCW & !0x4 };
+        assert_eq!(status.pending(), 0x4);
+        assert_eq!(status.top(), 7);
+        assert_eq!(status.to_string(), "divide by zero [FSW=0xb884 FCW=0x037b top=7]");
+    }
+
+    #[test]
+    fn x87_masked_flags_are_not_pending() {
+        let status = X87Status { status: 0x0024, control: DEFAULT_FCW };
+        assert_eq!(status.pending(), 0);
+        assert!(status.to_string().starts_with("no exception"));
+    }
+
+    #[test]
+    fn x87_stack_overflow() {
+        let status = X87Status { status: 0x0241, control: DEFAULT_FCW & !0x1 };
+        assert!(status.to_string().starts_with("invalid operation (stack overflow)"));
+    }
+
+    #[test]
+    fn mxcsr_unmasked_flags() {
+        // Invalid and precision raised, only invalid unmasked
+        let mxcsr = Mxcsr(DEFAULT_MXCSR & !(1 << 7) | 0x21);
+        assert_eq!(mxcsr.pending(), 0x1);
+        assert_eq!(mxcsr.to_string(), "invalid operation [MXCSR=0x00001f21]");
+        assert_eq!(Mxcsr(DEFAULT_MXCSR).pending(), 0);
+    }
+}
@@ -0,0 +1,165 @@
+// frames.rs - Physical page frame allocator with reference counts
+//
+// One byte per 4 KiB frame: RESERVED for memory we must not hand out
+// (holes, the kernel image, boot modules), otherwise the number of users.
+// Frames shared copy-on-write after a fork have a count above one and only
+// become free again when the last address space lets go of them.
+
+use crate::paging::PAGE_SIZE;
+
+pub const RESERVED: u8 = u8::MAX;
+const MAX_REFS: u8 = RESERVED - 1;
+
+// Manages physical memory below N * 4 KiB
+pub struct FrameTable<const N: usize> {
+    refs: [u8; N],
+    free: usize,
+    next: usize,
+}
+
+impl<const N: usize> FrameTable<N> {
+    pub const fn new() -> Self {
+        FrameTable { refs: [RESERVED; N], free: 0, next: 0 }
+    }
+
+    // Frames lying completely inside start..end
+    fn range(start: u64, end: u64) -> core::ops::Range<usize> {
+        let page = PAGE_SIZE as u64;
+        let first = start.div_ceil(page).min(N as u64) as usize;
+        let last = (end / page).min(N as u64) as usize;
+        first..last.max(first)
+    }
+
+    // Make start..end available (from the memory map)
+    pub fn add_region(&mut self, start: u64, end: u64) {
+        for frame in Self::range(start, end) {
+            if self.refs[frame] == RESERVED {
+                self.refs[frame] = 0;
+                self.free += 1;
+            }
+        }
+    }
+
+    // Take every frame touching start..end out of circulation again
+    pub fn reserve(&mut self, start: u64, end: u64) {
+        let page = PAGE_SIZE as u64;
+        let first = (start / page).min(N as u64) as usize;
+        let last = end.div_ceil(page).min(N as u64) as usize;
+        for frame in first..last {
+            if self.refs[frame] == 0 {
+                self.free -= 1;
+            }
+            self.refs[frame] = RESERVED;
+        }
+    }
+
+    pub fn alloc(&mut self) -> Option<u32> {
+        for i in 0..N {
+            let frame = (self.next + i) % N;
+            if self.refs[frame] == 0 {
+                self.refs[frame] = 1;
+                self.free -= 1;
+                self.next = frame + 1;
+                return Some(frame as u32 * PAGE_SIZE);
+            }
+        }
+        None
+    }
+
+    // One more user of an allocated frame
+    pub fn share(&mut self, addr: u32) -> bool {
+        match self.refs.get_mut((addr / PAGE_SIZE) as usize) {
+            Some(refs) if *refs > 0 && *refs < MAX_REFS => {
+                *refs += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Drop a user; returns true when the frame became free
+    pub fn release(&mut self, addr: u32) -> bool {
+        let frame = (addr / PAGE_SIZE) as usize;
+        match self.refs.get_mut(frame) {
+            Some(refs) if *refs > 0 && *refs != RESERVED => {
+                *refs -= 1;
+                if *refs == 0 {
+                    self.free += 1;
+                    self.next = self.next.min(frame);
+                    return true;
+                }
+                false
+            }
+            _ => false,
+        }
+    }
+
+    pub fn refs(&self, addr: u32) -> u8 {
+        self.refs.get((addr / PAGE_SIZE) as usize).copied().unwrap_or(RESERVED)
+    }
+
+    pub fn free_frames(&self) -> usize {
+        self.free
+    }
+}
+
+impl<const N: usize> Default for FrameTable<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allocates_only_added_memory() {
+        let mut frames = FrameTable::<16>::new();
+        assert_eq!(frames.alloc(), None);
+        // Partial frames at the edges are not usable
+        frames.add_region(0x1800, 0x4000);
+        assert_eq!(frames.free_frames(), 2);
+        assert_eq!(frames.alloc(), Some(0x2000));
+        assert_eq!(frames.alloc(), Some(0x3000));
+        assert_eq!(frames.alloc(), None);
+    }
+
+    #[test]
+    fn reserve_takes_out_touched_frames() {
+        let mut frames = FrameTable::<16>::new();
+        frames.add_region(0, 0x10000);
+        frames.reserve(0x1000, 0x2001);
+        assert_eq!(frames.free_frames(), 14);
+        assert_eq!(frames.refs(0x2000), RESERVED);
+        // Memory beyond the table is ignored
+        frames.add_region(0x10000, 0x20000);
+        assert_eq!(frames.free_frames(), 14);
+    }
+
+    #[test]
+    fn shared_frames_free_with_the_last_user() {
+        let mut frames = FrameTable::<4>::new();
+        frames.add_region(0, 0x4000);
+        let frame = frames.alloc().unwrap();
+        assert!(frames.share(frame));
+        assert_eq!(frames.refs(frame), 2);
+        assert!(!frames.release(frame));
+        assert!(frames.release(frame));
+        assert_eq!(frames.free_frames(), 4);
+        // Free and reserved frames cannot be shared or released
+        assert!(!frames.share(frame));
+        assert!(!frames.release(frame));
+        assert!(!frames.release(0x10000));
+    }
+
+    #[test]
+    fn freed_frames_are_reused_first() {
+        let mut frames = FrameTable::<8>::new();
+        frames.add_region(0, 0x8000);
+        let a = frames.alloc().unwrap();
+        let _b = frames.alloc().unwrap();
+        frames.release(a);
+        assert_eq!(frames.alloc(), Some(a));
+    }
+}
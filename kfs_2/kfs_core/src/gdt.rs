@@ -0,0 +1,340 @@
+// gdt.rs - Segment descriptor encoding
+//
+// GdtEntry is the raw 8-byte descriptor; Descriptor builds one from typed
+// arguments instead of hand-assembled access and flag bytes, and
+// GdtEntry::decode() turns one back into words for the `gdt` command.
+
+use core::fmt;
+use crate::tss::{Tss, TSS_SIZE};
+
+// Access byte
+pub const ACCESS_PRESENT: u8 = 0x80;
+pub const ACCESS_DPL_SHIFT: u8 = 5;
+pub const ACCESS_SEGMENT: u8 = 0x10;      // Code/data (clear for system descriptors)
+pub const ACCESS_EXECUTABLE: u8 = 0x08;
+pub const ACCESS_DIRECTION: u8 = 0x04;    // Conforming code / expand-down data
+pub const ACCESS_RW: u8 = 0x02;           // Readable code / writable data
+pub const ACCESS_ACCESSED: u8 = 0x01;
+
+// System descriptor types (access bits 0-3 with ACCESS_SEGMENT clear)
+pub const TYPE_LDT: u8 = 0x2;
+pub const TYPE_TASK_GATE: u8 = 0x5;
+pub const TYPE_TSS_AVAILABLE: u8 = 0x9;
+pub const TYPE_TSS_BUSY: u8 = 0xB;
+pub const TYPE_CALL_GATE: u8 = 0xC;
+
+// Flags nibble (high half of the granularity byte)
+pub const FLAG_GRANULARITY_4K: u8 = 0x80;
+pub const FLAG_32BIT: u8 = 0x40;
+pub const FLAG_LONG_MODE: u8 = 0x20;
+
+// GDT Entry structure (8 bytes)
+#[repr(C, packed)]
+#[derive(Copy, Clone)]
+pub struct GdtEntry {
+    limit_low: u16,    // Lower 16 bits of limit
+    base_low: u16,     // Lower 16 bits of base
+    base_middle: u8,   // Next 8 bits of base
+    access: u8,        // Access flags
+    granularity: u8,   // Granularity and limit bits 16-19
+    base_high: u8,     // Last 8 bits of base
+}
+
+impl GdtEntry {
+    // Create a null entry
+    pub const fn null() -> GdtEntry {
+        GdtEntry {
+            limit_low: 0,
+            base_low: 0,
+            base_middle: 0,
+            access: 0,
+            granularity: 0,
+            base_high: 0,
+        }
+    }
+
+    // Create a GDT entry
+    // base: Base address
+    // limit: Limit (max offset)
+    // access: Access byte
+    // gran: Granularity byte
+    pub const fn new(base: u32, limit: u32, access: u8, gran: u8) -> GdtEntry {
+        GdtEntry {
+            limit_low: (limit & 0xFFFF) as u16,
+            base_low: (base & 0xFFFF) as u16,
+            base_middle: ((base >> 16) & 0xFF) as u8,
+            access,
+            granularity: ((limit >> 16) & 0x0F) as u8 | (gran & 0xF0),
+            base_high: ((base >> 24) & 0xFF) as u8,
+        }
+    }
+
+    pub fn base(&self) -> u32 {
+        self.base_low as u32 | (self.base_middle as u32) << 16 | (self.base_high as u32) << 24
+    }
+
+    // 20-bit limit, in bytes or 4 KiB pages depending on the granularity flag
+    pub fn limit(&self) -> u32 {
+        self.limit_low as u32 | ((self.granularity & 0x0F) as u32) << 16
+    }
+
+    pub fn access(&self) -> u8 {
+        self.access
+    }
+
+    // Flags in the high nibble, limit bits 16-19 in the low one
+    pub fn granularity(&self) -> u8 {
+        self.granularity
+    }
+
+    pub fn flags(&self) -> u8 {
+        self.granularity & 0xF0
+    }
+
+    pub fn is_null(&self) -> bool {
+        self.access == 0 && self.limit() == 0 && self.base() == 0
+    }
+
+    pub fn present(&self) -> bool {
+        self.access & ACCESS_PRESENT != 0
+    }
+
+    pub fn dpl(&self) -> u8 {
+        (self.access >> ACCESS_DPL_SHIFT) & 0x3
+    }
+
+    // Readable words, e.g. "Present DPL3 Code R/X 4K 32-bit"
+    pub fn decode(&self) -> Decoded {
+        Decoded(*self)
+    }
+}
+
+pub struct Decoded(GdtEntry);
+
+impl fmt::Display for Decoded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let e = &self.0;
+        if e.is_null() {
+            return write!(f, "Null");
+        }
+        write!(f, "{} DPL{} ", if e.present() { "Present" } else { "Not present" }, e.dpl())?;
+
+        let access = e.access;
+        if access & ACCESS_SEGMENT != 0 {
+            if access & ACCESS_EXECUTABLE != 0 {
+                write!(f, "Code {}", if access & ACCESS_RW != 0 { "R/X" } else { "X" })?;
+                if access & ACCESS_DIRECTION != 0 {
+                    write!(f, " Conforming")?;
+                }
+            } else {
+                write!(f, "Data {}", if access & ACCESS_RW != 0 { "R/W" } else { "R" })?;
+                if access & ACCESS_DIRECTION != 0 {
+                    write!(f, " Expand-down")?;
+                }
+            }
+            if access & ACCESS_ACCESSED != 0 {
+                write!(f, " Accessed")?;
+            }
+        } else {
+            let name = match access & 0x0F {
+                TYPE_LDT => "LDT",
+                TYPE_TASK_GATE => "Task Gate",
+                TYPE_TSS_AVAILABLE => "TSS32 Available",
+                TYPE_TSS_BUSY => "TSS32 Busy",
+                TYPE_CALL_GATE => "Call Gate",
+                _ => "System (reserved type)",
+            };
+            write!(f, "{}", name)?;
+        }
+
+        let flags = e.flags();
+        write!(f, " {}", if flags & FLAG_GRANULARITY_4K != 0 { "4K" } else { "Byte" })?;
+        // D/B and L only mean something for code and data segments
+        if access & ACCESS_SEGMENT == 0 {
+            return Ok(());
+        }
+        let size = if flags & FLAG_LONG_MODE != 0 {
+            "64-bit"
+        } else if flags & FLAG_32BIT != 0 {
+            "32-bit"
+        } else {
+            "16-bit"
+        };
+        write!(f, " {}", size)
+    }
+}
+
+// Typed descriptor builder. Code and data segments start out flat: base 0,
+// 4 GiB limit in 4 KiB units, 32-bit.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Descriptor {
+    base: u32,
+    limit: u32,
+    access: u8,
+    flags: u8,
+}
+
+impl Descriptor {
+    const fn flat(dpl: u8, access: u8) -> Descriptor {
+        Descriptor {
+            base: 0,
+            limit: 0xFFFFF,
+            access: ACCESS_PRESENT | (dpl & 0x3) << ACCESS_DPL_SHIFT | ACCESS_SEGMENT | access,
+            flags: FLAG_GRANULARITY_4K | FLAG_32BIT,
+        }
+    }
+
+    pub const fn code(dpl: u8, readable: bool, conforming: bool) -> Descriptor {
+        let mut access = ACCESS_EXECUTABLE;
+        if readable {
+            access |= ACCESS_RW;
+        }
+        if conforming {
+            access |= ACCESS_DIRECTION;
+        }
+        Descriptor::flat(dpl, access)
+    }
+
+    pub const fn data(dpl: u8, writable: bool, expand_down: bool) -> Descriptor {
+        let mut access = 0;
+        if writable {
+            access |= ACCESS_RW;
+        }
+        if expand_down {
+            access |= ACCESS_DIRECTION;
+        }
+        Descriptor::flat(dpl, access)
+    }
+
+    // 32-bit available TSS covering exactly `tss`, byte granular
+    pub fn tss(tss: &Tss) -> Descriptor {
+        Descriptor {
+            base: tss as *const Tss as usize as u32,
+            limit: TSS_SIZE as u32 - 1,
+            access: ACCESS_PRESENT | TYPE_TSS_AVAILABLE,
+            flags: 0,
+        }
+    }
+
+    pub const fn base(mut self, base: u32) -> Descriptor {
+        self.base = base;
+        self
+    }
+
+    // In bytes or 4 KiB pages, depending on the granularity
+    pub const fn limit(mut self, limit: u32) -> Descriptor {
+        self.limit = limit;
+        self
+    }
+
+    pub const fn byte_granular(mut self) -> Descriptor {
+        self.flags &= !FLAG_GRANULARITY_4K;
+        self
+    }
+
+    pub const fn page_granular(mut self) -> Descriptor {
+        self.flags |= FLAG_GRANULARITY_4K;
+        self
+    }
+
+    pub const fn bits16(mut self) -> Descriptor {
+        self.flags &= !FLAG_32BIT;
+        self
+    }
+
+    pub const fn bits32(mut self) -> Descriptor {
+        self.flags |= FLAG_32BIT;
+        self
+    }
+
+    pub const fn entry(self) -> GdtEntry {
+        GdtEntry::new(self.base, self.limit, self.access, self.flags)
+    }
+}
+
+impl From<Descriptor> for GdtEntry {
+    fn from(descriptor: Descriptor) -> GdtEntry {
+        descriptor.entry()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entry_splits_base_and_limit() {
+        let e = GdtEntry::new(0x12345678, 0xABCDE, 0x9A, 0xC0);
+        assert_eq!({ e.base_low }, 0x5678);
+        assert_eq!(e.base_middle, 0x34);
+        assert_eq!(e.base_high, 0x12);
+        assert_eq!({ e.limit_low }, 0xBCDE);
+        assert_eq!(e.granularity, 0xCA);
+        assert_eq!(e.access, 0x9A);
+    }
+
+    #[test]
+    fn accessors_reassemble_fields() {
+        let e = GdtEntry::new(0xDEADBEEF, 0x12345, 0xF2, 0xC0);
+        assert_eq!(e.base(), 0xDEADBEEF);
+        assert_eq!(e.limit(), 0x12345);
+        assert_eq!(e.access(), 0xF2);
+    }
+
+    #[test]
+    fn granularity_keeps_only_flag_bits() {
+        // The low nibble belongs to the limit, flags may not leak into it
+        let e = GdtEntry::new(0, 0x10000, 0x92, 0xCF);
+        assert_eq!(e.granularity, 0xC1);
+    }
+
+    #[test]
+    fn limit_is_truncated_to_20_bits() {
+        assert_eq!(GdtEntry::new(0, 0xFFFF_FFFF, 0x92, 0xC0).limit(), 0xFFFFF);
+    }
+
+    #[test]
+    fn builders_match_the_classic_bytes() {
+        assert_eq!(Descriptor::code(0, true, false).entry().access(), 0x9A);
+        assert_eq!(Descriptor::data(0, true, false).entry().access(), 0x92);
+        assert_eq!(Descriptor::code(3, true, false).entry().access(), 0xFA);
+        assert_eq!(Descriptor::data(3, true, false).entry().access(), 0xF2);
+        let e = Descriptor::code(0, true, false).entry();
+        assert_eq!((e.base(), e.limit(), e.granularity()), (0, 0xFFFFF, 0xCF));
+    }
+
+    #[test]
+    fn builder_flags() {
+        let e = Descriptor::data(0, false, true).base(0x1000).limit(0xFFFF).byte_granular().bits16().entry();
+        assert_eq!(e.access(), 0x94);
+        assert_eq!(e.flags(), 0);
+        assert_eq!((e.base(), e.limit()), (0x1000, 0xFFFF));
+        assert_eq!(Descriptor::code(0, false, true).entry().access(), 0x9C);
+    }
+
+    #[test]
+    fn tss_descriptor() {
+        let tss = Tss::new();
+        let e = Descriptor::tss(&tss).entry();
+        assert_eq!(e.access(), 0x89);
+        assert_eq!(e.limit(), 103);
+        assert_eq!(e.flags(), 0);
+    }
+
+    #[test]
+    fn decode_words() {
+        let text = |d: Descriptor| d.entry().decode().to_string();
+        assert_eq!(text(Descriptor::code(3, true, false)), "Present DPL3 Code R/X 4K 32-bit");
+        assert_eq!(text(Descriptor::data(0, true, false)), "Present DPL0 Data R/W 4K 32-bit");
+        assert_eq!(text(Descriptor::code(0, false, true).bits16()), "Present DPL0 Code X Conforming 4K 16-bit");
+        assert_eq!(text(Descriptor::data(0, false, true).byte_granular()), "Present DPL0 Data R Expand-down Byte 32-bit");
+        assert_eq!(GdtEntry::new(0, 103, 0x8B, 0).decode().to_string(), "Present DPL0 TSS32 Busy Byte");
+        assert_eq!(GdtEntry::null().decode().to_string(), "Null");
+        assert_eq!(GdtEntry::new(0, 0xFFFFF, 0x12, 0xC0).decode().to_string(), "Not present DPL0 Data R/W 4K 32-bit");
+    }
+
+    #[test]
+    fn entries_are_8_bytes() {
+        assert_eq!(core::mem::size_of::<GdtEntry>(), 8);
+    }
+}
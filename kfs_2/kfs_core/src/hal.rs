@@ -0,0 +1,17 @@
+// hal.rs - The little hardware access the portable code needs
+//
+// The kernel implements this with in/out instructions and the text buffer at
+// 0xB8000; tests use mock::MockHal, which records every access instead.
+
+pub trait Hal {
+    fn outb(&mut self, port: u16, value: u8);
+    fn outw(&mut self, port: u16, value: u16);
+    fn outl(&mut self, port: u16, value: u32);
+    fn inb(&mut self, port: u16) -> u8;
+    fn inw(&mut self, port: u16) -> u16;
+    fn inl(&mut self, port: u16) -> u32;
+
+    // Text mode buffer, one cell per character: byte | attribute << 8
+    fn write_cell(&mut self, index: usize, cell: u16);
+    fn read_cell(&mut self, index: usize) -> u16;
+}
@@ -0,0 +1,134 @@
+// keymap.rs - Scancode (set 1) to ASCII tables for the supported layouts
+//
+// Only keys that produce ASCII are mapped; umlauts, ß and dead keys give 0
+// and are ignored like any other unmapped key.
+
+const KEY_ISO: usize = 0x56;    // Extra key left of Y/Z on ISO keyboards
+
+pub struct Keymap {
+    pub name: &'static str,
+    normal: [u8; 128],
+    shifted: [u8; 128],
+    altgr: &'static [(u8, u8)],    // (scancode, ASCII) pairs typed with AltGr
+}
+
+impl Keymap {
+    // ASCII for a make code with the given modifiers, 0 if the key has none
+    pub fn translate(&self, code: u8, shift: bool, altgr: bool) -> u8 {
+        if altgr {
+            return self.altgr.iter().find(|&&(c, _)| c == code).map_or(0, |&(_, ascii)| ascii);
+        }
+        let table = if shift { &self.shifted } else { &self.normal };
+        table.get(code as usize).copied().unwrap_or(0)
+    }
+}
+
+// Expand the main block (0x00-0x39) and the ISO key into a full table
+const fn table(main: [u8; 0x3A], iso: u8) -> [u8; 128] {
+    let mut full = [0u8; 128];
+    let mut i = 0;
+    while i < main.len() {
+        full[i] = main[i];
+        i += 1;
+    }
+    full[KEY_ISO] = iso;
+    full
+}
+
+pub static US: Keymap = Keymap {
+    name: "us",
+    normal: table([
+        0,    27,  b'1', b'2', b'3', b'4', b'5', b'6',   // 0x00-0x07
+        b'7', b'8', b'9', b'0', b'-', b'=', 8,   b'\t',  // 0x08-0x0F (backspace, tab)
+        b'q', b'w', b'e', b'r', b't', b'y', b'u', b'i',  // 0x10-0x17
+        b'o', b'p', b'[', b']', b'\n', 0,   b'a', b's',  // 0x18-0x1F (enter, ctrl)
+        b'd', b'f', b'g', b'h', b'j', b'k', b'l', b';',  // 0x20-0x27
+        b'\'', b'`', 0,   b'\\', b'z', b'x', b'c', b'v', // 0x28-0x2F (shift)
+        b'b', b'n', b'm', b',', b'.', b'/', 0,   b'*',   // 0x30-0x37 (shift, *)
+        0,    b' ',                                      // 0x38-0x39 (alt, space)
+    ], b'\\'),
+    shifted: table([
+        0,    27,  b'!', b'@', b'#', b'$', b'%', b'^',
+        b'&', b'*', b'(', b')', b'_', b'+', 8,   b'\t',
+        b'Q', b'W', b'E', b'R', b'T', b'Y', b'U', b'I',
+        b'O', b'P', b'{', b'}', b'\n', 0,   b'A', b'S',
+        b'D', b'F', b'G', b'H', b'J', b'K', b'L', b':',
+        b'"', b'~', 0,   b'|', b'Z', b'X', b'C', b'V',
+        b'B', b'N', b'M', b'<', b'>', b'?', 0,   b'*',
+        0,    b' ',
+    ], b'|'),
+    altgr: &[],
+};
+
+pub static DE: Keymap = Keymap {
+    name: "de",
+    normal: table([
+        0,    27,  b'1', b'2', b'3', b'4', b'5', b'6',   // 0x00-0x07
+        b'7', b'8', b'9', b'0', 0,   0,    8,   b'\t',   // 0x08-0x0F (ß, dead ´)
+        b'q', b'w', b'e', b'r', b't', b'z', b'u', b'i',  // 0x10-0x17
+        b'o', b'p', 0,   b'+', b'\n', 0,   b'a', b's',   // 0x18-0x1F (ü)
+        b'd', b'f', b'g', b'h', b'j', b'k', b'l', 0,     // 0x20-0x27 (ö)
+        0,    b'^', 0,   b'#', b'y', b'x', b'c', b'v',   // 0x28-0x2F (ä)
+        b'b', b'n', b'm', b',', b'.', b'-', 0,   b'*',   // 0x30-0x37
+        0,    b' ',                                      // 0x38-0x39
+    ], b'<'),
+    shifted: table([
+        0,    27,  b'!', b'"', 0,    b'$', b'%', b'&',   // (§)
+        b'/', b'(', b')', b'=', b'?', b'`', 8,   b'\t',
+        b'Q', b'W', b'E', b'R', b'T', b'Z', b'U', b'I',
+        b'O', b'P', 0,   b'*', b'\n', 0,   b'A', b'S',
+        b'D', b'F', b'G', b'H', b'J', b'K', b'L', 0,
+        0,    0,   0,   b'\'', b'Y', b'X', b'C', b'V',   // (°)
+        b'B', b'N', b'M', b';', b':', b'_', 0,   b'*',
+        0,    b' ',
+    ], b'>'),
+    altgr: &[
+        (0x10, b'@'), (0x08, b'{'), (0x09, b'['), (0x0A, b']'), (0x0B, b'}'),
+        (0x0C, b'\\'), (0x1B, b'~'), (0x56, b'|'),
+    ],
+};
+
+pub static KEYMAPS: [&Keymap; 2] = [&DE, &US];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tables_are_ascii() {
+        for keymap in KEYMAPS {
+            for &ascii in keymap.normal.iter().chain(keymap.shifted.iter()) {
+                assert!(ascii < 0x80);
+            }
+        }
+    }
+
+    #[test]
+    fn letters_shift_to_uppercase() {
+        for keymap in KEYMAPS {
+            for code in 0..128u8 {
+                let lower = keymap.translate(code, false, false);
+                if lower.is_ascii_lowercase() {
+                    assert_eq!(keymap.translate(code, true, false), lower.to_ascii_uppercase());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn y_and_z_swap_between_layouts() {
+        assert_eq!(US.translate(0x15, false, false), b'y');
+        assert_eq!(US.translate(0x2C, false, false), b'z');
+        assert_eq!(DE.translate(0x15, false, false), b'z');
+        assert_eq!(DE.translate(0x2C, false, false), b'y');
+    }
+
+    #[test]
+    fn german_symbols() {
+        assert_eq!(DE.translate(0x03, true, false), b'"');
+        assert_eq!(DE.translate(0x35, false, false), b'-');
+        assert_eq!(DE.translate(0x10, false, true), b'@');
+        assert_eq!(DE.translate(0x56, false, true), b'|');
+        assert_eq!(DE.translate(0x1A, false, false), 0);    // ü has no ASCII
+    }
+}
@@ -0,0 +1,24 @@
+// kfs_core - The hardware independent parts of the kernel
+//
+// Everything in here is plain computation or reaches the machine only through
+// the `Hal` trait, so it builds both for the kernel target (no_std) and for
+// the host, where `cargo test` runs it against `mock::MockHal`.
+
+#![cfg_attr(not(test), no_std)]
+
+pub mod hal;
+pub mod port;
+#[cfg(test)]
+pub mod mock;
+
+pub mod cpuid;
+pub mod elf;
+pub mod fpu;
+pub mod frames;
+pub mod gdt;
+pub mod keymap;
+pub mod paging;
+pub mod shell;
+pub mod signal;
+pub mod tss;
+pub mod vga;
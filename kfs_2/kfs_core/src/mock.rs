@@ -0,0 +1,96 @@
+// mock.rs - Hal that records port I/O and text buffer writes for host tests
+
+use std::collections::VecDeque;
+use std::string::String;
+use std::vec::Vec;
+
+use crate::hal::Hal;
+use crate::vga::{VGA_MAX_HEIGHT, VGA_WIDTH};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Event {
+    Out8(u16, u8),
+    Out16(u16, u16),
+    Out32(u16, u32),
+    In8(u16),
+    In16(u16),
+    In32(u16),
+    Cell(usize, u16),
+}
+
+pub struct MockHal {
+    pub events: Vec<Event>,
+    pub cells: Vec<u16>,
+    // Values handed out by the next in* calls (0 once empty)
+    pub reads: VecDeque<u32>,
+}
+
+impl MockHal {
+    pub fn new() -> MockHal {
+        MockHal {
+            events: Vec::new(),
+            cells: vec![0; VGA_WIDTH * VGA_MAX_HEIGHT],
+            reads: VecDeque::new(),
+        }
+    }
+
+    // Port accesses only, in order
+    pub fn port_events(&self) -> Vec<Event> {
+        self.events.iter().copied().filter(|e| !matches!(e, Event::Cell(..))).collect()
+    }
+
+    // Characters of one text row, trailing blanks removed
+    pub fn row_text(&self, row: usize) -> String {
+        let cells = &self.cells[row * VGA_WIDTH..(row + 1) * VGA_WIDTH];
+        let text: String = cells.iter().map(|&c| (c as u8) as char).collect();
+        text.trim_end_matches([' ', '\0']).into()
+    }
+
+    fn next_read(&mut self) -> u32 {
+        self.reads.pop_front().unwrap_or(0)
+    }
+}
+
+impl Default for MockHal {
+    fn default() -> MockHal {
+        MockHal::new()
+    }
+}
+
+impl Hal for MockHal {
+    fn outb(&mut self, port: u16, value: u8) {
+        self.events.push(Event::Out8(port, value));
+    }
+
+    fn outw(&mut self, port: u16, value: u16) {
+        self.events.push(Event::Out16(port, value));
+    }
+
+    fn outl(&mut self, port: u16, value: u32) {
+        self.events.push(Event::Out32(port, value));
+    }
+
+    fn inb(&mut self, port: u16) -> u8 {
+        self.events.push(Event::In8(port));
+        self.next_read() as u8
+    }
+
+    fn inw(&mut self, port: u16) -> u16 {
+        self.events.push(Event::In16(port));
+        self.next_read() as u16
+    }
+
+    fn inl(&mut self, port: u16) -> u32 {
+        self.events.push(Event::In32(port));
+        self.next_read()
+    }
+
+    fn write_cell(&mut self, index: usize, cell: u16) {
+        self.events.push(Event::Cell(index, cell));
+        self.cells[index] = cell;
+    }
+
+    fn read_cell(&mut self, index: usize) -> u16 {
+        self.cells[index]
+    }
+}
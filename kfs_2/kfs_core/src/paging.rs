@@ -0,0 +1,169 @@
+// paging.rs - 32-bit page directory/table entries and page fault error codes
+//
+// Both levels share one entry format: the frame address in the top 20 bits
+// and flags below. Bits 9-11 are left to the OS; we use one of them to mark
+// pages shared copy-on-write after a fork.
+
+use core::fmt;
+
+pub const PAGE_SIZE: u32 = 4096;
+pub const ENTRIES: usize = 1024;
+// Bytes covered by one directory entry (a whole page table, or a 4 MiB page)
+pub const TABLE_SPAN: u32 = PAGE_SIZE * ENTRIES as u32;
+
+pub const PRESENT: u32 = 1 << 0;
+pub const WRITABLE: u32 = 1 << 1;
+pub const USER: u32 = 1 << 2;
+pub const ACCESSED: u32 = 1 << 5;
+pub const DIRTY: u32 = 1 << 6;
+pub const HUGE: u32 = 1 << 7;       // 4 MiB page, directory entries only (CR4.PSE)
+pub const COW: u32 = 1 << 9;        // Read-only until the next write copies it
+
+const ADDRESS_MASK: u32 = !(PAGE_SIZE - 1);
+
+#[repr(transparent)]
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub struct Entry(pub u32);
+
+impl Entry {
+    pub const EMPTY: Entry = Entry(0);
+
+    pub const fn new(addr: u32, flags: u32) -> Entry {
+        Entry((addr & ADDRESS_MASK) | (flags & !ADDRESS_MASK))
+    }
+
+    pub fn addr(&self) -> u32 {
+        self.0 & ADDRESS_MASK
+    }
+
+    pub fn flags(&self) -> u32 {
+        self.0 & !ADDRESS_MASK
+    }
+
+    pub fn has(&self, flag: u32) -> bool {
+        self.0 & flag == flag
+    }
+
+    pub fn present(&self) -> bool {
+        self.has(PRESENT)
+    }
+
+    // Same page with `flags` set and `clear` cleared
+    pub fn with(&self, set: u32, clear: u32) -> Entry {
+        Entry((self.0 & !clear) | set)
+    }
+
+    // The entry both sides of a fork get: writable pages turn read-only + COW
+    pub fn shared(&self) -> Entry {
+        if self.has(WRITABLE) {
+            self.with(COW, WRITABLE)
+        } else {
+            *self
+        }
+    }
+}
+
+pub fn directory_index(addr: u32) -> usize {
+    (addr >> 22) as usize
+}
+
+pub fn table_index(addr: u32) -> usize {
+    ((addr >> 12) & 0x3FF) as usize
+}
+
+pub fn align_down(addr: u32) -> u32 {
+    addr & ADDRESS_MASK
+}
+
+pub fn align_up(addr: u32) -> Option<u32> {
+    addr.checked_next_multiple_of(PAGE_SIZE)
+}
+
+// Error code pushed with #PF
+pub const FAULT_PRESENT: u32 = 1 << 0;     // Protection violation, not a missing page
+pub const FAULT_WRITE: u32 = 1 << 1;
+pub const FAULT_USER: u32 = 1 << 2;
+pub const FAULT_RESERVED: u32 = 1 << 3;
+pub const FAULT_FETCH: u32 = 1 << 4;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct PageFault {
+    pub addr: u32,      // CR2
+    pub error: u32,
+}
+
+impl PageFault {
+    pub fn write(&self) -> bool {
+        self.error & FAULT_WRITE != 0
+    }
+
+    pub fn protection(&self) -> bool {
+        self.error & FAULT_PRESENT != 0
+    }
+
+    pub fn user(&self) -> bool {
+        self.error & FAULT_USER != 0
+    }
+}
+
+impl fmt::Display for PageFault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let access = if self.error & FAULT_FETCH != 0 {
+            "fetch from"
+        } else if self.write() {
+            "write to"
+        } else {
+            "read from"
+        };
+        let page = if self.protection() { "protected" } else { "non-present" };
+        let mode = if self.user() { "user" } else { "kernel" };
+        write!(f, "{} {} page at {:#010x} in {} mode", access, page, self.addr, mode)?;
+        if self.error & FAULT_RESERVED != 0 {
+            write!(f, " (reserved bit set)")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_split_address_and_flags() {
+        let entry = Entry::new(0x0012_3456, PRESENT | WRITABLE | USER);
+        assert_eq!(entry.addr(), 0x0012_3000);
+        assert_eq!(entry.flags(), 0x7);
+        assert!(entry.present());
+        assert!(!Entry::EMPTY.present());
+    }
+
+    #[test]
+    fn sharing_makes_writable_pages_cow() {
+        let entry = Entry::new(0x5000, PRESENT | WRITABLE | USER);
+        let shared = entry.shared();
+        assert!(shared.has(COW) && !shared.has(WRITABLE));
+        assert_eq!(shared.addr(), 0x5000);
+        // Read-only pages stay read-only and are not copied on write
+        let read_only = Entry::new(0x6000, PRESENT | USER);
+        assert_eq!(read_only.shared(), read_only);
+        assert_eq!(shared.with(WRITABLE, COW), entry);
+    }
+
+    #[test]
+    fn address_indices() {
+        assert_eq!(directory_index(0x8040_2000), 513);
+        assert_eq!(table_index(0x8040_2000), 2);
+        assert_eq!(align_down(0x1FFF), 0x1000);
+        assert_eq!(align_up(0x1001), Some(0x2000));
+        assert_eq!(align_up(0xFFFF_F001), None);
+    }
+
+    #[test]
+    fn page_fault_reports() {
+        let fault = PageFault { addr: 0x8000_1000, error: FAULT_PRESENT | FAULT_WRITE | FAULT_USER };
+        assert_eq!(fault.to_string(), "write to protected page at 0x80001000 in user mode");
+        let fault = PageFault { addr: 0, error: 0 };
+        assert_eq!(fault.to_string(), "read from non-present page at 0x00000000 in kernel mode");
+    }
+}
@@ -0,0 +1,149 @@
+// port.rs - Typed x86 I/O ports
+//
+// Drivers declare their registers as constants, e.g.
+//   const KEYBOARD_DATA: Port<u8> = Port::new(0x60);
+// and every access goes through a Hal, the only code that executes in/out.
+// Registers that must not be written (or read) use the one-way variants.
+
+use core::marker::PhantomData;
+use crate::hal::Hal;
+
+// Widths a port can be accessed with
+pub trait PortValue: Copy {
+    fn read_from<H: Hal>(hal: &mut H, port: u16) -> Self;
+    fn write_to<H: Hal>(self, hal: &mut H, port: u16);
+}
+
+impl PortValue for u8 {
+    fn read_from<H: Hal>(hal: &mut H, port: u16) -> u8 {
+        hal.inb(port)
+    }
+
+    fn write_to<H: Hal>(self, hal: &mut H, port: u16) {
+        hal.outb(port, self)
+    }
+}
+
+impl PortValue for u16 {
+    fn read_from<H: Hal>(hal: &mut H, port: u16) -> u16 {
+        hal.inw(port)
+    }
+
+    fn write_to<H: Hal>(self, hal: &mut H, port: u16) {
+        hal.outw(port, self)
+    }
+}
+
+impl PortValue for u32 {
+    fn read_from<H: Hal>(hal: &mut H, port: u16) -> u32 {
+        hal.inl(port)
+    }
+
+    fn write_to<H: Hal>(self, hal: &mut H, port: u16) {
+        hal.outl(port, self)
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct Port<T> {
+    port: u16,
+    value: PhantomData<T>,
+}
+
+#[derive(Copy, Clone)]
+pub struct ReadOnlyPort<T> {
+    port: u16,
+    value: PhantomData<T>,
+}
+
+#[derive(Copy, Clone)]
+pub struct WriteOnlyPort<T> {
+    port: u16,
+    value: PhantomData<T>,
+}
+
+impl<T: PortValue> Port<T> {
+    pub const fn new(port: u16) -> Port<T> {
+        Port { port, value: PhantomData }
+    }
+
+    pub const fn number(&self) -> u16 {
+        self.port
+    }
+
+    pub fn read_with<H: Hal>(&self, hal: &mut H) -> T {
+        T::read_from(hal, self.port)
+    }
+
+    pub fn write_with<H: Hal>(&self, hal: &mut H, value: T) {
+        value.write_to(hal, self.port)
+    }
+}
+
+impl<T: PortValue> ReadOnlyPort<T> {
+    pub const fn new(port: u16) -> ReadOnlyPort<T> {
+        ReadOnlyPort { port, value: PhantomData }
+    }
+
+    pub const fn number(&self) -> u16 {
+        self.port
+    }
+
+    pub fn read_with<H: Hal>(&self, hal: &mut H) -> T {
+        T::read_from(hal, self.port)
+    }
+}
+
+impl<T: PortValue> WriteOnlyPort<T> {
+    pub const fn new(port: u16) -> WriteOnlyPort<T> {
+        WriteOnlyPort { port, value: PhantomData }
+    }
+
+    pub const fn number(&self) -> u16 {
+        self.port
+    }
+
+    pub fn write_with<H: Hal>(&self, hal: &mut H, value: T) {
+        value.write_to(hal, self.port)
+    }
+}
+
+// POST code port: unused after boot, so writing to it is a harmless ~1 µs delay
+const POST_PORT: WriteOnlyPort<u8> = WriteOnlyPort::new(0x80);
+
+// Give slow devices (the PIC on old hardware) time to settle between accesses
+pub fn io_wait_with<H: Hal>(hal: &mut H) {
+    POST_PORT.write_with(hal, 0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{Event, MockHal};
+
+    #[test]
+    fn widths_use_matching_instructions() {
+        let mut hal = MockHal::new();
+        Port::<u8>::new(0x60).write_with(&mut hal, 0xED);
+        Port::<u16>::new(0x1F0).write_with(&mut hal, 0xBEEF);
+        Port::<u32>::new(0xF4).write_with(&mut hal, 0x10);
+        assert_eq!(hal.events, [Event::Out8(0x60, 0xED), Event::Out16(0x1F0, 0xBEEF), Event::Out32(0xF4, 0x10)]);
+    }
+
+    #[test]
+    fn reads_return_device_values() {
+        let mut hal = MockHal::new();
+        hal.reads.extend([0x1C, 0xABCD, 0x12345678]);
+        assert_eq!(ReadOnlyPort::<u8>::new(0x60).read_with(&mut hal), 0x1C);
+        assert_eq!(Port::<u16>::new(0x1F0).read_with(&mut hal), 0xABCD);
+        assert_eq!(Port::<u32>::new(0xCFC).read_with(&mut hal), 0x12345678);
+        assert_eq!(hal.events, [Event::In8(0x60), Event::In16(0x1F0), Event::In32(0xCFC)]);
+    }
+
+    #[test]
+    fn io_wait_writes_the_post_port() {
+        let mut hal = MockHal::new();
+        io_wait_with(&mut hal);
+        assert_eq!(hal.events, [Event::Out8(0x80, 0)]);
+    }
+}
@@ -0,0 +1,157 @@
+// shell.rs - Command line parsing for NPS
+
+pub const MAX_ARGS: usize = 16;
+
+// Split a line into words. Supports 'single quotes' (taken literally),
+// "double quotes" (with \" and \\ escapes) and backslash escapes outside quotes.
+// Unescaped words are written to `scratch`; argv slices point into it.
+pub fn tokenize<'a>(line: &str, scratch: &'a mut [u8], argv: &mut [&'a str; MAX_ARGS]) -> Result<usize, &'static str> {
+    let mut bounds = [(0usize, 0usize); MAX_ARGS];
+    let mut argc = 0;
+    let mut len = 0;
+    let mut bytes = line.bytes();
+    let mut in_word = false;
+    let mut quote: Option<u8> = None;
+
+    while let Some(byte) = bytes.next() {
+        let literal = match (quote, byte) {
+            (Some(q), b) if b == q => {
+                quote = None;
+                None
+            }
+            (Some(b'"'), b'\\') => match bytes.next() {
+                Some(next @ (b'"' | b'\\')) => Some(next),
+                Some(next) => {
+                    // Unknown escape inside double quotes: keep the backslash
+                    if len >= scratch.len() {
+                        return Err("line too long");
+                    }
+                    scratch[len] = b'\\';
+                    len += 1;
+                    Some(next)
+                }
+                None => return Err("unterminated quote"),
+            },
+            (Some(_), b) => Some(b),
+            (None, b'\'' | b'"') => {
+                quote = Some(byte);
+                if !in_word {
+                    in_word = true;
+                    if argc == MAX_ARGS {
+                        return Err("too many arguments");
+                    }
+                    bounds[argc] = (len, len);
+                    argc += 1;
+                }
+                None
+            }
+            (None, b'\\') => match bytes.next() {
+                Some(next) => Some(next),
+                None => return Err("trailing backslash"),
+            },
+            (None, b' ' | b'\t') => {
+                in_word = false;
+                None
+            }
+            (None, b) => Some(b),
+        };
+
+        if let Some(b) = literal {
+            if !in_word {
+                in_word = true;
+                if argc == MAX_ARGS {
+                    return Err("too many arguments");
+                }
+                bounds[argc] = (len, len);
+                argc += 1;
+            }
+            if len >= scratch.len() {
+                return Err("line too long");
+            }
+            scratch[len] = b;
+            len += 1;
+            bounds[argc - 1].1 = len;
+        }
+    }
+
+    if quote.is_some() {
+        return Err("unterminated quote");
+    }
+
+    let scratch: &'a [u8] = scratch;
+    for (i, &(start, end)) in bounds[..argc].iter().enumerate() {
+        argv[i] = core::str::from_utf8(&scratch[start..end]).unwrap_or("");
+    }
+    Ok(argc)
+}
+
+// Parse a decimal or 0x-prefixed hexadecimal number
+pub fn parse_number(s: &str) -> Option<u32> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split<'a>(line: &str, scratch: &'a mut [u8; 128]) -> Result<std::vec::Vec<&'a str>, &'static str> {
+        let mut argv = [""; MAX_ARGS];
+        let argc = tokenize(line, scratch, &mut argv)?;
+        Ok(argv[..argc].to_vec())
+    }
+
+    #[test]
+    fn splits_on_whitespace() {
+        let mut scratch = [0; 128];
+        assert_eq!(split("  gdt \t 1  ", &mut scratch), Ok(vec!["gdt", "1"]));
+    }
+
+    #[test]
+    fn empty_line_has_no_words() {
+        let mut scratch = [0; 128];
+        assert_eq!(split("   ", &mut scratch), Ok(vec![]));
+    }
+
+    #[test]
+    fn quotes_group_words() {
+        let mut scratch = [0; 128];
+        assert_eq!(split("echo 'a b' \"c d\"e", &mut scratch), Ok(vec!["echo", "a b", "c de"]));
+    }
+
+    #[test]
+    fn empty_quotes_are_a_word() {
+        let mut scratch = [0; 128];
+        assert_eq!(split("x '' y", &mut scratch), Ok(vec!["x", "", "y"]));
+    }
+
+    #[test]
+    fn escapes() {
+        let mut scratch = [0; 128];
+        assert_eq!(split(r#"a\ b "q\"x\\" "\n" 'no\esc'"#, &mut scratch), Ok(vec!["a b", "q\"x\\", "\\n", "no\\esc"]));
+    }
+
+    #[test]
+    fn errors() {
+        let mut scratch = [0; 128];
+        assert_eq!(split("say 'oops", &mut scratch), Err("unterminated quote"));
+        assert_eq!(split("trailing\\", &mut scratch), Err("trailing backslash"));
+        let many = "a ".repeat(MAX_ARGS + 1);
+        assert_eq!(split(&many, &mut scratch), Err("too many arguments"));
+        let mut tiny = [0; 4];
+        let mut argv = [""; MAX_ARGS];
+        assert_eq!(tokenize("hello", &mut tiny, &mut argv), Err("line too long"));
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(parse_number("42"), Some(42));
+        assert_eq!(parse_number("0x2a"), Some(42));
+        assert_eq!(parse_number("0XFF"), Some(255));
+        assert_eq!(parse_number("0x"), None);
+        assert_eq!(parse_number("-1"), None);
+        assert_eq!(parse_number("4294967296"), None);
+    }
+}
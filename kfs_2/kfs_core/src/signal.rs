@@ -0,0 +1,192 @@
+// signal.rs - Signal numbers, default actions and wait() status words
+//
+// Numbers and the status encoding follow i386 Linux, so user code can use
+// the usual constants and WIFEXITED()/WTERMSIG() arithmetic.
+
+use core::fmt;
+
+pub const SIGHUP: u32 = 1;
+pub const SIGINT: u32 = 2;
+pub const SIGQUIT: u32 = 3;
+pub const SIGILL: u32 = 4;
+pub const SIGTRAP: u32 = 5;
+pub const SIGABRT: u32 = 6;
+pub const SIGBUS: u32 = 7;
+pub const SIGFPE: u32 = 8;
+pub const SIGKILL: u32 = 9;
+pub const SIGUSR1: u32 = 10;
+pub const SIGSEGV: u32 = 11;
+pub const SIGUSR2: u32 = 12;
+pub const SIGPIPE: u32 = 13;
+pub const SIGALRM: u32 = 14;
+pub const SIGTERM: u32 = 15;
+pub const SIGCHLD: u32 = 17;
+pub const SIGCONT: u32 = 18;
+pub const SIGSTOP: u32 = 19;
+pub const SIGTSTP: u32 = 20;
+
+pub const NSIG: u32 = 32;
+
+// Handler values with a special meaning
+pub const SIG_DFL: u32 = 0;
+pub const SIG_IGN: u32 = 1;
+
+const NAMES: [&str; 21] = [
+    "", "SIGHUP", "SIGINT", "SIGQUIT", "SIGILL", "SIGTRAP", "SIGABRT", "SIGBUS", "SIGFPE",
+    "SIGKILL", "SIGUSR1", "SIGSEGV", "SIGUSR2", "SIGPIPE", "SIGALRM", "SIGTERM", "SIGSTKFLT",
+    "SIGCHLD", "SIGCONT", "SIGSTOP", "SIGTSTP",
+];
+
+pub fn name(signal: u32) -> Option<&'static str> {
+    NAMES.get(signal as usize).copied().filter(|name| !name.is_empty())
+}
+
+// "SIGTERM" or "TERM"
+pub fn number(name: &str) -> Option<u32> {
+    let name = name.strip_prefix("SIG").unwrap_or(name);
+    NAMES.iter().skip(1).position(|n| n[3..] == *name).map(|i| i as u32 + 1)
+}
+
+pub fn valid(signal: u32) -> bool {
+    signal > 0 && signal < NSIG
+}
+
+// SIGKILL and SIGSTOP can be neither caught nor ignored
+pub fn catchable(signal: u32) -> bool {
+    valid(signal) && signal != SIGKILL && signal != SIGSTOP
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Action {
+    Terminate,
+    CoreDump,       // Terminate and report a core dump in the status
+    Ignore,
+    Stop,
+}
+
+pub fn default_action(signal: u32) -> Action {
+    match signal {
+        SIGQUIT | SIGILL | SIGTRAP | SIGABRT | SIGBUS | SIGFPE | SIGSEGV => Action::CoreDump,
+        SIGCHLD | SIGCONT | 23 | 28 => Action::Ignore,   // SIGURG, SIGWINCH
+        SIGSTOP | SIGTSTP | 21 | 22 => Action::Stop,     // SIGTTIN, SIGTTOU
+        _ => Action::Terminate,
+    }
+}
+
+// Set of signals as a bit mask (bit n for signal n)
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub struct SigSet(pub u32);
+
+impl SigSet {
+    pub const fn empty() -> SigSet {
+        SigSet(0)
+    }
+
+    pub fn add(&mut self, signal: u32) {
+        self.0 |= 1 << signal;
+    }
+
+    pub fn remove(&mut self, signal: u32) {
+        self.0 &= !(1 << signal);
+    }
+
+    pub fn contains(&self, signal: u32) -> bool {
+        self.0 & (1 << signal) != 0
+    }
+
+    // Lowest signal in the set that `blocked` lets through
+    pub fn first_unblocked(&self, blocked: SigSet) -> Option<u32> {
+        let ready = self.0 & !blocked.0;
+        (ready != 0).then(|| ready.trailing_zeros())
+    }
+}
+
+// Status word returned by wait()/waitpid()
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum WaitStatus {
+    Exited(u8),
+    Signaled { signal: u32, core: bool },
+}
+
+impl WaitStatus {
+    pub fn encode(&self) -> u32 {
+        match *self {
+            WaitStatus::Exited(code) => (code as u32) << 8,
+            WaitStatus::Signaled { signal, core } => signal | if core { 0x80 } else { 0 },
+        }
+    }
+
+    pub fn decode(status: u32) -> WaitStatus {
+        match status & 0x7F {
+            0 => WaitStatus::Exited((status >> 8) as u8),
+            signal => WaitStatus::Signaled { signal, core: status & 0x80 != 0 },
+        }
+    }
+
+    // What a process dies with when `signal` takes its default action
+    pub fn killed_by(signal: u32) -> WaitStatus {
+        WaitStatus::Signaled { signal, core: default_action(signal) == Action::CoreDump }
+    }
+}
+
+impl fmt::Display for WaitStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            WaitStatus::Exited(code) => write!(f, "exited with {}", code),
+            WaitStatus::Signaled { signal, core } => {
+                match name(signal) {
+                    Some(name) => write!(f, "killed by {}", name)?,
+                    None => write!(f, "killed by signal {}", signal)?,
+                }
+                if core {
+                    write!(f, " (core dumped)")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_and_actions() {
+        assert_eq!(name(SIGSEGV), Some("SIGSEGV"));
+        assert_eq!(name(16), Some("SIGSTKFLT"));
+        assert_eq!(name(0), None);
+        assert_eq!(name(31), None);
+        assert_eq!(number("SIGKILL"), Some(SIGKILL));
+        assert_eq!(number("TERM"), Some(SIGTERM));
+        assert_eq!(number("SIG"), None);
+        assert_eq!(default_action(SIGSEGV), Action::CoreDump);
+        assert_eq!(default_action(SIGCHLD), Action::Ignore);
+        assert_eq!(default_action(SIGTERM), Action::Terminate);
+        assert!(!catchable(SIGKILL) && catchable(SIGUSR1) && !catchable(NSIG));
+    }
+
+    #[test]
+    fn pending_sets() {
+        let mut pending = SigSet::empty();
+        pending.add(SIGTERM);
+        pending.add(SIGINT);
+        assert_eq!(pending.first_unblocked(SigSet::empty()), Some(SIGINT));
+        let mut blocked = SigSet::empty();
+        blocked.add(SIGINT);
+        assert_eq!(pending.first_unblocked(blocked), Some(SIGTERM));
+        pending.remove(SIGTERM);
+        assert_eq!(pending.first_unblocked(blocked), None);
+    }
+
+    #[test]
+    fn wait_status_round_trip() {
+        // As in Linux: exit(3) is 0x300, a segfault with core dump 0x8b
+        assert_eq!(WaitStatus::Exited(3).encode(), 0x300);
+        assert_eq!(WaitStatus::killed_by(SIGSEGV).encode(), 0x8B);
+        assert_eq!(WaitStatus::decode(0x300), WaitStatus::Exited(3));
+        assert_eq!(WaitStatus::decode(0x0F), WaitStatus::Signaled { signal: SIGTERM, core: false });
+        assert_eq!(WaitStatus::decode(0x8B).to_string(), "killed by SIGSEGV (core dumped)");
+        assert_eq!(WaitStatus::Exited(0).to_string(), "exited with 0");
+    }
+}
@@ -0,0 +1,103 @@
+// tss.rs - 32-bit Task State Segment layout
+//
+// Selector-sized fields are stored as u32 with the reserved upper half left
+// at zero, which keeps every field at its architectural offset.
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct Tss {
+    pub link: u32,          // Previous task, written by the CPU on nested task switches
+    pub esp0: u32,          // Stack loaded on a ring 3 -> ring 0 transition
+    pub ss0: u32,
+    pub esp1: u32,
+    pub ss1: u32,
+    pub esp2: u32,
+    pub ss2: u32,
+    pub cr3: u32,
+    pub eip: u32,
+    pub eflags: u32,
+    pub eax: u32,
+    pub ecx: u32,
+    pub edx: u32,
+    pub ebx: u32,
+    pub esp: u32,
+    pub ebp: u32,
+    pub esi: u32,
+    pub edi: u32,
+    pub es: u32,
+    pub cs: u32,
+    pub ss: u32,
+    pub ds: u32,
+    pub fs: u32,
+    pub gs: u32,
+    pub ldt: u32,
+    pub trap: u16,
+    pub iomap_base: u16,
+}
+
+pub const TSS_SIZE: usize = core::mem::size_of::<Tss>();
+
+impl Tss {
+    // An I/O map base past the limit means "no I/O bitmap": ring 3 gets no ports
+    pub const fn new() -> Tss {
+        Tss {
+            link: 0,
+            esp0: 0,
+            ss0: 0,
+            esp1: 0,
+            ss1: 0,
+            esp2: 0,
+            ss2: 0,
+            cr3: 0,
+            eip: 0,
+            eflags: 0,
+            eax: 0,
+            ecx: 0,
+            edx: 0,
+            ebx: 0,
+            esp: 0,
+            ebp: 0,
+            esi: 0,
+            edi: 0,
+            es: 0,
+            cs: 0,
+            ss: 0,
+            ds: 0,
+            fs: 0,
+            gs: 0,
+            ldt: 0,
+            trap: 0,
+            iomap_base: TSS_SIZE as u16,
+        }
+    }
+}
+
+impl Default for Tss {
+    fn default() -> Tss {
+        Tss::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::mem::offset_of;
+
+    #[test]
+    fn layout_matches_the_architecture() {
+        assert_eq!(TSS_SIZE, 104);
+        assert_eq!(offset_of!(Tss, esp0), 4);
+        assert_eq!(offset_of!(Tss, ss0), 8);
+        assert_eq!(offset_of!(Tss, cr3), 28);
+        assert_eq!(offset_of!(Tss, eip), 32);
+        assert_eq!(offset_of!(Tss, esp), 56);
+        assert_eq!(offset_of!(Tss, cs), 76);
+        assert_eq!(offset_of!(Tss, ldt), 96);
+        assert_eq!(offset_of!(Tss, iomap_base), 102);
+    }
+
+    #[test]
+    fn no_io_bitmap_by_default() {
+        assert_eq!(Tss::new().iomap_base as usize, TSS_SIZE);
+    }
+}
@@ -0,0 +1,315 @@
+// vga.rs - VGA text mode: colors and the scrolling text writer
+
+use crate::hal::Hal;
+use crate::port::Port;
+
+pub const VGA_WIDTH: usize = 80;
+pub const VGA_HEIGHT: usize = 25;
+// 80x50 with an 8x8 font - the most rows the text buffer is ever asked to hold
+pub const VGA_MAX_HEIGHT: usize = 50;
+
+const CRTC_INDEX: Port<u8> = Port::new(0x3D4);
+const CRTC_DATA: Port<u8> = Port::new(0x3D5);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Color {
+    Black = 0,
+    Blue = 1,
+    Green = 2,
+    Cyan = 3,
+    Red = 4,
+    Magenta = 5,
+    Brown = 6,
+    LightGray = 7,
+    DarkGray = 8,
+    LightBlue = 9,
+    LightGreen = 10,
+    LightCyan = 11,
+    LightRed = 12,
+    Pink = 13,
+    Yellow = 14,
+    White = 15,
+}
+
+pub const fn color_byte(fg: Color, bg: Color) -> u8 {
+    (bg as u8) << 4 | (fg as u8)
+}
+
+pub struct Writer<H: Hal> {
+    hal: H,
+    column: usize,
+    row: usize,
+    color: u8,
+    rows: usize,        // Visible text rows (25 with 8x16 glyphs, 50 with 8x8)
+    cell_height: u8,    // Scanlines per character cell
+    top: usize,         // First row of the scroll region (rows above it are never touched)
+}
+
+impl<H: Hal> Writer<H> {
+    pub const fn new(hal: H) -> Writer<H> {
+        Writer {
+            hal,
+            column: 0,
+            row: 0,
+            color: color_byte(Color::White, Color::Black),
+            rows: VGA_HEIGHT,
+            cell_height: 16,
+            top: 0,
+        }
+    }
+
+    fn put(&mut self, row: usize, col: usize, byte: u8, color: u8) {
+        self.hal.write_cell(row * VGA_WIDTH + col, byte as u16 | (color as u16) << 8);
+    }
+
+    // Restrict scrolling and clearing to rows top..rows (e.g. to keep a status bar on row 0)
+    pub fn set_scroll_top(&mut self, top: usize) {
+        self.top = top.min(self.rows - 1);
+        if self.row < self.top {
+            self.row = self.top;
+            self.update_cursor();
+        }
+    }
+
+    // Called by the font loader after the character cell height changed
+    pub fn set_geometry(&mut self, rows: usize, cell_height: u8) {
+        self.rows = rows.clamp(1, VGA_MAX_HEIGHT);
+        self.cell_height = cell_height;
+        self.top = self.top.min(self.rows - 1);
+        if self.row >= self.rows {
+            self.row = self.rows - 1;
+        }
+        self.set_cursor_visible(true);
+        self.clear();
+    }
+
+    fn new_line(&mut self) {
+        self.column = 0;
+
+        if self.row < self.rows - 1 {
+            self.row += 1;
+        } else {
+            self.scroll();
+        }
+    }
+
+    fn scroll(&mut self) {
+        for row in self.top + 1..self.rows {
+            for col in 0..VGA_WIDTH {
+                let cell = self.hal.read_cell(row * VGA_WIDTH + col);
+                self.hal.write_cell((row - 1) * VGA_WIDTH + col, cell);
+            }
+        }
+
+        for col in 0..VGA_WIDTH {
+            self.put(self.rows - 1, col, b' ', self.color);
+        }
+
+        self.row = self.rows - 1;
+        self.column = 0;
+    }
+
+    // Draw a character without moving the cursor (status bar, full-screen UIs)
+    pub fn put_char_at(&mut self, row: usize, col: usize, byte: u8, color: u8) {
+        if row < self.rows && col < VGA_WIDTH {
+            self.put(row, col, byte, color);
+        }
+    }
+
+    fn write_crtc(&mut self, index: u8, value: u8) {
+        CRTC_INDEX.write_with(&mut self.hal, index);
+        CRTC_DATA.write_with(&mut self.hal, value);
+    }
+
+    fn update_cursor(&mut self) {
+        let pos = self.row * VGA_WIDTH + self.column;
+        self.write_crtc(0x0E, (pos >> 8) as u8);
+        self.write_crtc(0x0F, pos as u8);
+    }
+
+    pub fn set_color(&mut self, fg: Color, bg: Color) {
+        self.color = color_byte(fg, bg);
+    }
+
+    pub fn write_byte(&mut self, byte: u8) {
+        match byte {
+            b'\n' => self.new_line(),
+            byte => {
+                if self.column >= VGA_WIDTH {
+                    self.new_line();
+                }
+                self.put(self.row, self.column, byte, self.color);
+                self.column += 1;
+            }
+        }
+        self.update_cursor();
+    }
+
+    // Bytes outside printable ASCII show as a block
+    pub fn write_string(&mut self, s: &str) {
+        for byte in s.bytes() {
+            match byte {
+                0x20..=0x7e | b'\n' => self.write_byte(byte),
+                _ => self.write_byte(0xfe),
+            }
+        }
+    }
+
+    pub fn backspace(&mut self) {
+        if self.column == 0 {
+            return;
+        }
+
+        self.column -= 1;
+        self.put(self.row, self.column, b' ', self.color);
+        self.update_cursor();
+    }
+
+    pub fn clear(&mut self) {
+        for row in self.top..VGA_MAX_HEIGHT {
+            for col in 0..VGA_WIDTH {
+                self.put(row, col, b' ', self.color);
+            }
+        }
+        self.column = 0;
+        self.row = self.top;
+        self.update_cursor();
+    }
+
+    pub fn set_cursor_visible(&mut self, visible: bool) {
+        // Underline cursor on the last two scanlines of the cell
+        let cursor_start = if visible { self.cell_height - 2 } else { 0x20 };
+        self.write_crtc(0x0A, cursor_start);
+        self.write_crtc(0x0B, self.cell_height - 1);
+    }
+
+    pub fn set_cursor_position(&mut self, row: usize, col: usize) {
+        if row >= self.top && row < self.rows && col < VGA_WIDTH {
+            self.row = row;
+            self.column = col;
+            self.update_cursor();
+        }
+    }
+
+    pub fn cursor_position(&self) -> (usize, usize) {
+        (self.row, self.column)
+    }
+
+    pub fn size(&self) -> (usize, usize) {
+        (self.rows, VGA_WIDTH)
+    }
+
+    #[cfg(test)]
+    pub fn hal(&mut self) -> &mut H {
+        &mut self.hal
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{Event, MockHal};
+
+    fn writer() -> Writer<MockHal> {
+        let mut w = Writer::new(MockHal::new());
+        w.clear();
+        w.hal().events.clear();
+        w
+    }
+
+    #[test]
+    fn color_byte_packs_background_high() {
+        assert_eq!(color_byte(Color::White, Color::Black), 0x0F);
+        assert_eq!(color_byte(Color::Yellow, Color::Blue), 0x1E);
+        assert_eq!(color_byte(Color::Black, Color::White), 0xF0);
+    }
+
+    #[test]
+    fn write_byte_stores_char_and_color() {
+        let mut w = writer();
+        w.set_color(Color::LightGreen, Color::Red);
+        w.write_byte(b'A');
+        assert_eq!(w.hal().cells[0], 0x4A41);
+    }
+
+    #[test]
+    fn cursor_follows_output() {
+        let mut w = writer();
+        w.write_string("ab");
+        // Position 2 = high byte 0, low byte 2 through the CRTC index/data pair
+        let ports = w.hal().port_events();
+        assert_eq!(&ports[ports.len() - 4..], &[
+            Event::Out8(0x3D4, 0x0E), Event::Out8(0x3D5, 0),
+            Event::Out8(0x3D4, 0x0F), Event::Out8(0x3D5, 2),
+        ]);
+    }
+
+    #[test]
+    fn scrolls_when_the_last_row_is_full() {
+        let mut w = writer();
+        for i in 0..VGA_HEIGHT + 2 {
+            w.write_byte(b'a' + i as u8);
+            w.write_byte(b'\n');
+        }
+        // Three newlines hit the bottom row, so 'a'-'c' scrolled away
+        assert_eq!(w.hal().row_text(0), "d");
+        assert_eq!(w.hal().row_text(VGA_HEIGHT - 2), "{");
+        assert_eq!(w.hal().row_text(VGA_HEIGHT - 1), "");
+        assert_eq!(w.cursor_position(), (VGA_HEIGHT - 1, 0));
+    }
+
+    #[test]
+    fn long_lines_wrap() {
+        let mut w = writer();
+        for _ in 0..VGA_WIDTH + 1 {
+            w.write_byte(b'x');
+        }
+        assert_eq!(w.hal().row_text(1), "x");
+        assert_eq!(w.cursor_position(), (1, 1));
+    }
+
+    #[test]
+    fn scroll_region_keeps_rows_above_top() {
+        let mut w = writer();
+        w.put_char_at(0, 0, b'#', color_byte(Color::White, Color::Black));
+        w.set_scroll_top(1);
+        w.hal().events.clear();
+        for _ in 0..VGA_HEIGHT * 2 {
+            w.write_string("y\n");
+        }
+        assert_eq!(w.hal().row_text(0), "#");
+        assert!(!w.hal().events.iter().any(|e| matches!(e, Event::Cell(i, _) if *i < VGA_WIDTH)));
+    }
+
+    #[test]
+    fn non_ascii_becomes_a_block() {
+        let mut w = writer();
+        w.write_string("ü");
+        assert_eq!(w.hal().cells[0] as u8, 0xfe);
+        assert_eq!(w.hal().cells[1] as u8, 0xfe);
+    }
+
+    #[test]
+    fn backspace_stops_at_column_zero() {
+        let mut w = writer();
+        w.write_string("ab");
+        w.backspace();
+        w.backspace();
+        w.backspace();
+        assert_eq!(w.cursor_position(), (0, 0));
+        assert_eq!(w.hal().row_text(0), "");
+    }
+
+    #[test]
+    fn cursor_shape_follows_cell_height() {
+        let mut w = writer();
+        w.set_geometry(50, 8);
+        let ports = w.hal().port_events();
+        assert_eq!(&ports[..4], &[
+            Event::Out8(0x3D4, 0x0A), Event::Out8(0x3D5, 6),
+            Event::Out8(0x3D4, 0x0B), Event::Out8(0x3D5, 7),
+        ]);
+        assert_eq!(w.size(), (50, VGA_WIDTH));
+    }
+}
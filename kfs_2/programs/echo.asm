@@ -0,0 +1,47 @@
+; echo.asm - Example user program for `run echo [args...]`
+; Prints its arguments on one line with the write system call and exits with
+; the argument count, using the stack the ELF loader builds (see elf.rs).
+
+section .text
+
+SYS_EXIT  equ 1
+SYS_WRITE equ 4
+
+global _start
+_start:
+    mov esi, [esp]              ; argc
+    lea edi, [esp+8]            ; argv[1]
+.next:
+    mov ecx, [edi]
+    test ecx, ecx
+    jz .done
+    xor edx, edx
+.length:
+    cmp byte [ecx+edx], 0
+    je .print
+    inc edx
+    jmp .length
+.print:
+    mov eax, SYS_WRITE
+    mov ebx, 1
+    int 0x80
+    add edi, 4
+    mov ecx, space
+    cmp dword [edi], 0
+    jne .separator
+    mov ecx, newline
+.separator:
+    mov eax, SYS_WRITE
+    mov ebx, 1
+    mov edx, 1
+    int 0x80
+    jmp .next
+.done:
+    mov eax, SYS_EXIT
+    mov ebx, esi
+    dec ebx
+    int 0x80
+
+section .rodata
+space:   db ' '
+newline: db 10
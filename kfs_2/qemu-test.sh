@@ -0,0 +1,24 @@
+#!/bin/sh
+# qemu-test.sh - Boot a test kernel headless and turn its isa-debug-exit code
+# into an exit status. Used as the cargo runner (kfs/.cargo/config.toml).
+#
+# The kernel writes 0x10 (pass) or 0x11 (fail) to port 0xf4, and QEMU exits
+# with (code << 1) | 1. Anything else means the kernel crashed or hung.
+
+QEMU=${QEMU:-qemu-system-i386}
+TIMEOUT=${TIMEOUT:-120}
+
+timeout "$TIMEOUT" "$QEMU" \
+    -kernel "$1" \
+    -append "test=${KFS_TEST:-all}" \
+    -serial stdio \
+    -display none \
+    -device isa-debug-exit,iobase=0xf4,iosize=0x04 \
+    -no-reboot
+
+case $? in
+    33) exit 0 ;;
+    35) echo "qemu-test: tests failed" >&2; exit 1 ;;
+    124) echo "qemu-test: timed out after ${TIMEOUT}s" >&2; exit 1 ;;
+    *) echo "qemu-test: kernel did not report a result" >&2; exit 1 ;;
+esac